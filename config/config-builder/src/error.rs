@@ -13,6 +13,8 @@ pub enum Error {
     MissingConfigs { found: usize },
     #[error("Missing full node network")]
     MissingFullNodeNetwork,
+    #[error("Seed peer address {0} does not contain a noise public key")]
+    MissingSeedPeerKey(String),
     #[error("Network config is missing network keypairs")]
     MissingNetworkKeyPairs,
     #[error("Specified a service-oriented safety rules but did not provide a host")]
@@ -4,14 +4,14 @@
 use crate::{BuildSwarm, Error, ValidatorConfig};
 use anyhow::{ensure, Result};
 use libra_config::{
-    config::{DiscoveryMethod, NodeConfig, RoleType, SeedPublicKeys},
+    config::{DiscoveryMethod, Identity, NodeConfig, RoleType, SeedPublicKeys},
     generator,
     network_id::NetworkId,
     utils,
 };
 use libra_crypto::ed25519::Ed25519PrivateKey;
 use libra_network_address::NetworkAddress;
-use libra_types::{chain_id::ChainId, transaction::Transaction};
+use libra_types::{chain_id::ChainId, transaction::Transaction, PeerId};
 use rand::{rngs::StdRng, SeedableRng};
 use std::{collections::HashSet, str::FromStr};
 
@@ -24,6 +24,10 @@ pub struct FullNodeConfig {
     pub genesis: Option<Transaction>,
     pub listen_address: NetworkAddress,
     pub mutual_authentication: bool,
+    /// Additional seed peers, their peer ids are derived from the noise key in their address
+    pub seed_peers: Vec<NetworkAddress>,
+    /// Network identity of the node, a random one is generated when unset
+    pub network_identity: Option<Identity>,
     template: NodeConfig,
     validator_config: ValidatorConfig,
 }
@@ -46,6 +50,8 @@ impl Default for FullNodeConfig {
             genesis: None,
             listen_address: NetworkAddress::from_str(DEFAULT_LISTEN_ADDRESS).unwrap(),
             mutual_authentication: true,
+            seed_peers: Vec::new(),
+            network_identity: None,
             template,
             validator_config: ValidatorConfig::new(),
         }
@@ -86,7 +92,16 @@ impl FullNodeConfig {
             .full_node_networks
             .last()
             .ok_or(Error::MissingFullNodeNetwork)?;
-        let seed_addrs = generator::build_seed_addrs(&seed_config, self.bootstrap.clone());
+        let mut seed_addrs = generator::build_seed_addrs(&seed_config, self.bootstrap.clone());
+        for seed_peer in &self.seed_peers {
+            let public_key = seed_peer
+                .find_noise_proto()
+                .ok_or_else(|| Error::MissingSeedPeerKey(seed_peer.to_string()))?;
+            seed_addrs
+                .entry(PeerId::from_identity_public_key(public_key))
+                .or_insert_with(Vec::new)
+                .push(seed_peer.clone());
+        }
 
         let mut config = configs.swap_remove(self.full_node_index);
         let network = &mut config
@@ -96,6 +111,9 @@ impl FullNodeConfig {
         network.discovery_method = DiscoveryMethod::gossip(self.advertised_address.clone());
         network.listen_address = self.listen_address.clone();
         network.seed_addrs = seed_addrs;
+        if let Some(identity) = &self.network_identity {
+            network.identity = identity.clone();
+        }
 
         Ok(config)
     }
@@ -223,6 +241,8 @@ impl BuildSwarm for FullNodeConfig {
 #[cfg(test)]
 mod test {
     use super::*;
+    use libra_config::config::{SecureBackend, Token, VaultConfig, HANDSHAKE_VERSION};
+    use libra_crypto::{x25519, Uniform};
 
     #[test]
     fn verify_correctness() {
@@ -252,6 +272,50 @@ mod test {
         assert!(config.execution.genesis.is_some());
     }
 
+    #[test]
+    fn verify_seed_peers() {
+        let seed_key = x25519::PrivateKey::generate(&mut StdRng::from_seed([7u8; 32]));
+        let seed_peer = NetworkAddress::from_str("/ip4/10.0.0.2/tcp/6182")
+            .unwrap()
+            .append_prod_protos(seed_key.public_key(), HANDSHAKE_VERSION);
+
+        let mut fnc = FullNodeConfig::new();
+        fnc.seed_peers = vec![seed_peer.clone()];
+        let config = fnc.build().unwrap();
+        let network = &config.full_node_networks[0];
+
+        network.verify_seed_addrs().unwrap();
+        assert_eq!(network.seed_addrs.len(), 2);
+        assert_eq!(
+            network.seed_addrs[&PeerId::from_identity_public_key(seed_key.public_key())],
+            vec![seed_peer]
+        );
+
+        let mut fnc = FullNodeConfig::new();
+        fnc.seed_peers = vec![NetworkAddress::from_str("/ip4/10.0.0.2/tcp/6182").unwrap()];
+        assert!(fnc.build().is_err());
+    }
+
+    #[test]
+    fn verify_network_identity() {
+        let backend = SecureBackend::Vault(VaultConfig {
+            ca_certificate: None,
+            namespace: None,
+            server: "http://10.0.0.3:8200".to_string(),
+            token: Token::FromConfig("s.token".to_string()),
+        });
+        let identity = Identity::from_storage(
+            "fn-0-0__fullnode_network".to_string(),
+            "fn-0-0__fullnode_network_peer_id".to_string(),
+            backend,
+        );
+
+        let mut fnc = FullNodeConfig::new();
+        fnc.network_identity = Some(identity.clone());
+        let config = fnc.build().unwrap();
+        assert_eq!(config.full_node_networks[0].identity, identity);
+    }
+
     #[test]
     fn verify_upstream_config() {
         let mut validator_config = ValidatorConfig::new().build().unwrap();
@@ -4,7 +4,10 @@
 #![forbid(unsafe_code)]
 
 use config_builder::{FullNodeConfig, KeyManagerConfig, ValidatorConfig};
-use libra_config::config::{KeyManagerConfig as KMConfig, NodeConfig, PersistableConfig};
+use libra_config::config::{
+    Identity, KeyManagerConfig as KMConfig, NodeConfig, PersistableConfig, SecureBackend, Token,
+    VaultConfig,
+};
 use libra_network_address::NetworkAddress;
 use libra_types::chain_id::ChainId;
 use std::{convert::TryInto, fs, fs::File, io::Write, net::SocketAddr, path::PathBuf};
@@ -73,9 +76,25 @@ struct FullNodeArgs {
     #[structopt(short = "b", long, parse(from_str = parse_addr))]
     /// Advertised address for the first node in this FullNode network.
     bootstrap: NetworkAddress,
+    #[structopt(long, parse(from_str = parse_addr), number_of_values = 1)]
+    /// Additional seed peer for this node, must include the peer's noise public key. Can be
+    /// repeated.
+    seed_peer: Vec<NetworkAddress>,
     #[structopt(short = "d", long, parse(from_os_str))]
     /// The data directory for the configs (e.g. /opt/libra/data).
     data_dir: PathBuf,
+    #[structopt(long, requires_all = &["peer-id-name", "vault-host", "vault-token"])]
+    /// Name of the key in vault to use as network identity instead of a generated one.
+    network_key_name: Option<String>,
+    #[structopt(long)]
+    /// Name of the peer id of the network key in vault.
+    peer_id_name: Option<String>,
+    #[structopt(long)]
+    /// Specifies the vault host URL holding the network key.
+    vault_host: Option<String>,
+    #[structopt(long)]
+    /// Specifies the token for the vault holding the network key.
+    vault_token: Option<String>,
     #[structopt(short = "c", long)]
     /// Use the provided seed for generating keys for each of the FullNodes.
     full_node_seed: Option<String>,
@@ -295,6 +314,7 @@ fn build_full_node_config_builder(args: &FullNodeArgs) -> FullNodeConfig {
     }
     config_builder.advertised_address = args.advertised.clone();
     config_builder.bootstrap = args.bootstrap.clone();
+    config_builder.seed_peers = args.seed_peer.clone();
     config_builder.full_node_index = args.full_node_index;
     config_builder.num_full_nodes = args.full_nodes;
     config_builder.listen_address = args.listen.clone();
@@ -310,6 +330,20 @@ fn build_full_node_config_builder(args: &FullNodeArgs) -> FullNodeConfig {
         config_builder.mutual_authentication = false;
     }
 
+    if let Some(network_key_name) = args.network_key_name.as_ref() {
+        let backend = SecureBackend::Vault(VaultConfig {
+            ca_certificate: None,
+            namespace: None,
+            server: args.vault_host.clone().expect("Missing vault host"),
+            token: Token::FromConfig(args.vault_token.clone().expect("Missing vault token")),
+        });
+        config_builder.network_identity = Some(Identity::from_storage(
+            network_key_name.clone(),
+            args.peer_id_name.clone().expect("Missing peer id name"),
+            backend,
+        ));
+    }
+
     if let Some(seed) = args.seed.as_ref() {
         config_builder.validator_seed(parse_seed(seed));
     }
@@ -10,12 +10,55 @@ use libra_management::{
 use libra_types::{
     account_address,
     chain_id::ChainId,
+    on_chain_config::VMPublishingOption,
     transaction::{Transaction, TransactionPayload},
 };
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{fmt, fs::File, io::Write, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 use vm_genesis::{OperatorAssignment, OperatorRegistration};
 
+/// Which scripts and modules can be published on the network, either anything (open) or only
+/// the allowlisted stdlib scripts (locked).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PublishingOption {
+    Open,
+    Locked,
+}
+
+impl PublishingOption {
+    fn vm_publishing_option(self) -> Option<VMPublishingOption> {
+        match self {
+            PublishingOption::Open => Some(VMPublishingOption::open()),
+            // Genesis defaults to the stdlib script allowlist
+            PublishingOption::Locked => None,
+        }
+    }
+}
+
+impl FromStr for PublishingOption {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(PublishingOption::Open),
+            "locked" => Ok(PublishingOption::Locked),
+            _ => Err(Error::CommandArgumentError(format!(
+                "Invalid publishing option {}, expected open or locked",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PublishingOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PublishingOption::Open => write!(f, "open"),
+            PublishingOption::Locked => write!(f, "locked"),
+        }
+    }
+}
+
 /// Note, it is implicitly expected that the storage supports
 /// a namespace but one has not been set.
 #[derive(Debug, StructOpt)]
@@ -28,6 +71,9 @@ pub struct Genesis {
     pub backend: SharedBackend,
     #[structopt(long)]
     pub path: Option<PathBuf>,
+    /// Publishing option set in genesis, open or locked
+    #[structopt(long, default_value = "open")]
+    pub publishing_option: PublishingOption,
 }
 
 impl Genesis {
@@ -49,7 +95,7 @@ impl Genesis {
             &operator_assignments,
             &operator_registrations,
             // TODO: swap back by 8/15
-            Some(libra_types::on_chain_config::VMPublishingOption::open()),
+            self.publishing_option.vm_publishing_option(),
             self.config()?.chain_id,
         );
 
@@ -7,16 +7,67 @@ use libra_management::{
 use libra_secure_storage::Value;
 use serde::{Deserialize, Serialize};
 use std::{
+    fmt,
     fs::File,
     io::Read,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 use structopt::StructOpt;
 
+/// Formats a layout file can be written in, files are read in the format matching their
+/// extension and default to toml
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayoutFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl LayoutFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => LayoutFormat::Yaml,
+            Some("json") => LayoutFormat::Json,
+            _ => LayoutFormat::Toml,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            LayoutFormat::Toml => "toml",
+            LayoutFormat::Yaml => "yaml",
+            LayoutFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for LayoutFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "toml" => Ok(LayoutFormat::Toml),
+            "yaml" => Ok(LayoutFormat::Yaml),
+            "json" => Ok(LayoutFormat::Json),
+            _ => Err(Error::UnexpectedError(format!(
+                "Invalid layout format {}, expected toml, yaml or json",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for LayoutFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
 /// Layout defines the set of roles to identities within genesis. In practice, these identities
 /// will map to distinct namespaces where the expected data should be stored in the deterministic
 /// location as defined within this tool.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Layout {
     pub operators: Vec<String>,
     pub owners: Vec<String>,
@@ -29,15 +80,33 @@ impl Layout {
         let mut contents = String::new();
         file.read_to_string(&mut contents)
             .map_err(|e| Error::UnexpectedError(e.to_string()))?;
-        Self::parse(&contents)
+        Self::parse_format(&contents, LayoutFormat::from_path(&path))
     }
 
     pub fn parse(contents: &str) -> Result<Self, Error> {
-        toml::from_str(&contents).map_err(|e| Error::UnexpectedError(e.to_string()))
+        Self::parse_format(contents, LayoutFormat::Toml)
+    }
+
+    pub fn parse_format(contents: &str, format: LayoutFormat) -> Result<Self, Error> {
+        match format {
+            LayoutFormat::Toml => toml::from_str(&contents).map_err(|e| e.to_string()),
+            LayoutFormat::Yaml => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+            LayoutFormat::Json => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+        }
+        .map_err(Error::UnexpectedError)
     }
 
     pub fn to_toml(&self) -> Result<String, Error> {
-        toml::to_string(&self).map_err(|e| Error::UnexpectedError(e.to_string()))
+        self.to_format(LayoutFormat::Toml)
+    }
+
+    pub fn to_format(&self, format: LayoutFormat) -> Result<String, Error> {
+        match format {
+            LayoutFormat::Toml => toml::to_string(&self).map_err(|e| e.to_string()),
+            LayoutFormat::Yaml => serde_yaml::to_string(&self).map_err(|e| e.to_string()),
+            LayoutFormat::Json => serde_json::to_string_pretty(&self).map_err(|e| e.to_string()),
+        }
+        .map_err(Error::UnexpectedError)
     }
 }
 
@@ -93,4 +162,25 @@ mod tests {
         assert_eq!(layout.owners, vec!["carol".to_string()]);
         assert_eq!(layout.libra_root, vec!["dave".to_string()]);
     }
+
+    #[test]
+    fn test_layout_formats() {
+        let layout = Layout::parse(
+            "operators = [\"alice\"]\nowners = [\"carol\"]\nlibra_root = [\"dave\"]\n",
+        )
+        .unwrap();
+        for format in &[LayoutFormat::Toml, LayoutFormat::Yaml, LayoutFormat::Json] {
+            let contents = layout.to_format(*format).unwrap();
+            let parsed = Layout::parse_format(&contents, *format).unwrap();
+            assert_eq!(parsed.operators, layout.operators);
+            assert_eq!(parsed.owners, layout.owners);
+            assert_eq!(parsed.libra_root, layout.libra_root);
+            assert_eq!(format.to_string().parse::<LayoutFormat>().unwrap(), *format);
+        }
+        assert!(Layout::parse_format(&layout.to_toml().unwrap(), LayoutFormat::Json).is_err());
+
+        assert_eq!(LayoutFormat::from_path("layout.yml"), LayoutFormat::Yaml);
+        assert_eq!(LayoutFormat::from_path("layout.json"), LayoutFormat::Json);
+        assert_eq!(LayoutFormat::from_path("layout"), LayoutFormat::Toml);
+    }
 }
@@ -11,6 +11,8 @@ mod validator_operator;
 mod verify;
 mod waypoint;
 
+pub use genesis::PublishingOption;
+
 #[cfg(any(test, feature = "testing"))]
 mod storage_helper;
 
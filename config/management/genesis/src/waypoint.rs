@@ -1,6 +1,7 @@
 // Copyright (c) The Libra Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::genesis::PublishingOption;
 use executor::db_bootstrapper;
 use libra_global_constants::WAYPOINT;
 use libra_management::{
@@ -26,6 +27,9 @@ pub struct CreateWaypoint {
     chain_id: Option<ChainId>,
     #[structopt(flatten)]
     shared_backend: SharedBackend,
+    /// Publishing option genesis was created with
+    #[structopt(long, default_value = "open")]
+    publishing_option: PublishingOption,
 }
 
 impl CreateWaypoint {
@@ -35,6 +39,7 @@ impl CreateWaypoint {
             chain_id: self.chain_id,
             backend: self.shared_backend,
             path: None,
+            publishing_option: self.publishing_option,
         };
 
         let genesis = genesis_helper.execute()?;
@@ -59,6 +64,9 @@ pub struct CreateAndInsertWaypoint {
     shared_backend: SharedBackend,
     #[structopt(flatten)]
     validator_backend: ValidatorBackend,
+    /// Publishing option genesis was created with
+    #[structopt(long, default_value = "open")]
+    publishing_option: PublishingOption,
 }
 
 impl CreateAndInsertWaypoint {
@@ -67,6 +75,7 @@ impl CreateAndInsertWaypoint {
             chain_id: self.chain_id,
             config: self.config.clone(),
             shared_backend: self.shared_backend,
+            publishing_option: self.publishing_option,
         }
         .execute()?;
 
@@ -61,13 +61,18 @@ pub fn validator_swarm_for_testing(nodes: usize) -> ValidatorSwarm {
 
 /// Convenience function that builds a `SeedAddresses` containing a single peer
 /// with a fully formatted `NetworkAddress` containing its network identity pubkey
-/// and handshake protocol version.
+/// and handshake protocol version. An address that already carries a noise pubkey is
+/// used as is.
 pub fn build_seed_addrs(
     seed_config: &NetworkConfig,
     seed_base_addr: NetworkAddress,
 ) -> SeedAddresses {
-    let seed_pubkey = libra_crypto::PrivateKey::public_key(&seed_config.identity_key());
-    let seed_addr = seed_base_addr.append_prod_protos(seed_pubkey, HANDSHAKE_VERSION);
+    let seed_addr = if seed_base_addr.find_noise_proto().is_some() {
+        seed_base_addr
+    } else {
+        let seed_pubkey = libra_crypto::PrivateKey::public_key(&seed_config.identity_key());
+        seed_base_addr.append_prod_protos(seed_pubkey, HANDSHAKE_VERSION)
+    };
 
     let mut seed_addrs = SeedAddresses::default();
     seed_addrs.insert(seed_config.peer_id(), vec![seed_addr]);
@@ -4,6 +4,7 @@
 set -ex
 
 declare -a params
+CFG_LISTEN_PORT="${CFG_LISTEN_PORT:-6180}" # Port the fullnode listens on
 if [ -n "${CFG_BASE_CONFIG}" ]; then # Path to base config
 	    echo "${CFG_BASE_CONFIG}" > /opt/libra/etc/base.yaml
 	    params+="-t /opt/libra/etc/base.yaml "
@@ -12,10 +13,10 @@ if [ -n "${CFG_CHAIN_ID}" ]; then
         params+="--chain-id ${CFG_CHAIN_ID} "
 fi
 if [ -n "${CFG_LISTEN_ADDR}" ]; then # Advertised listen address for network config
-	    params+="-a /ip4/${CFG_LISTEN_ADDR}/tcp/6180 "
+	    params+="-a /ip4/${CFG_LISTEN_ADDR}/tcp/${CFG_LISTEN_PORT} "
 fi
 if [ -n "${CFG_LISTEN_ADDR}" ]; then # Listen address for node
-	    params+="-l /ip4/0.0.0.0/tcp/6180 "
+	    params+="-l /ip4/0.0.0.0/tcp/${CFG_LISTEN_PORT} "
 fi
 if [ -n "${CFG_FULLNODE_INDEX}" ]; then
 	    params+="-i ${CFG_FULLNODE_INDEX} "
@@ -29,9 +30,23 @@ fi
 if [ -n "${CFG_FULLNODE_SEED}" ]; then # Random seed to use for fullnode network
 	    params+="-c ${CFG_FULLNODE_SEED} "
 fi
-if [ -n "${CFG_SEED_PEER_IP}" ]; then # Seed peer ip for discovery
+if [ -n "${CFG_SEED_PEER_ADDR}" ]; then # Seed peer address for discovery
+	    params+="--bootstrap ${CFG_SEED_PEER_ADDR} "
+elif [ -n "${CFG_SEED_PEER_IP}" ]; then # Seed peer ip for discovery
 	    params+="--bootstrap /ip4/${CFG_SEED_PEER_IP}/tcp/6181 "
 fi
+if [ -n "${CFG_SEED_PEER_ADDRS}" ]; then # Comma separated seed peer addresses, the first one is the bootstrap
+	    IFS=',' read -ra SEED_PEER_ADDRS <<< "${CFG_SEED_PEER_ADDRS}"
+	    for SEED_PEER_ADDR in "${SEED_PEER_ADDRS[@]:1}"; do
+	        params+="--seed-peer ${SEED_PEER_ADDR} "
+	    done
+fi
+if [ -n "${CFG_FULLNODE_NETWORK_KEY}" ]; then # Vault key holding the network identity of this fullnode
+	    params+="--network-key-name ${CFG_FULLNODE_NETWORK_KEY} "
+	    params+="--peer-id-name ${CFG_FULLNODE_PEER_ID} "
+	    params+="--vault-host http://${CFG_VAULT_ADDR}:8200 "
+	    params+="--vault-token ${CFG_VAULT_TOKEN} "
+fi
 if [ -n "${CFG_NUM_FULLNODES}" ]; then # Random seed to use for fullnode network
 	    params+="-f ${CFG_NUM_FULLNODES} "
 fi
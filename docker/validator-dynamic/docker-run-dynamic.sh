@@ -32,6 +32,15 @@ fi
 if [ -n "${CFG_SEED_PEER_IP}" ]; then # Seed peer ip for discovery
 	    params+="--bootstrap /ip4/${CFG_SEED_PEER_IP}/tcp/6180 "
 fi
+if [ -n "${CFG_SAFETY_RULES_REPLICA_ADDRS}" ]; then # Comma separated ip:port of the safety rules replicas
+    IFS=',' read -ra SAFETY_RULES_REPLICA_ADDRS <<< "${CFG_SAFETY_RULES_REPLICA_ADDRS}"
+    for SAFETY_RULES_REPLICA_ADDR in "${SAFETY_RULES_REPLICA_ADDRS[@]}"; do # Use the first reachable replica
+        if timeout 5 bash -c "< /dev/tcp/${SAFETY_RULES_REPLICA_ADDR%:*}/${SAFETY_RULES_REPLICA_ADDR##*:}"; then
+            CFG_SAFETY_RULES_ADDR="${SAFETY_RULES_REPLICA_ADDR}"
+            break
+        fi
+    done
+fi
 if [ -n "${CFG_SAFETY_RULES_ADDR}" ]; then
     params+="--safety-rules-addr ${CFG_SAFETY_RULES_ADDR} "
 fi
@@ -59,6 +68,8 @@ if [ -n "${CFG_FULLNODE_SEED}" ]; then # We have a full node seed, add fullnode
 
 fi
 
+bash /fetch-genesis.sh
+
 # Set CFG_OVERRIDES to any values that you want to override in the config
 # Example: CFG_OVERRIDES='grpc_max_receive_len=45,genesis_file_location="genesis2.blob",max_block_size=250'
 # Note: Double quotes are required for string parameters and should be
@@ -0,0 +1,11 @@
+#!/bin/bash
+# Copyright (c) The Libra Core Contributors
+# SPDX-License-Identifier: Apache-2.0
+# Downloads the genesis blob at CFG_GENESIS_URL to CFG_GENESIS_PATH, for validators that are not
+# given the blob on their node, e.g. when cluster-test runs with --waypoint-only.
+set -ex
+
+if [ -n "${CFG_GENESIS_URL}" ]; then
+	curl --fail --silent --show-error --retry 5 --create-dirs \
+	    -o "${CFG_GENESIS_PATH:-/opt/libra/etc/genesis.blob}" "${CFG_GENESIS_URL}"
+fi
@@ -4,12 +4,270 @@
 #![forbid(unsafe_code)]
 
 use anyhow::{bail, format_err, Result};
+use futures::Future;
 use libra_logger::{info, warn};
+use once_cell::sync::Lazy;
 use rusoto_autoscaling::{
-    AutoScalingGroupNamesType, Autoscaling, AutoscalingClient, SetDesiredCapacityType,
+    AutoScalingGroupNamesType, AutoScalingGroupsType, Autoscaling, AutoscalingClient,
+    CreateOrUpdateTagsType, SetDesiredCapacityType, Tag,
 };
-use rusoto_core::Region;
+use rusoto_core::{Region, RusotoError};
+use rusoto_ecr::{DescribeImagesRequest, Ecr, EcrClient, ImageIdentifier};
 use rusoto_sts::WebIdentityProvider;
+use std::{
+    error::Error,
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Circuit breaker shared by all AWS API calls of the process
+static CIRCUIT_BREAKER: Lazy<CircuitBreaker> =
+    Lazy::new(|| CircuitBreaker::new(CircuitBreakerConfig::default()));
+
+/// Size the autoscaling group is left at once a run is torn down: `zero`, `keep` (warm instances
+/// are reused by the next run) or an explicit number of instances
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TeardownAsgSize {
+    Zero,
+    Keep,
+    Size(u32),
+}
+
+impl TeardownAsgSize {
+    /// Desired capacity to set on teardown, None if the size is kept
+    pub fn desired_capacity(self) -> Option<i64> {
+        match self {
+            TeardownAsgSize::Zero => Some(0),
+            TeardownAsgSize::Keep => None,
+            TeardownAsgSize::Size(size) => Some(size as i64),
+        }
+    }
+}
+
+impl FromStr for TeardownAsgSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "zero" => Ok(TeardownAsgSize::Zero),
+            "keep" => Ok(TeardownAsgSize::Keep),
+            _ => s.parse().map(TeardownAsgSize::Size).map_err(|_| {
+                format_err!(
+                    "Invalid teardown asg size {}, expected zero, keep or a number",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// Thresholds of the circuit breaker guarding the AWS API calls
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed calls after which the circuit opens, AWS calls then fail right away
+    /// for `open_duration`
+    pub failure_threshold: u32,
+    /// Delay before the call following a throttled one, doubled for every consecutive throttle
+    pub throttle_backoff: Duration,
+    /// Upper bound of the throttle backoff
+    pub max_throttle_backoff: Duration,
+    /// Cool-down of the open circuit. Once it is over the circuit is half open: a single trial
+    /// call goes through, closing the circuit if it succeeds and opening it again otherwise
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 10,
+            throttle_backoff: Duration::from_secs(5),
+            max_throttle_backoff: Duration::from_secs(120),
+            open_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CircuitBreakerState {
+    consecutive_failures: u32,
+    consecutive_throttles: u32,
+    // When the circuit opened, or when the last trial call of the half open circuit started
+    opened_at: Option<Instant>,
+}
+
+/// Backs off while AWS throttles the calls and stops calling AWS once they keep failing, instead
+/// of retrying into the throttling. After a cool-down a trial call decides whether the circuit
+/// closes again.
+pub struct CircuitBreaker {
+    config: Mutex<CircuitBreakerConfig>,
+    state: Mutex<CircuitBreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config: Mutex::new(config),
+            state: Mutex::new(CircuitBreakerState::default()),
+        }
+    }
+
+    /// Replaces the thresholds and closes the circuit
+    pub fn configure(&self, config: CircuitBreakerConfig) {
+        *self.config.lock().expect("config lock poisoned") = config;
+        *self.state.lock().expect("state lock poisoned") = CircuitBreakerState::default();
+    }
+
+    /// Whether the circuit is open and still cooling down, calls fail right away until then
+    pub fn is_open(&self) -> bool {
+        let open_duration = self
+            .config
+            .lock()
+            .expect("config lock poisoned")
+            .open_duration;
+        self.state
+            .lock()
+            .expect("state lock poisoned")
+            .opened_at
+            .map_or(false, |opened_at| opened_at.elapsed() < open_duration)
+    }
+
+    /// Delay before the next call, None unless the previous calls were throttled
+    fn backoff(&self) -> Option<Duration> {
+        let config = *self.config.lock().expect("config lock poisoned");
+        let throttles = self
+            .state
+            .lock()
+            .expect("state lock poisoned")
+            .consecutive_throttles;
+        if throttles == 0 {
+            return None;
+        }
+        let backoff = config
+            .throttle_backoff
+            .checked_mul(1 << (throttles - 1).min(16))
+            .unwrap_or(config.max_throttle_backoff);
+        Some(backoff.min(config.max_throttle_backoff))
+    }
+
+    /// Runs the AWS call `name` unless the circuit is open, after the throttle backoff if any,
+    /// and records whether it failed
+    pub async fn call<T, F, Fut>(&self, name: &str, call: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let config = *self.config.lock().expect("config lock poisoned");
+        let trial = {
+            let mut state = self.state.lock().expect("state lock poisoned");
+            match state.opened_at {
+                Some(opened_at) if opened_at.elapsed() < config.open_duration => bail!(
+                    "AWS circuit breaker is open after {} consecutive failed calls, not calling {}",
+                    state.consecutive_failures,
+                    name
+                ),
+                // Calls stay blocked while the trial runs, and if it never completes
+                Some(_) => {
+                    state.opened_at = Some(Instant::now());
+                    true
+                }
+                None => false,
+            }
+        };
+        if trial {
+            info!("AWS circuit breaker is half open, trying {}", name);
+        }
+        if let Some(backoff) = self.backoff() {
+            warn!(
+                "AWS calls are throttled, waiting {:?} before {}",
+                backoff, name
+            );
+            tokio::time::delay_for(backoff).await;
+        }
+        let result = call().await;
+        let mut state = self.state.lock().expect("state lock poisoned");
+        match &result {
+            Ok(_) => *state = CircuitBreakerState::default(),
+            Err(e) => {
+                state.consecutive_failures += 1;
+                if is_throttling(e) {
+                    state.consecutive_throttles += 1;
+                } else {
+                    state.consecutive_throttles = 0;
+                }
+                if trial || state.consecutive_failures >= config.failure_threshold {
+                    state.opened_at = Some(Instant::now());
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Sets the thresholds of the circuit breaker shared by all AWS calls
+pub fn configure_circuit_breaker(config: CircuitBreakerConfig) {
+    CIRCUIT_BREAKER.configure(config);
+}
+
+/// Failed AWS call, with the error code AWS answered with if any
+#[derive(Debug)]
+struct AwsError {
+    call: String,
+    code: Option<String>,
+    message: String,
+}
+
+impl fmt::Display for AwsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {}", self.call, self.message)
+    }
+}
+
+impl Error for AwsError {}
+
+/// Wraps a failed rusoto call into an AwsError, keeping the error code is_throttling matches
+fn aws_error<E: Error + 'static>(call: &str, error: RusotoError<E>) -> anyhow::Error {
+    let code = match &error {
+        RusotoError::Unknown(response) => error_code(&response.body),
+        _ => None,
+    };
+    AwsError {
+        call: call.to_string(),
+        code,
+        message: error.to_string(),
+    }
+    .into()
+}
+
+/// Error code of the body of a failed AWS call: the Code element of query protocol services
+/// (e.g. autoscaling) or the __type field of JSON protocol services (e.g. ECR)
+fn error_code(body: &[u8]) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(body) {
+        // The type may be prefixed with the namespace of the service
+        let error_type = json["__type"].as_str()?;
+        return error_type.rsplit('#').next().map(str::to_string);
+    }
+    let start = body.find("<Code>")? + "<Code>".len();
+    let end = start + body[start..].find("</Code>")?;
+    Some(body[start..end].trim().to_string())
+}
+
+/// Throttled calls fail with a Throttling (or ThrottlingException) error code
+fn is_throttling(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<AwsError>() {
+        Some(AwsError {
+            code: Some(code), ..
+        }) => code == "Throttling" || code == "ThrottlingException",
+        _ => false,
+    }
+}
+
+/// fixed_retry_strategy that stops retrying once the circuit breaker is open
+fn retry_strategy(delay_ms: u64, tries: usize) -> impl Iterator<Item = Duration> {
+    libra_retrier::fixed_retry_strategy(delay_ms, tries).take_while(|_| !CIRCUIT_BREAKER.is_open())
+}
 
 /// set_asg_size sets the size of the given autoscaling group
 #[allow(clippy::collapsible_if)]
@@ -34,27 +292,28 @@ pub async fn set_asg_size(
         desired_capacity: desired_capacity + buffer,
         honor_cooldown: Some(false),
     };
-    let credentials_provider = WebIdentityProvider::from_k8s_env();
-
-    let dispatcher = rusoto_core::HttpClient::new().expect("failed to create request dispatcher");
-    let asc = AutoscalingClient::new_with(dispatcher, credentials_provider, Region::UsWest2);
-    libra_retrier::retry_async(libra_retrier::fixed_retry_strategy(10_000, 60), || {
+    let asc = autoscaling_client();
+    libra_retrier::retry_async(retry_strategy(10_000, 60), || {
         let asc = asc.clone();
         let set_desired_capacity_type = set_desired_capacity_type.clone();
         Box::pin(async move {
-            asc.set_desired_capacity(set_desired_capacity_type)
-                .await
-                .map_err(|e| {
-                    warn!("set_desired_capacity failed: {}, retrying", e);
-                    format_err!("set_desired_capacity failed: {}", e)
+            CIRCUIT_BREAKER
+                .call("set_desired_capacity", || async {
+                    asc.set_desired_capacity(set_desired_capacity_type)
+                        .await
+                        .map_err(|e| {
+                            warn!("set_desired_capacity failed: {}, retrying", e);
+                            aws_error("set_desired_capacity", e)
+                        })
                 })
+                .await
         })
     })
     .await?;
     if !wait_for_completion {
         return Ok(());
     }
-    libra_retrier::retry_async(libra_retrier::fixed_retry_strategy(10_000, 60), || {
+    libra_retrier::retry_async(retry_strategy(10_000, 60), || {
         let asc_clone = asc.clone();
         Box::pin(async move {
             let mut total = 0;
@@ -68,8 +327,10 @@ pub async fn set_asg_size(
                     max_records: Some(100),
                     next_token: current_token_clone,
                 };
-                let asgs = asc_clone
-                    .describe_auto_scaling_groups(auto_scaling_group_names_type)
+                let asgs = CIRCUIT_BREAKER
+                    .call("describe_auto_scaling_groups", || {
+                        describe_auto_scaling_groups(&asc_clone, auto_scaling_group_names_type)
+                    })
                     .await?;
                 if asgs.auto_scaling_groups.is_empty() {
                     bail!("asgs.auto_scaling_groups.is_empty()");
@@ -126,3 +387,262 @@ pub async fn set_asg_size(
     })
     .await
 }
+
+/// get_asg_running_count returns the number of InService instances of the given autoscaling group
+pub async fn get_asg_running_count(asg_name: &str) -> Result<i64> {
+    let asc = autoscaling_client();
+    let mut total = 0;
+    let mut current_token = None;
+    loop {
+        let auto_scaling_group_names_type = AutoScalingGroupNamesType {
+            auto_scaling_group_names: Some(vec![asg_name.to_string()]),
+            max_records: Some(100),
+            next_token: current_token,
+        };
+        let asgs = CIRCUIT_BREAKER
+            .call("describe_auto_scaling_groups", || {
+                describe_auto_scaling_groups(&asc, auto_scaling_group_names_type)
+            })
+            .await?;
+        if asgs.auto_scaling_groups.is_empty() {
+            bail!("asgs.auto_scaling_groups.is_empty()");
+        }
+        total += asgs.auto_scaling_groups[0]
+            .instances
+            .as_ref()
+            .ok_or_else(|| format_err!("instances not found for auto_scaling_group"))?
+            .iter()
+            .filter(|instance| instance.lifecycle_state == "InService")
+            .count() as i64;
+        if asgs.next_token.is_none() {
+            break;
+        }
+        current_token = asgs.next_token;
+    }
+    Ok(total)
+}
+
+/// tag_asg_instances sets tags on the given autoscaling group, propagated to the instances it
+/// launches from then on
+pub async fn tag_asg_instances(asg_name: &str, tags: &[(String, String)]) -> Result<()> {
+    if tags.is_empty() {
+        return Ok(());
+    }
+    let create_or_update_tags_type = CreateOrUpdateTagsType {
+        tags: tags
+            .iter()
+            .map(|(key, value)| Tag {
+                key: key.clone(),
+                value: Some(value.clone()),
+                propagate_at_launch: Some(true),
+                resource_id: Some(asg_name.to_string()),
+                resource_type: Some("auto-scaling-group".to_string()),
+            })
+            .collect(),
+    };
+    let asc = autoscaling_client();
+    libra_retrier::retry_async(retry_strategy(10_000, 6), || {
+        let asc = asc.clone();
+        let create_or_update_tags_type = create_or_update_tags_type.clone();
+        Box::pin(async move {
+            CIRCUIT_BREAKER
+                .call("create_or_update_tags", || async {
+                    asc.create_or_update_tags(create_or_update_tags_type)
+                        .await
+                        .map_err(|e| {
+                            warn!("create_or_update_tags failed: {}, retrying", e);
+                            aws_error("create_or_update_tags", e)
+                        })
+                })
+                .await
+        })
+    })
+    .await?;
+    info!("Tagged {} with {:?}", asg_name, tags);
+    Ok(())
+}
+
+/// image_digest returns the digest of the image `tag` currently points to in the ECR repository
+/// `repository`
+pub async fn image_digest(repository: &str, tag: &str) -> Result<String> {
+    let ecr = ecr_client();
+    let describe_images_request = DescribeImagesRequest {
+        repository_name: repository.to_string(),
+        image_ids: Some(vec![ImageIdentifier {
+            image_digest: None,
+            image_tag: Some(tag.to_string()),
+        }]),
+        ..Default::default()
+    };
+    let images = libra_retrier::retry_async(retry_strategy(5_000, 6), || {
+        let ecr = ecr.clone();
+        let describe_images_request = describe_images_request.clone();
+        Box::pin(async move {
+            CIRCUIT_BREAKER
+                .call("describe_images", || async {
+                    ecr.describe_images(describe_images_request)
+                        .await
+                        .map_err(|e| {
+                            warn!("describe_images failed: {}, retrying", e);
+                            aws_error("describe_images", e)
+                        })
+                })
+                .await
+        })
+    })
+    .await?;
+    images
+        .image_details
+        .into_iter()
+        .flatten()
+        .find_map(|details| details.image_digest)
+        .ok_or_else(|| format_err!("No image found for tag {} in {}", tag, repository))
+}
+
+async fn describe_auto_scaling_groups(
+    asc: &AutoscalingClient,
+    auto_scaling_group_names_type: AutoScalingGroupNamesType,
+) -> Result<AutoScalingGroupsType> {
+    asc.describe_auto_scaling_groups(auto_scaling_group_names_type)
+        .await
+        .map_err(|e| aws_error("describe_auto_scaling_groups", e))
+}
+
+fn autoscaling_client() -> AutoscalingClient {
+    let credentials_provider = WebIdentityProvider::from_k8s_env();
+    let dispatcher = rusoto_core::HttpClient::new().expect("failed to create request dispatcher");
+    AutoscalingClient::new_with(dispatcher, credentials_provider, Region::UsWest2)
+}
+
+fn ecr_client() -> EcrClient {
+    let credentials_provider = WebIdentityProvider::from_k8s_env();
+    let dispatcher = rusoto_core::HttpClient::new().expect("failed to create request dispatcher");
+    EcrClient::new_with(dispatcher, credentials_provider, Region::UsWest2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn throttled() -> anyhow::Error {
+        AwsError {
+            call: "test".to_string(),
+            code: Some("Throttling".to_string()),
+            message: "Rate exceeded".to_string(),
+        }
+        .into()
+    }
+
+    #[test]
+    pub fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 3,
+            throttle_backoff: Duration::from_millis(1),
+            max_throttle_backoff: Duration::from_millis(2),
+            open_duration: Duration::from_secs(60),
+        });
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut call =
+            |result: Result<u32>| rt.block_on(breaker.call("test", || async move { result }));
+
+        call(Err(throttled())).unwrap_err();
+        assert_eq!(breaker.backoff(), Some(Duration::from_millis(1)));
+        call(Err(throttled())).unwrap_err();
+        assert_eq!(breaker.backoff(), Some(Duration::from_millis(2)));
+        // Successful calls close the circuit and reset the backoff
+        assert_eq!(call(Ok(1)).unwrap(), 1);
+        assert_eq!(breaker.backoff(), None);
+        // Only the error code counts, not the message
+        call(Err(format_err!("Throttling"))).unwrap_err();
+        assert_eq!(breaker.backoff(), None);
+        assert_eq!(call(Ok(2)).unwrap(), 2);
+
+        for _ in 0..3 {
+            call(Err(format_err!("ValidationError"))).unwrap_err();
+        }
+        assert!(breaker.is_open());
+        assert_eq!(breaker.backoff(), None);
+        let error = call(Ok(3)).unwrap_err().to_string();
+        assert!(error.contains("circuit breaker is open after 3 consecutive failed calls"));
+
+        breaker.configure(CircuitBreakerConfig::default());
+        assert_eq!(call(Ok(4)).unwrap(), 4);
+    }
+
+    #[test]
+    pub fn test_circuit_breaker_half_open() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            throttle_backoff: Duration::from_millis(1),
+            max_throttle_backoff: Duration::from_millis(1),
+            open_duration: Duration::from_millis(50),
+        });
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut call =
+            |result: Result<u32>| rt.block_on(breaker.call("test", || async move { result }));
+
+        for _ in 0..2 {
+            call(Err(format_err!("ValidationError"))).unwrap_err();
+        }
+        assert!(breaker.is_open());
+        assert!(call(Ok(1)).unwrap_err().to_string().contains("is open"));
+
+        // After the cool-down a failed trial call opens the circuit again
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!breaker.is_open());
+        let error = call(Err(format_err!("ValidationError"))).unwrap_err();
+        assert_eq!(error.to_string(), "ValidationError");
+        assert!(breaker.is_open());
+        assert!(call(Ok(2)).is_err());
+
+        // and a successful one closes it
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(call(Ok(3)).unwrap(), 3);
+        assert!(!breaker.is_open());
+        call(Err(format_err!("ValidationError"))).unwrap_err();
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    pub fn test_error_code() {
+        let query_error =
+            br#"<ErrorResponse xmlns="http://autoscaling.amazonaws.com/doc/2011-01-01/">
+  <Error>
+    <Type>Sender</Type>
+    <Code>Throttling</Code>
+    <Message>Rate exceeded</Message>
+  </Error>
+</ErrorResponse>"#;
+        assert_eq!(error_code(query_error).as_deref(), Some("Throttling"));
+        let json_error =
+            br#"{"__type":"com.amazonaws.ecr#ThrottlingException","message":"Rate exceeded"}"#;
+        assert_eq!(
+            error_code(json_error).as_deref(),
+            Some("ThrottlingException")
+        );
+        assert_eq!(error_code(br#"{"message":"Rate exceeded"}"#), None);
+        assert_eq!(error_code(b"Throttling"), None);
+    }
+
+    #[test]
+    pub fn test_teardown_asg_size() {
+        assert_eq!(
+            TeardownAsgSize::from_str("zero").unwrap(),
+            TeardownAsgSize::Zero
+        );
+        assert_eq!(
+            TeardownAsgSize::from_str("keep").unwrap(),
+            TeardownAsgSize::Keep
+        );
+        assert_eq!(
+            TeardownAsgSize::from_str("12").unwrap(),
+            TeardownAsgSize::Size(12)
+        );
+        assert!(TeardownAsgSize::from_str("-1").is_err());
+        assert!(TeardownAsgSize::from_str("all").is_err());
+
+        assert_eq!(TeardownAsgSize::Zero.desired_capacity(), Some(0));
+        assert_eq!(TeardownAsgSize::Keep.desired_capacity(), None);
+        assert_eq!(TeardownAsgSize::Size(12).desired_capacity(), Some(12));
+    }
+}
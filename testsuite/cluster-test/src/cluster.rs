@@ -3,15 +3,39 @@
 
 #![forbid(unsafe_code)]
 
-use crate::instance::{Instance, ValidatorGroup};
+use crate::{
+    cluster_builder::{VAULT_PORT, VAULT_TOKEN},
+    instance::{
+        validator_pod_name, vault_index, ApplicationConfig::Fullnode, ConsensusStatus, Instance,
+        ValidatorGroup,
+    },
+};
+use anyhow::{format_err, Result};
 use config_builder::ValidatorConfig;
+use futures::future::try_join_all;
 use libra_crypto::{
     ed25519::{Ed25519PrivateKey, Ed25519PublicKey},
     test_utils::KeyPair,
+    x25519, ValidCryptoMaterial,
+};
+use libra_genesis_tool::layout::Layout;
+use libra_global_constants::{FULLNODE_NETWORK_KEY, OWNER_KEY};
+use libra_secure_storage::{CryptoStorage, Storage, VaultStorage};
+use libra_swarm::swarm::LibraSwarm;
+use libra_types::{
+    account_address::{self, AccountAddress},
+    chain_id::ChainId,
+    waypoint::Waypoint,
 };
 use rand::prelude::*;
 use reqwest::Client;
-use std::convert::TryInto;
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fmt::{self, Display},
+    sync::{Arc, Mutex},
+};
+use tokio::task::spawn_blocking;
 
 #[derive(Clone)]
 pub struct Cluster {
@@ -21,6 +45,47 @@ pub struct Cluster {
     lsr_instances: Vec<Instance>,
     vault_instances: Vec<Instance>,
     mint_key_pair: KeyPair<Ed25519PrivateKey, Ed25519PublicKey>,
+    info: ClusterInfo,
+    // Processes of a cluster launched on this machine, killed once the last clone is dropped
+    _local_swarm: Option<Arc<Mutex<LibraSwarm>>>,
+}
+
+/// Deployment details of a cluster, fields are unset when they are not known (e.g. for clusters
+/// built from a list of peers)
+#[derive(Clone, Debug, Default)]
+pub struct ClusterInfo {
+    pub chain_id: Option<ChainId>,
+    pub lsr_backend: Option<String>,
+    pub image_tag: Option<String>,
+    pub image_digest: Option<String>,
+    pub waypoint: Option<Waypoint>,
+    pub run_id: Option<String>,
+    /// Number of validators sharing each vault
+    pub validators_per_vault: Option<u32>,
+    /// Owners, operators and libra root of the genesis
+    pub layout: Option<Layout>,
+    /// Token of the vaults, resolved from --vault-token, --vault-token-file or LIBRA_VAULT_TOKEN
+    pub vault_token: Option<VaultToken>,
+}
+
+/// Vault token that is left out of Debug output, so it doesn't end up in logs
+#[derive(Clone, PartialEq)]
+pub struct VaultToken(String);
+
+impl VaultToken {
+    pub fn new(token: String) -> Self {
+        Self(token)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for VaultToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VaultToken(<redacted>)")
+    }
 }
 
 impl Cluster {
@@ -46,6 +111,8 @@ impl Cluster {
             lsr_instances: vec![],
             vault_instances: vec![],
             mint_key_pair,
+            info: ClusterInfo::default(),
+            _local_swarm: None,
         }
     }
 
@@ -71,6 +138,8 @@ impl Cluster {
             lsr_instances,
             vault_instances,
             mint_key_pair: Self::get_mint_key_pair(),
+            info: ClusterInfo::default(),
+            _local_swarm: None,
         }
     }
 
@@ -106,6 +175,26 @@ impl Cluster {
         &self.vault_instances
     }
 
+    pub fn validator_count(&self) -> usize {
+        self.validator_instances.len()
+    }
+
+    pub fn fullnode_count(&self) -> usize {
+        self.fullnode_instances.len()
+    }
+
+    pub fn lsr_count(&self) -> usize {
+        self.lsr_instances.len()
+    }
+
+    pub fn vault_count(&self) -> usize {
+        self.vault_instances.len()
+    }
+
+    pub fn total_instance_count(&self) -> usize {
+        self.validator_count() + self.fullnode_count() + self.lsr_count() + self.vault_count()
+    }
+
     pub fn all_instances(&self) -> impl Iterator<Item = &Instance> {
         self.validator_instances
             .iter()
@@ -120,6 +209,17 @@ impl Cluster {
             .chain(self.fullnode_instances.iter())
     }
 
+    /// Consensus status of every validator, queried concurrently. A validator whose current
+    /// round doesn't move between two calls is stalled
+    pub async fn consensus_status(&self) -> Result<Vec<ConsensusStatus>> {
+        try_join_all(
+            self.validator_instances
+                .iter()
+                .map(|instance| instance.consensus_status()),
+        )
+        .await
+    }
+
     pub fn into_validator_instances(self) -> Vec<Instance> {
         self.validator_instances
     }
@@ -136,6 +236,98 @@ impl Cluster {
         self.vault_instances
     }
 
+    /// Consumes the cluster into its validators, fullnodes, LSRs and vaults, in the order of
+    /// all_instances
+    pub fn into_all_instances(self) -> Vec<Instance> {
+        let mut instances = self.validator_instances;
+        instances.extend(self.fullnode_instances);
+        instances.extend(self.lsr_instances);
+        instances.extend(self.vault_instances);
+        instances
+    }
+
+    pub fn with_info(mut self, info: ClusterInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Makes the cluster own the local `swarm` its instances run in
+    pub(crate) fn with_local_swarm(mut self, swarm: LibraSwarm) -> Self {
+        self._local_swarm = Some(Arc::new(Mutex::new(swarm)));
+        self
+    }
+
+    pub fn info(&self) -> &ClusterInfo {
+        &self.info
+    }
+
+    /// Token the vault clients of the cluster use, the dev mode token of the vault template if
+    /// it was not resolved
+    pub fn vault_token(&self) -> &str {
+        self.info
+            .vault_token
+            .as_ref()
+            .map_or(VAULT_TOKEN, VaultToken::as_str)
+    }
+
+    /// Layout the genesis of the cluster was generated with, if it was generated by cluster-test
+    pub fn layout(&self) -> Option<&Layout> {
+        self.info.layout.as_ref()
+    }
+
+    /// Multi-line human-readable description of the cluster followed by a table of its
+    /// instances, meant for logs
+    pub fn summary(&self) -> String {
+        fn or_unknown<T: Display>(value: &Option<T>) -> String {
+            value
+                .as_ref()
+                .map_or_else(|| "unknown".to_string(), ToString::to_string)
+        }
+        // Fullnodes are seeded with the first validator
+        let seed_peer = self
+            .validator_instances
+            .first()
+            .map(|instance| format!("{} ({})", instance.peer_name(), instance.ip()));
+        let mut summary = format!(
+            "Cluster summary:\n  \
+             run id:       {}\n  \
+             chain id:     {}\n  \
+             image tag:    {}\n  \
+             image digest: {}\n  \
+             validators:   {}\n  \
+             fullnodes:    {}\n  \
+             lsrs:         {}\n  \
+             vaults:       {}\n  \
+             lsr backend:  {}\n  \
+             waypoint:     {}\n  \
+             seed peer:    {}\n  \
+             {:<16} {:<16} {}",
+            or_unknown(&self.info.run_id),
+            or_unknown(&self.info.chain_id),
+            or_unknown(&self.info.image_tag),
+            or_unknown(&self.info.image_digest),
+            self.validator_instances.len(),
+            self.fullnode_instances.len(),
+            self.lsr_instances.len(),
+            self.vault_instances.len(),
+            or_unknown(&self.info.lsr_backend),
+            or_unknown(&self.info.waypoint),
+            or_unknown(&seed_peer),
+            "NAME",
+            "IP",
+            "NODE",
+        );
+        for instance in self.all_instances() {
+            summary.push_str(&format!(
+                "\n  {:<16} {:<16} {}",
+                instance.peer_name(),
+                instance.ip(),
+                instance.k8s_node().unwrap_or("-")
+            ));
+        }
+        summary
+    }
+
     pub fn mint_key_pair(&self) -> &KeyPair<Ed25519PrivateKey, Ed25519PublicKey> {
         &self.mint_key_pair
     }
@@ -190,6 +382,8 @@ impl Cluster {
             lsr_instances: vec![],
             vault_instances: vec![],
             mint_key_pair: self.mint_key_pair.clone(),
+            info: self.info.clone(),
+            _local_swarm: self._local_swarm.clone(),
         }
     }
 
@@ -200,6 +394,8 @@ impl Cluster {
             lsr_instances: vec![],
             vault_instances: vec![],
             mint_key_pair: self.mint_key_pair.clone(),
+            info: self.info.clone(),
+            _local_swarm: self._local_swarm.clone(),
         }
     }
 
@@ -229,18 +425,102 @@ impl Cluster {
             .filter(move |v| v.validator_group() == validator_group)
     }
 
+    /// Returns all LSR replicas backing the given validators
     pub fn lsr_instances_for_validators(&self, validators: &[Instance]) -> Vec<Instance> {
         validators
             .iter()
-            .filter_map(|l| {
+            .flat_map(|l| {
                 self.lsr_instances
                     .iter()
-                    .find(|x| l.validator_group() == x.validator_group())
+                    .filter(move |x| l.validator_group() == x.validator_group())
                     .cloned()
             })
             .collect()
     }
 
+    /// Network public keys of the fullnodes, read from the vault of their validator. Fullnodes
+    /// spawned with --fullnode-network-keys have their own key, the others share the validator's
+    pub async fn fullnode_network_keys(&self) -> Result<HashMap<String, x25519::PublicKey>> {
+        let validators_per_vault = self.info.validators_per_vault.unwrap_or(1);
+        let mut locations = vec![];
+        for fullnode in self.fullnode_instances.iter() {
+            let fullnode_config = match &fullnode.instance_config().application_config {
+                Fullnode(fullnode_config) => fullnode_config,
+                _ => continue,
+            };
+            let validator_index = fullnode.validator_group().index;
+            let key_name = fullnode_config.network_key.clone().unwrap_or_else(|| {
+                format!(
+                    "{}__{}",
+                    validator_pod_name(validator_index),
+                    FULLNODE_NETWORK_KEY
+                )
+            });
+            let vault_addr = match &fullnode_config.vault_addr {
+                Some(vault_addr) => vault_addr.clone(),
+                None => self
+                    .vault_instances
+                    .iter()
+                    .find(|v| {
+                        v.validator_group().index
+                            == vault_index(validator_index, validators_per_vault)
+                    })
+                    .map(|v| v.ip().clone())
+                    .ok_or_else(|| {
+                        format_err!("No vault found for fullnode {}", fullnode.peer_name())
+                    })?,
+            };
+            locations.push((fullnode.peer_name().clone(), vault_addr, key_name));
+        }
+        let vault_token = self.vault_token().to_string();
+        spawn_blocking(move || {
+            read_network_keys(locations, |vault_addr| {
+                Storage::VaultStorage(VaultStorage::new(
+                    format!("http://{}:{}", vault_addr, VAULT_PORT),
+                    vault_token.clone(),
+                    None,
+                    None,
+                ))
+            })
+        })
+        .await?
+    }
+
+    /// Owner accounts of the validators, derived from the owner keys in their vaults the same way
+    /// the genesis tool derives the accounts it puts in the validator set
+    pub fn validator_owner_accounts(&self) -> Result<Vec<AccountAddress>> {
+        let validators_per_vault = self.info.validators_per_vault.unwrap_or(1);
+        self.validator_instances
+            .iter()
+            .map(|validator| {
+                let validator_index = validator.validator_group().index;
+                let vault_index = vault_index(validator_index, validators_per_vault);
+                let vault = self
+                    .vault_instances
+                    .iter()
+                    .find(|v| v.validator_group().index == vault_index)
+                    .ok_or_else(|| {
+                        format_err!("No vault found for validator {}", validator.peer_name())
+                    })?;
+                let storage = VaultStorage::new(
+                    format!("http://{}:{}", vault.ip(), VAULT_PORT),
+                    self.vault_token().to_string(),
+                    None,
+                    None,
+                );
+                let key_name = format!("{}__{}", validator_pod_name(validator_index), OWNER_KEY);
+                let owner_key = storage.get_public_key(&key_name).map_err(|e| {
+                    format_err!(
+                        "Failed to read owner key of {} : {}",
+                        validator.peer_name(),
+                        e
+                    )
+                })?;
+                Ok(account_address::from_public_key(&owner_key.public_key))
+            })
+            .collect()
+    }
+
     pub fn vault_instances_for_validators(&self, validators: &[Instance]) -> Vec<Instance> {
         validators
             .iter()
@@ -253,3 +533,264 @@ impl Cluster {
             .collect()
     }
 }
+
+/// x25519 network public key for the ed25519 key `key_name`, derived the same way the genesis
+/// tool derives the keys it puts on chain
+pub(crate) fn network_public_key<S: CryptoStorage>(
+    storage: &S,
+    key_name: &str,
+) -> Result<x25519::PublicKey> {
+    let private_key = storage.export_private_key(key_name)?;
+    let private_key = x25519::PrivateKey::from_ed25519_private_bytes(&private_key.to_bytes())?;
+    Ok(private_key.public_key())
+}
+
+/// Network public keys of (peer name, storage address, key name) `locations`, by peer name, from
+/// the storages `open_storage` returns for their addresses. Blocks on the storages.
+fn read_network_keys<F>(
+    locations: Vec<(String, String, String)>,
+    open_storage: F,
+) -> Result<HashMap<String, x25519::PublicKey>>
+where
+    F: Fn(&str) -> Storage,
+{
+    let mut keys = HashMap::new();
+    for (peer_name, addr, key_name) in locations {
+        let storage = open_storage(&addr);
+        let key = network_public_key(&storage, &key_name)
+            .map_err(|e| format_err!("Failed to read network key of {} : {}", peer_name, e))?;
+        keys.insert(peer_name, key);
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libra_secure_storage::OnDiskStorage;
+    use std::str::FromStr;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Answers every request on a local port with `body` as JSON, returns the counters URL
+    async fn serve_counters(body: String) -> String {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/counters", addr)
+    }
+
+    #[test]
+    pub fn test_read_network_keys() {
+        let dir = libra_temppath::TempPath::new();
+        dir.create_as_dir().unwrap();
+        let open_storage = |addr: &str| Storage::from(OnDiskStorage::new(dir.path().join(addr)));
+        let shared_key = format!("val-0__{}", FULLNODE_NETWORK_KEY);
+        let own_key = format!("fn-1-0__{}", FULLNODE_NETWORK_KEY);
+        let mut expected = HashMap::new();
+        for (peer_name, addr, key_name) in &[
+            ("fn-0-0", "10.0.0.1", &shared_key),
+            ("fn-1-0", "10.0.0.2", &own_key),
+        ] {
+            let mut storage = open_storage(addr);
+            storage.create_key(key_name).unwrap();
+            let public_key = x25519::PrivateKey::from_ed25519_private_bytes(
+                &storage.export_private_key(key_name).unwrap().to_bytes(),
+            )
+            .unwrap()
+            .public_key();
+            expected.insert(peer_name.to_string(), public_key);
+        }
+        let location = |peer_name: &str, addr: &str, key_name: &str| {
+            (
+                peer_name.to_string(),
+                addr.to_string(),
+                key_name.to_string(),
+            )
+        };
+
+        let keys = read_network_keys(
+            vec![
+                location("fn-0-0", "10.0.0.1", &shared_key),
+                location("fn-1-0", "10.0.0.2", &own_key),
+            ],
+            open_storage,
+        )
+        .unwrap();
+        assert_eq!(keys, expected);
+        // The key is read from the storage of its fullnode only
+        let err = read_network_keys(vec![location("fn-1-0", "10.0.0.1", &own_key)], open_storage)
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Failed to read network key of fn-1-0"));
+    }
+
+    #[test]
+    pub fn test_consensus_status() {
+        let counters = |current_round: u64, last_committed_round: u64| {
+            serde_json::json!({
+                "libra_consensus_current_round": current_round,
+                "libra_consensus_last_committed_round": last_committed_round,
+                "libra_consensus_proposals_count": 3,
+            })
+            .to_string()
+        };
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let client = Client::new();
+            let val_0 = serve_counters(counters(12, 10)).await;
+            let val_1 = serve_counters(counters(7, 5)).await;
+            let statuses = try_join_all(vec![
+                ConsensusStatus::fetch(&client, "val-0", &val_0),
+                ConsensusStatus::fetch(&client, "val-1", &val_1),
+            ])
+            .await
+            .unwrap();
+            assert_eq!(
+                statuses,
+                vec![
+                    ConsensusStatus {
+                        peer_name: "val-0".to_string(),
+                        current_round: 12,
+                        last_committed_round: 10,
+                        proposals: 3,
+                    },
+                    ConsensusStatus {
+                        peer_name: "val-1".to_string(),
+                        current_round: 7,
+                        last_committed_round: 5,
+                        proposals: 3,
+                    },
+                ]
+            );
+
+            let no_consensus = serve_counters("{}".to_string()).await;
+            let error = ConsensusStatus::fetch(&client, "val-2", &no_consensus)
+                .await
+                .unwrap_err();
+            assert!(error.to_string().contains("libra_consensus_current_round"));
+        });
+    }
+
+    #[test]
+    pub fn test_layout() {
+        let cluster = Cluster::new(vec![], vec![], vec![], vec![]);
+        assert!(cluster.layout().is_none());
+        let cluster = cluster.with_info(ClusterInfo {
+            layout: Some(Layout {
+                operators: vec!["operator-0".to_string()],
+                owners: vec!["val-0".to_string()],
+                libra_root: vec!["libra_root".to_string()],
+            }),
+            ..Default::default()
+        });
+        let layout = cluster.layout().unwrap();
+        assert_eq!(layout.owners, vec!["val-0"]);
+        assert_ne!(layout.operators, layout.owners);
+    }
+
+    #[test]
+    pub fn test_vault_token() {
+        let cluster = Cluster::new(vec![], vec![], vec![], vec![]);
+        assert_eq!(cluster.vault_token(), VAULT_TOKEN);
+        let cluster = cluster.with_info(ClusterInfo {
+            vault_token: Some(VaultToken::new("s.token".to_string())),
+            ..Default::default()
+        });
+        assert_eq!(cluster.vault_token(), "s.token");
+        let info = format!("{:?}", cluster.info());
+        assert!(info.contains("VaultToken(<redacted>)"));
+        assert!(!info.contains("s.token"));
+    }
+
+    #[test]
+    pub fn test_summary() {
+        let instance = |pod_name: String, ip: &str| {
+            Instance::new(pod_name, ip.to_string(), 8080, None, Client::new())
+        };
+        let waypoint = Waypoint::from_str(
+            "0:683185844ef67e5c8eeaa158e635de2a4c574ce7bbb7f41f787d38db2d623ae2",
+        )
+        .unwrap();
+        let cluster = Cluster::new(
+            vec![
+                instance(validator_pod_name(0), "10.0.0.1"),
+                instance(validator_pod_name(1), "10.0.0.2"),
+            ],
+            vec![instance("fn-0-0".to_string(), "10.0.0.3")],
+            vec![],
+            vec![],
+        )
+        .with_info(ClusterInfo {
+            chain_id: Some(ChainId::test()),
+            waypoint: Some(waypoint),
+            ..Default::default()
+        });
+
+        let summary = cluster.summary();
+        assert!(summary.contains("validators:   2\n"), "{}", summary);
+        assert!(summary.contains("fullnodes:    1\n"), "{}", summary);
+        assert!(summary.contains("vaults:       0\n"), "{}", summary);
+        assert!(summary.contains(&format!("waypoint:     {}\n", waypoint)));
+        assert!(summary.contains("seed peer:    val-0 (10.0.0.1)\n"));
+        assert!(summary.ends_with("fn-0-0           10.0.0.3         -"));
+    }
+
+    #[test]
+    pub fn test_instance_counts() {
+        let instances = |prefix: &str, count: usize| -> Vec<Instance> {
+            (0..count)
+                .map(|i| {
+                    Instance::new(
+                        format!("{}-{}", prefix, i),
+                        "127.0.0.1".to_string(),
+                        8080,
+                        None,
+                        Client::new(),
+                    )
+                })
+                .collect()
+        };
+        let cluster = Cluster::new(
+            instances("val", 4),
+            instances("fn", 8),
+            instances("lsr", 4),
+            instances("vault", 4),
+        );
+        assert_eq!(cluster.validator_count(), 4);
+        assert_eq!(cluster.fullnode_count(), 8);
+        assert_eq!(cluster.lsr_count(), 4);
+        assert_eq!(cluster.vault_count(), 4);
+        assert_eq!(cluster.total_instance_count(), 20);
+        let names: Vec<_> = cluster
+            .all_instances()
+            .map(|instance| instance.peer_name().clone())
+            .collect();
+        assert_eq!(names.len(), 20);
+        assert_eq!(names[4], "fn-0");
+        assert_eq!(names[19], "vault-3");
+        let instances = cluster.into_all_instances();
+        assert_eq!(
+            instances
+                .iter()
+                .map(|instance| instance.peer_name().clone())
+                .collect::<Vec<_>>(),
+            names
+        );
+    }
+}
@@ -0,0 +1,1619 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generation of the genesis blob and waypoint, and their distribution to the validators
+
+use super::*;
+
+/// Outputs of genesis generation, the same artifacts are also written to their well known paths
+/// for the node images that read them from there
+pub struct GenesisArtifacts {
+    pub blob: Vec<u8>,
+    pub waypoint: Waypoint,
+    pub mint_key: Ed25519PrivateKey,
+    pub layout: Layout,
+}
+
+/// Genesis artifacts of the last setup_cluster, archived by archive_genesis
+#[derive(Clone, Debug)]
+pub(super) struct GenesisArchive {
+    pub(super) paths: BuildPaths,
+    pub(super) layout_format: LayoutFormat,
+    // Whether mint.key is archived along the public artifacts, see --include-secrets
+    pub(super) include_secrets: bool,
+}
+
+/// Storage the keys of a genesis owner live in, owners of the same genesis can use different
+/// sources, e.g. while migrating validators from on-disk keys to vault
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeySource {
+    /// Vault server at `addr`, accessed with the token stored in `token_path`
+    Vault { addr: String, token_path: PathBuf },
+    /// On-disk storage file
+    Disk { path: PathBuf },
+}
+
+impl KeySource {
+    /// Secure backend argument of the genesis and operational tools, without the namespace
+    pub fn backend(&self) -> String {
+        match self {
+            KeySource::Vault { addr, token_path } => format!(
+                "backend={};server=http://{}:{};token={}",
+                VAULT_BACKEND,
+                addr,
+                VAULT_PORT,
+                token_path.display()
+            ),
+            KeySource::Disk { path } => format!("backend={};path={}", DISK, path.display()),
+        }
+    }
+
+    /// Storage of `namespace` in the source
+    fn storage(&self, namespace: &str) -> Result<Storage> {
+        Ok(match self {
+            KeySource::Vault { addr, token_path } => {
+                let token = fs::read_to_string(token_path)
+                    .map_err(|e| format_err!("Failed to read {} : {}", token_path.display(), e))?;
+                Storage::VaultStorage(VaultStorage::new(
+                    format!("http://{}:{}", addr, VAULT_PORT),
+                    token.trim().to_string(),
+                    Some(namespace.to_string()),
+                    None,
+                ))
+            }
+            KeySource::Disk { path } => Storage::NamespacedStorage(NamespacedStorage::new(
+                Box::new(OnDiskStorage::new(path.clone())),
+                namespace.to_string(),
+            )),
+        })
+    }
+
+    /// Overwrites the waypoint in the storage of `namespace`, blocks
+    pub fn set_waypoint(&self, namespace: &str, waypoint: Waypoint) -> Result<()> {
+        self.storage(namespace)?
+            .set(WAYPOINT, Value::String(waypoint.to_string()))
+            .map_err(|e| format_err!("Failed to insert waypoint for {} : {}", namespace, e))
+    }
+
+    /// Waypoint stored in the storage of `namespace`, blocks
+    pub fn waypoint(&self, namespace: &str) -> Result<Waypoint> {
+        let waypoint = self
+            .storage(namespace)?
+            .get(WAYPOINT)
+            .and_then(|response| response.value.string())
+            .map_err(|e| format_err!("Failed to read waypoint of {} : {}", namespace, e))?;
+        Waypoint::from_str(&waypoint)
+            .map_err(|e| format_err!("Invalid waypoint of {} : {}", namespace, e))
+    }
+}
+
+fn write_layout(layout: &Layout, format: LayoutFormat, path: &Path) -> Result<()> {
+    let contents = layout
+        .to_format(format)
+        .map_err(|e| format_err!("Failed to serialize layout as {} : {}", format, e))?;
+    fs::write(path, contents).map_err(|e| format_err!("Failed to write {} : {}", path.display(), e))
+}
+
+/// Downloads a genesis.blob, retrying while the object storage is unreachable
+async fn fetch_genesis(url: &str) -> Result<Vec<u8>> {
+    let http_client = reqwest::Client::new();
+    libra_retrier::retry_async(libra_retrier::fixed_retry_strategy(5000, 6), || {
+        let request = http_client.get(url);
+        Box::pin(async move {
+            let genesis = request.send().await?.error_for_status()?.bytes().await?;
+            Ok::<_, anyhow::Error>(genesis.to_vec())
+        })
+    })
+    .await
+    .map_err(|e| format_err!("Failed to download genesis.blob from {}: {}", url, e))
+}
+
+/// Creates `genesis_accounts` in the genesis blob at `genesis_path`, then applies the genesis
+/// script `code` to it, if any. Rewrites the blob and returns its new waypoint.
+fn post_process_genesis(
+    genesis_path: &Path,
+    code: Option<&[u8]>,
+    genesis_accounts: &[GenesisAccount],
+) -> Result<Waypoint> {
+    let blob = fs::read(genesis_path)
+        .map_err(|e| format_err!("Failed to read {} : {}", genesis_path.display(), e))?;
+    let mut genesis: Transaction = lcs::from_bytes(&blob)
+        .map_err(|e| format_err!("Failed to decode the genesis blob : {}", e))?;
+    if !genesis_accounts.is_empty() {
+        genesis = apply_genesis_accounts(&genesis, genesis_accounts)?;
+    }
+    if let Some(code) = code {
+        genesis = apply_genesis_script(&genesis, code)?;
+    }
+    let db_path = libra_temppath::TempPath::new();
+    let db_rw = DbReaderWriter::new(LibraDB::open(db_path.path(), false, None)?);
+    let waypoint = db_bootstrapper::generate_waypoint::<LibraVM>(&db_rw, &genesis)
+        .map_err(|e| format_err!("Failed to execute the post-processed genesis : {}", e))?;
+    fs::write(genesis_path, lcs::to_bytes(&genesis)?)
+        .map_err(|e| format_err!("Failed to write {} : {}", genesis_path.display(), e))?;
+    Ok(waypoint)
+}
+
+/// Inserts the waypoint into the storage of the given validator in its vault, like the genesis
+/// tool does when it generates genesis
+async fn insert_waypoint(
+    validator_index: u32,
+    vault_node: &KubeNode,
+    waypoint: Waypoint,
+    vault_token: &str,
+) -> Result<()> {
+    let addr = vault_node.internal_ip.clone();
+    let vault_token = vault_token.to_string();
+    tokio::task::spawn_blocking(move || {
+        let pod_name = validator_pod_name(validator_index);
+        let mut vault_storage = VaultStorage::new(
+            format!("http://{}:{}", addr, VAULT_PORT),
+            vault_token,
+            Some(pod_name.clone()),
+            None,
+        );
+        vault_storage
+            .set(WAYPOINT, Value::String(waypoint.to_string()))
+            .map_err(|e| format_err!("Failed to insert waypoint for {} : {}", pod_name, e))
+    })
+    .await??;
+    Ok(())
+}
+
+fn validator_set(blob: &AccountStateBlob) -> Result<ValidatorSet> {
+    AccountState::try_from(blob)?
+        .get_validator_set()?
+        .ok_or_else(|| format_err!("No validator set in the config account state"))
+}
+
+pub(super) fn validator_set_accounts(blob: &AccountStateBlob) -> Result<Vec<AccountAddress>> {
+    Ok(validator_set(blob)?
+        .payload()
+        .iter()
+        .map(|info| *info.account_address())
+        .collect())
+}
+
+/// What verify_genesis found in a genesis blob
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerificationReport {
+    pub waypoint: Waypoint,
+    /// Accounts of the validator set genesis starts with
+    pub validators: Vec<AccountAddress>,
+    /// Everything that does not match the layout or is inconsistent, empty for a valid blob
+    pub problems: Vec<String>,
+}
+
+impl VerificationReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "waypoint:   {}", self.waypoint)?;
+        writeln!(f, "validators: {}", self.validators.len())?;
+        if self.is_valid() {
+            write!(f, "genesis is valid")
+        } else {
+            write!(f, "problems:\n  {}", self.problems.join("\n  "))
+        }
+    }
+}
+
+/// Checks that `blob` sets up exactly as many validators as `layout` has owners, each with a usable
+/// config, and that its waypoint is derived consistently: executing genesis again gives the same
+/// waypoint, which matches the committed ledger info and its validator set. Only fails when the
+/// blob can't be decoded or executed, the problems found are listed in the report.
+pub async fn verify_genesis(blob: &[u8], layout: &Layout) -> Result<VerificationReport> {
+    let genesis: Transaction = lcs::from_bytes(blob)
+        .map_err(|e| format_err!("Failed to decode the genesis blob : {}", e))?;
+    let num_owners = layout.owners.len();
+    spawn_blocking(move || verify_genesis_transaction(&genesis, num_owners)).await?
+}
+
+fn verify_genesis_transaction(
+    genesis: &Transaction,
+    num_owners: usize,
+) -> Result<VerificationReport> {
+    let db_path = libra_temppath::TempPath::new();
+    let db_rw = DbReaderWriter::new(LibraDB::open(db_path.path(), false, None)?);
+    let waypoint = db_bootstrapper::generate_waypoint::<LibraVM>(&db_rw, genesis)
+        .map_err(|e| format_err!("Failed to execute genesis : {}", e))?;
+    // Executes genesis again and only commits it if it derives the same waypoint
+    db_bootstrapper::maybe_bootstrap::<LibraVM>(&db_rw, genesis, waypoint)
+        .map_err(|e| format_err!("Genesis does not derive a stable waypoint : {}", e))?;
+
+    let mut problems = vec![];
+    let ledger_info = db_rw.reader.get_latest_ledger_info()?;
+    let ledger_info = ledger_info.ledger_info();
+    if let Err(e) = waypoint.verify(ledger_info) {
+        problems.push(format!(
+            "Waypoint does not match the genesis ledger info : {}",
+            e
+        ));
+    }
+    let config_blob = db_rw
+        .reader
+        .get_latest_account_state(config_address())?
+        .ok_or_else(|| format_err!("Genesis does not create the config account"))?;
+    let validator_set = validator_set(&config_blob)?;
+    if validator_set.payload().len() != num_owners {
+        problems.push(format!(
+            "Genesis has {} validators, the layout has {} owners",
+            validator_set.payload().len(),
+            num_owners
+        ));
+    }
+    let mut consensus_keys = HashSet::new();
+    for info in validator_set.payload() {
+        let account = info.account_address();
+        if info.consensus_voting_power() == 0 {
+            problems.push(format!("Validator {} has no voting power", account));
+        }
+        if !consensus_keys.insert(info.consensus_public_key()) {
+            problems.push(format!(
+                "Validator {} shares its consensus key with another validator",
+                account
+            ));
+        }
+        if let Err(e) = NetworkAddress::try_from(&info.config().full_node_network_address) {
+            problems.push(format!(
+                "Validator {} has an invalid fullnode network address : {}",
+                account, e
+            ));
+        }
+    }
+    let validators: Vec<_> = validator_set
+        .payload()
+        .iter()
+        .map(|info| *info.account_address())
+        .collect();
+    match ledger_info.next_epoch_state() {
+        Some(epoch_state) => {
+            let in_epoch: Vec<_> = epoch_state
+                .verifier
+                .get_ordered_account_addresses_iter()
+                .collect();
+            if let Err(e) = check_validator_set(&validators, &in_epoch) {
+                problems.push(format!("Genesis epoch state does not match: {}", e));
+            }
+        }
+        None => problems.push("Genesis ledger info does not start an epoch".to_string()),
+    }
+    Ok(VerificationReport {
+        waypoint,
+        validators,
+        problems,
+    })
+}
+
+/// Fails listing the accounts missing from and unexpected in the on chain validator set
+pub(super) fn check_validator_set(
+    expected: &[AccountAddress],
+    on_chain: &[AccountAddress],
+) -> Result<()> {
+    let expected: BTreeSet<_> = expected.iter().collect();
+    let on_chain: BTreeSet<_> = on_chain.iter().collect();
+    if expected == on_chain {
+        return Ok(());
+    }
+    let accounts = |accounts: BTreeSet<&&AccountAddress>| -> String {
+        accounts
+            .into_iter()
+            .map(|account| account.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    bail!(
+        "On chain validator set does not match genesis, missing: [{}], unexpected: [{}]",
+        accounts(expected.difference(&on_chain).collect()),
+        accounts(on_chain.difference(&expected).collect())
+    )
+}
+
+/// Node config snippet setting the base waypoint of a fullnode, in the yaml format of node configs
+fn fullnode_waypoint_config(waypoint: Waypoint) -> Result<String> {
+    let mut base = BTreeMap::new();
+    base.insert("waypoint", WaypointConfig::FromConfig(waypoint));
+    let mut config = BTreeMap::new();
+    config.insert("base", base);
+    Ok(serde_yaml::to_string(&config)?)
+}
+
+/// Waypoints the storage of each validator holds, by pod name, blocks
+fn stored_waypoints(validator_keys: &[KeySource]) -> Result<Vec<(String, Waypoint)>> {
+    validator_keys
+        .iter()
+        .enumerate()
+        .map(|(i, source)| {
+            let pod_name = validator_pod_name(i as u32);
+            let waypoint = source.waypoint(&pod_name)?;
+            Ok((pod_name, waypoint))
+        })
+        .collect()
+}
+
+/// Checks that every validator stores the waypoint derived from the genesis blob, a divergent one
+/// (e.g. from a stale genesis) would keep that validator from joining the network
+fn verify_waypoints(expected: Waypoint, stored: &[(String, Waypoint)]) -> Result<()> {
+    let divergent: Vec<_> = stored
+        .iter()
+        .filter(|(_, waypoint)| *waypoint != expected)
+        .map(|(pod_name, waypoint)| format!("{}: {}", pod_name, waypoint))
+        .collect();
+    if !divergent.is_empty() {
+        bail!(
+            "Waypoints diverge from genesis waypoint {}: [{}]",
+            expected,
+            divergent.join(", ")
+        );
+    }
+    Ok(())
+}
+
+impl ClusterBuilder {
+    /// Forgets the genesis blobs copied to `node`, or to every node if None, so that the next
+    /// distribution copies them again
+    pub(super) fn forget_distributed_genesis(&self, node: Option<&str>) {
+        let mut distributed = self
+            .distributed_genesis
+            .lock()
+            .expect("distributed_genesis lock poisoned");
+        match node {
+            Some(node) => distributed.retain(|(name, _), _| name != node),
+            None => distributed.clear(),
+        }
+    }
+
+    /// Generates a genesis blob and waypoint without allocating any nodes, the validator keys
+    /// are kept in on-disk storage under the genesis-only directory of --work-dir
+    pub async fn generate_genesis_only(params: &ClusterBuilderParams) -> Result<GenesisArtifacts> {
+        let num_validators = params.num_validators;
+        let paths = params.build_paths();
+        let dir = paths.genesis_only_dir();
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .map_err(|e| format_err!("Failed to remove {} : {}", dir.display(), e))?;
+        }
+        fs::create_dir_all(&dir)
+            .map_err(|e| format_err!("Failed to create {} : {}", dir.display(), e))?;
+        let backend_path = |name: &str| dir.join(format!("{}.json", name));
+
+        let libra_root_path = backend_path(LIBRA_ROOT_NAME);
+        let mut libra_root_storage = NamespacedStorage::new(
+            Box::new(OnDiskStorage::new(libra_root_path.clone())),
+            LIBRA_ROOT_NAME.to_string(),
+        );
+        libra_root_storage
+            .create_key(LIBRA_ROOT_KEY)
+            .map_err(|e| format_err!("Failed to create {} : {}", LIBRA_ROOT_KEY, e))?;
+        let mut validator_key_sources = vec![];
+        let mut validator_addresses = vec![];
+        let mut fullnode_addresses = vec![];
+        for i in 0..num_validators {
+            let pod_name = validator_pod_name(i);
+            let path = backend_path(&pod_name);
+            let mut storage = NamespacedStorage::new(
+                Box::new(OnDiskStorage::new(path.clone())),
+                pod_name.clone(),
+            );
+            for key in VALIDATOR_KEYS.iter() {
+                storage
+                    .create_key(key)
+                    .map_err(|e| format_err!("Failed to create {}__{} : {}", pod_name, key, e))?;
+            }
+            validator_key_sources.push(KeySource::Disk { path });
+            validator_addresses.push(network_address(&pod_name, "127.0.0.1", 6180 + i)?);
+            fullnode_addresses.push(network_address(&pod_name, "127.0.0.1", 7180 + i)?);
+        }
+
+        Self::generate_genesis(
+            num_validators,
+            params.genesis_chain_id,
+            params.network_mode,
+            params.genesis_naming,
+            params.layout_format,
+            &paths,
+            &KeySource::Disk {
+                path: libra_root_path,
+            },
+            &validator_key_sources,
+            &validator_addresses,
+            &fullnode_addresses,
+            params.extra_genesis_script()?.as_deref(),
+            &params.genesis_account,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(super) async fn generate_vault_genesis(
+        &self,
+        num_validators: u32,
+        chain_id: ChainId,
+        address_source: AddressSource,
+        network_mode: NetworkMode,
+        naming: GenesisNaming,
+        layout_format: LayoutFormat,
+        paths: &BuildPaths,
+        vault_nodes: &[KubeNode],
+        validator_nodes: &[KubeNode],
+        fullnode_nodes: &[KubeNode],
+        fullnode_port: u32,
+        vault_token: &str,
+        extra_genesis_script: Option<&[u8]>,
+        genesis_accounts: &[GenesisAccount],
+    ) -> Result<GenesisArtifacts> {
+        paths.create_dir()?;
+        let token_path = paths.token();
+        write!(
+            File::create(&token_path).map_err(|e| format_err!(
+                "Failed to create {} : {}",
+                token_path.display(),
+                e
+            ))?,
+            "{}",
+            vault_token
+        )
+        .map_err(|e| format_err!("Failed to write {} : {}", token_path.display(), e))?;
+        let validator_key_sources: Vec<_> = vault_nodes
+            .iter()
+            .map(|node| KeySource::Vault {
+                addr: node.internal_ip.clone(),
+                token_path: token_path.clone(),
+            })
+            .collect();
+        let validator_addresses = validator_nodes
+            .iter()
+            .take(vault_nodes.len())
+            .enumerate()
+            .map(|(i, node)| {
+                network_address(
+                    &validator_pod_name(i as u32),
+                    &node.ip(address_source)?,
+                    6180,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let fullnode_addresses = fullnode_nodes
+            .iter()
+            .take(vault_nodes.len())
+            .enumerate()
+            .map(|(i, node)| {
+                network_address(
+                    &format!("{} fullnode network", validator_pod_name(i as u32)),
+                    &node.ip(address_source)?,
+                    fullnode_port,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::generate_genesis(
+            num_validators,
+            chain_id,
+            network_mode,
+            naming,
+            layout_format,
+            paths,
+            &validator_key_sources[0],
+            &validator_key_sources,
+            &validator_addresses,
+            &fullnode_addresses,
+            extra_genesis_script,
+            genesis_accounts,
+        )
+        .await
+    }
+
+    /// Runs the genesis tool flow with the keys of each validator read from its own key source,
+    /// then creates `genesis_accounts` and applies `extra_genesis_script` to the genesis it
+    /// generated, if any. Writes the layout, genesis blob, waypoint and mint key to their paths
+    /// under the working directory.
+    async fn generate_genesis(
+        num_validators: u32,
+        chain_id: ChainId,
+        network_mode: NetworkMode,
+        naming: GenesisNaming,
+        layout_format: LayoutFormat,
+        paths: &BuildPaths,
+        libra_root_keys: &KeySource,
+        validator_keys: &[KeySource],
+        validator_addresses: &[NetworkAddress],
+        fullnode_addresses: &[NetworkAddress],
+        extra_genesis_script: Option<&[u8]>,
+        genesis_accounts: &[GenesisAccount],
+    ) -> Result<GenesisArtifacts> {
+        let _timer = counters::GENESIS_DURATION_S.start_timer();
+        paths.create_dir()?;
+        let genesis_helper = GenesisHelper::new(paths.genesis_json())
+            .with_publishing_option(network_mode.publishing_option());
+        let layout = Layout {
+            owners: (0..num_validators).map(|i| naming.owner_name(i)).collect(),
+            operators: (0..num_validators)
+                .map(|i| naming.operator_name(i))
+                .collect(),
+            libra_root: vec![LIBRA_ROOT_NAME.to_string()],
+        };
+        let layout_path = paths.layout(layout_format);
+        write_layout(&layout, layout_format, &layout_path)?;
+        genesis_helper
+            .set_layout(&layout_path, "common")
+            .await
+            .map_err(|e| format_err!("Failed to set_layout : {}", e))?;
+        genesis_helper
+            .libra_root_key(&libra_root_keys.backend(), LIBRA_ROOT_NAME, LIBRA_ROOT_NAME)
+            .await
+            .map_err(|e| format_err!("Failed to libra_root_key : {}", e))?;
+
+        let validator_backends: Vec<_> = validator_keys.iter().map(KeySource::backend).collect();
+        for (i, backend) in validator_backends.iter().enumerate() {
+            let pod_name = validator_pod_name(i as u32);
+            let owner_name = naming.owner_name(i as u32);
+            let operator_name = naming.operator_name(i as u32);
+            genesis_helper
+                .owner_key(backend, &pod_name, &owner_name)
+                .await
+                .map_err(|e| format_err!("Failed to owner_key for {} : {}", pod_name, e))?;
+            genesis_helper
+                .operator_key(backend, &pod_name, &operator_name)
+                .await
+                .map_err(|e| format_err!("Failed to operator_key for {} : {}", pod_name, e))?;
+            genesis_helper
+                .validator_config(
+                    &owner_name,
+                    validator_addresses[i].clone(),
+                    fullnode_addresses[i].clone(),
+                    chain_id,
+                    backend,
+                    &pod_name,
+                    &operator_name,
+                )
+                .await
+                .map_err(|e| format_err!("Failed to validator_config for {} : {}", pod_name, e))?;
+            genesis_helper
+                .set_operator(&operator_name, &owner_name)
+                .await
+                .map_err(|e| format_err!("Failed to set_operator for {} : {}", pod_name, e))?;
+        }
+        genesis_helper.genesis(chain_id, &paths.genesis()).await?;
+        if validator_backends.is_empty() {
+            bail!("No validators to generate genesis");
+        }
+        for (i, backend) in validator_backends.iter().enumerate() {
+            let pod_name = validator_pod_name(i as u32);
+            genesis_helper
+                .create_and_insert_waypoint(chain_id, backend, &pod_name)
+                .await
+                .map_err(|e| {
+                    format_err!(
+                        "Failed to create_and_insert_waypoint for {} : {}",
+                        pod_name,
+                        e
+                    )
+                })?;
+        }
+        let waypoint = genesis_helper
+            .create_waypoint(chain_id)
+            .await
+            .map_err(|e| format_err!("Failed to create_waypoint : {}", e))?;
+        let waypoint = if extra_genesis_script.is_some() || !genesis_accounts.is_empty() {
+            let genesis_path = paths.genesis();
+            let code = extra_genesis_script.map(<[u8]>::to_vec);
+            let genesis_accounts = genesis_accounts.to_vec();
+            let validator_keys = validator_keys.to_vec();
+            spawn_blocking(move || {
+                let waypoint =
+                    post_process_genesis(&genesis_path, code.as_deref(), &genesis_accounts)?;
+                // The tool inserted the waypoint of the genesis it generated
+                for (i, source) in validator_keys.iter().enumerate() {
+                    source.set_waypoint(&validator_pod_name(i as u32), waypoint)?;
+                }
+                Ok::<_, anyhow::Error>(waypoint)
+            })
+            .await??
+        } else {
+            waypoint
+        };
+        let validator_keys = validator_keys.to_vec();
+        let stored_waypoints = spawn_blocking(move || stored_waypoints(&validator_keys)).await??;
+        verify_waypoints(waypoint, &stored_waypoints)?;
+        let waypoint_path = paths.waypoint();
+        fs::write(&waypoint_path, waypoint.to_string())
+            .map_err(|e| format_err!("Failed to write {} : {}", waypoint_path.display(), e))?;
+        let mint_key_path = paths.mint_key();
+        genesis_helper
+            .extract_private_key(
+                LIBRA_ROOT_KEY,
+                &mint_key_path,
+                format!(
+                    "{};namespace={}",
+                    libra_root_keys.backend(),
+                    LIBRA_ROOT_NAME
+                )
+                .as_str(),
+            )
+            .await
+            .map_err(|e| format_err!("Failed to extract_private_key : {}", e))?;
+        let mint_key = fs::read(&mint_key_path)
+            .map_err(|e| format_err!("Failed to read {} : {}", mint_key_path.display(), e))
+            .and_then(|bytes| {
+                lcs::from_bytes(&bytes)
+                    .map_err(|e| format_err!("Failed to parse {} : {}", mint_key_path.display(), e))
+            })?;
+
+        let genesis_path = paths.genesis();
+        let blob = fs::read(&genesis_path)
+            .map_err(|e| format_err!("Failed to read {} : {}", genesis_path.display(), e))?;
+        Ok(GenesisArtifacts {
+            blob,
+            waypoint,
+            mint_key,
+            layout,
+        })
+    }
+
+    /// Downloads the genesis.blob at `url` to the working directory and inserts `waypoint` into
+    /// the storage of every validator in its vault, in place of generating genesis
+    pub(super) async fn download_genesis(
+        &self,
+        paths: &BuildPaths,
+        url: &str,
+        waypoint: Waypoint,
+        validator_vaults: &[KubeNode],
+        vault_token: &str,
+    ) -> Result<()> {
+        paths.create_dir()?;
+        let genesis = fetch_genesis(url).await?;
+        let genesis_path = paths.genesis();
+        fs::write(&genesis_path, &genesis)
+            .map_err(|e| format_err!("Failed to write {} : {}", genesis_path.display(), e))?;
+        let waypoint_path = paths.waypoint();
+        fs::write(&waypoint_path, waypoint.to_string())
+            .map_err(|e| format_err!("Failed to write {} : {}", waypoint_path.display(), e))?;
+        try_join_all(
+            validator_vaults
+                .iter()
+                .enumerate()
+                .map(|(i, node)| insert_waypoint(i as u32, node, waypoint, vault_token)),
+        )
+        .await?;
+        info!(
+            "Downloaded genesis.blob ({} bytes) from {} with waypoint {}",
+            genesis.len(),
+            url,
+            waypoint
+        );
+        Ok(())
+    }
+
+    /// Copies genesis.blob to `path` on the validator nodes that don't hold it yet, returns the
+    /// nodes it was copied to
+    pub(super) async fn copy_genesis(
+        &self,
+        paths: &BuildPaths,
+        path: &str,
+        validator_nodes: &[KubeNode],
+    ) -> Result<Vec<String>> {
+        let genesis_path = paths.genesis();
+        let genesis = fs::read(&genesis_path)
+            .map_err(|e| format_err!("Failed to read {} : {}", genesis_path.display(), e))?;
+        let updated = Self::distribute_genesis(
+            &*self.cluster_swarm,
+            &self.distributed_genesis,
+            &genesis,
+            path,
+            validator_nodes,
+        )
+        .await?;
+        info!(
+            "Copied genesis.blob to {} on {} of {} validator nodes",
+            path,
+            updated.len(),
+            validator_nodes.len()
+        );
+        Ok(updated)
+    }
+
+    /// Puts the waypoint of the genesis in --work-dir on the fullnodes, `fullnode_nodes` holds the
+    /// nodes of the fullnodes ordered by validator
+    pub(super) async fn copy_fullnode_waypoint(
+        &self,
+        paths: &BuildPaths,
+        path: &str,
+        fullnode_nodes: &[KubeNode],
+        fullnodes_per_validator: u32,
+    ) -> Result<()> {
+        let waypoint_path = paths.waypoint();
+        let waypoint = fs::read_to_string(&waypoint_path)
+            .map_err(|e| format_err!("Failed to read {} : {}", waypoint_path.display(), e))?;
+        let waypoint = Waypoint::from_str(waypoint.trim())
+            .map_err(|e| format_err!("Invalid waypoint in {} : {}", waypoint_path.display(), e))?;
+        let targets: Vec<_> = fullnode_nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let i = i as u32;
+                (
+                    fullnode_pod_name(i / fullnodes_per_validator, i % fullnodes_per_validator),
+                    node.name.clone(),
+                )
+            })
+            .collect();
+        Self::put_fullnode_waypoint(&*self.cluster_swarm, waypoint, path, &targets).await?;
+        info!(
+            "Put waypoint {} at {} on {} fullnode nodes",
+            waypoint,
+            path,
+            targets.len()
+        );
+        Ok(())
+    }
+
+    /// Puts the config snippet setting `waypoint` at `path` on the nodes of the (pod name, node
+    /// name) `targets`
+    async fn put_fullnode_waypoint(
+        swarm: &(impl ClusterSwarm + ?Sized),
+        waypoint: Waypoint,
+        path: &str,
+        targets: &[(String, String)],
+    ) -> Result<()> {
+        let config = fullnode_waypoint_config(waypoint)?;
+        try_join_all(targets.iter().map(|(pod_name, node)| {
+            swarm.put_file(node, pod_name, path, config.clone().into_bytes())
+        }))
+        .await
+        .map_err(|e| format_err!("Failed to put the waypoint on fullnode nodes : {}", e))?;
+        Ok(())
+    }
+
+    /// Puts the genesis.blob cached in --work-dir again on the named validators, e.g. after they
+    /// were rescheduled onto a fresh disk. The blob is put even if it was copied there before
+    pub async fn redistribute_genesis(
+        &self,
+        params: &ClusterBuilderParams,
+        cluster: &Cluster,
+        pod_names: &[String],
+    ) -> Result<()> {
+        let targets = validators_named(cluster, pod_names)?
+            .into_iter()
+            .map(|instance| {
+                let node = instance.k8s_node().ok_or_else(|| {
+                    format_err!(
+                        "Can not copy genesis to {}, it is not a k8s instance",
+                        instance.peer_name()
+                    )
+                })?;
+                Ok((instance.peer_name().clone(), node.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let genesis_path = params.build_paths().genesis();
+        let genesis = fs::read(&genesis_path)
+            .map_err(|e| format_err!("Failed to read {} : {}", genesis_path.display(), e))?;
+        Self::put_genesis(
+            &*self.cluster_swarm,
+            &self.distributed_genesis,
+            &genesis,
+            &params.distributed_genesis_path(),
+            &targets,
+        )
+        .await?;
+        info!("Copied genesis.blob to {}", pod_names.join(", "));
+        Ok(())
+    }
+
+    /// Puts `genesis` at `path` on the validators of `validator_nodes`, skipping the nodes
+    /// `distributed` records as already holding the identical blob. Returns the nodes the blob
+    /// was put on
+    async fn distribute_genesis(
+        swarm: &(impl ClusterSwarm + ?Sized),
+        distributed: &Mutex<HashMap<(String, String), HashValue>>,
+        genesis: &[u8],
+        path: &str,
+        validator_nodes: &[KubeNode],
+    ) -> Result<Vec<String>> {
+        let hash = HashValue::sha3_256_of(genesis);
+        let outdated: Vec<_> = {
+            let distributed = distributed.lock().unwrap();
+            validator_nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| {
+                    distributed.get(&(node.name.clone(), path.to_string())) != Some(&hash)
+                })
+                .map(|(i, node)| (validator_pod_name(i as u32), node.name.clone()))
+                .collect()
+        };
+        Self::put_genesis(swarm, distributed, genesis, path, &outdated).await?;
+        Ok(outdated.into_iter().map(|(_, node)| node).collect())
+    }
+
+    /// Puts `genesis` at `path` on the nodes of the (pod name, node name) `targets` and records
+    /// them in `distributed`
+    async fn put_genesis(
+        swarm: &(impl ClusterSwarm + ?Sized),
+        distributed: &Mutex<HashMap<(String, String), HashValue>>,
+        genesis: &[u8],
+        path: &str,
+        targets: &[(String, String)],
+    ) -> Result<()> {
+        let hash = HashValue::sha3_256_of(genesis);
+        try_join_all(targets.iter().map(|(pod_name, node)| async move {
+            swarm
+                .put_file(node, pod_name, path, genesis.to_vec())
+                .await?;
+            distributed
+                .lock()
+                .unwrap()
+                .insert((node.clone(), path.to_string()), hash);
+            Ok::<(), anyhow::Error>(())
+        }))
+        .await
+        .map_err(|e| format_err!("Failed to copy genesis.blob to validator nodes : {}", e))?;
+        Ok(())
+    }
+
+    /// Archives the genesis artifacts of the last setup_cluster (genesis.blob, layout, waypoint
+    /// and, if it ran with --include-secrets, mint.key) into a gzipped tarball at `out`
+    pub async fn archive_genesis(&self, out: &Path) -> Result<()> {
+        let genesis_archive = self
+            .genesis_archive
+            .lock()
+            .expect("genesis_archive lock poisoned")
+            .clone()
+            .ok_or_else(|| format_err!("No genesis was built to archive"))?;
+        let paths = &genesis_archive.paths;
+        let mut artifacts = vec![
+            paths.genesis(),
+            paths.layout(genesis_archive.layout_format),
+            paths.waypoint(),
+        ];
+        if genesis_archive.include_secrets {
+            artifacts.push(paths.mint_key());
+        }
+        let mut cmd = tokio::process::Command::new("tar");
+        cmd.arg("-czf").arg(out);
+        for path in artifacts.iter() {
+            if !path.exists() {
+                bail!("Genesis artifact {} not found", path.display());
+            }
+            let dir = path
+                .parent()
+                .ok_or_else(|| format_err!("Invalid artifact path {}", path.display()))?;
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| format_err!("Invalid artifact path {}", path.display()))?;
+            cmd.arg("-C").arg(dir).arg(file_name);
+        }
+        let status = cmd
+            .status()
+            .await
+            .map_err(|e| format_err!("Failed to spawn tar : {}", e))?;
+        if !status.success() {
+            bail!(
+                "Failed to archive genesis artifacts to {}, exit code {:?}",
+                out.display(),
+                status.code()
+            );
+        }
+        info!("Archived genesis artifacts to {}", out.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        genesis_script::{test_genesis_account, GenesisAccountType},
+        test_utils::{block_on, MockSwarm},
+    };
+
+    #[test]
+    pub fn test_archive_genesis() {
+        let builder = ClusterBuilder::new("test".to_string(), MockSwarm::default());
+        let work_dir = libra_temppath::TempPath::new();
+        work_dir.create_as_dir().unwrap();
+        let paths = BuildPaths::new(work_dir.path());
+        for path in &[
+            paths.genesis(),
+            paths.layout(LayoutFormat::Toml),
+            paths.waypoint(),
+            paths.mint_key(),
+        ] {
+            fs::write(path, b"test").unwrap();
+        }
+        let out = work_dir.path().join("genesis.tar.gz");
+        assert_eq!(
+            block_on(builder.archive_genesis(&out))
+                .unwrap_err()
+                .to_string(),
+            "No genesis was built to archive"
+        );
+
+        let mut archived = |include_secrets| {
+            *builder.genesis_archive.lock().unwrap() = Some(GenesisArchive {
+                paths: paths.clone(),
+                layout_format: LayoutFormat::Toml,
+                include_secrets,
+            });
+            block_on(builder.archive_genesis(&out)).unwrap();
+            let listing = std::process::Command::new("tar")
+                .arg("-tzf")
+                .arg(&out)
+                .output()
+                .unwrap();
+            String::from_utf8(listing.stdout)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            archived(false),
+            vec!["genesis.blob", "layout.toml", "waypoint.txt"]
+        );
+        assert_eq!(
+            archived(true),
+            vec!["genesis.blob", "layout.toml", "waypoint.txt", "mint.key"]
+        );
+    }
+
+    #[test]
+    pub fn test_distribute_genesis() {
+        let swarm = MockSwarm::default();
+        let nodes: Vec<_> = (0..3)
+            .map(|i| KubeNode {
+                name: format!("node-{}", i),
+                provider_id: format!("provider-{}", i),
+                internal_ip: format!("10.0.0.{}", i),
+                external_ip: None,
+                unschedulable: false,
+                labels: Default::default(),
+            })
+            .collect();
+        let distributed = Mutex::new(HashMap::new());
+        let mut distribute = |genesis: &[u8], nodes: &[KubeNode]| {
+            block_on(ClusterBuilder::distribute_genesis(
+                &swarm,
+                &distributed,
+                genesis,
+                "/opt/libra/etc/genesis2.blob",
+                nodes,
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(
+            distribute(b"genesis", &nodes[..2]),
+            vec!["node-0", "node-1"]
+        );
+        // Nodes already holding the blob are skipped
+        assert!(distribute(b"genesis", &nodes[..2]).is_empty());
+        assert_eq!(distribute(b"genesis", &nodes), vec!["node-2"]);
+        assert_eq!(distribute(b"genesis2", &nodes).len(), 3);
+
+        let files = swarm.files.into_inner().unwrap();
+        assert_eq!(files.len(), 6);
+        assert!(files
+            .iter()
+            .all(|(_, path)| path == "/opt/libra/etc/genesis2.blob"));
+    }
+
+    #[test]
+    pub fn test_distribute_genesis_invalidation() {
+        let swarm = Arc::new(MockSwarm::default());
+        let builder = ClusterBuilder::shared("test".to_string(), swarm.clone());
+        let nodes: Vec<_> = (0..2)
+            .map(|i| KubeNode {
+                name: format!("node-{}", i),
+                provider_id: format!("provider-{}", i),
+                internal_ip: format!("10.0.0.{}", i),
+                external_ip: None,
+                unschedulable: false,
+                labels: Default::default(),
+            })
+            .collect();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let distribute = |rt: &mut tokio::runtime::Runtime, path: &str| {
+            rt.block_on(ClusterBuilder::distribute_genesis(
+                &*swarm,
+                &builder.distributed_genesis,
+                b"genesis",
+                path,
+                &nodes,
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(distribute(&mut rt, "/opt/libra/etc/genesis2.blob").len(), 2);
+        // The same blob at another path is copied again
+        assert_eq!(
+            distribute(&mut rt, "/opt/libra/etc/distributed.blob"),
+            vec!["node-0", "node-1"]
+        );
+        assert!(distribute(&mut rt, "/opt/libra/etc/genesis2.blob").is_empty());
+
+        // Cleaning the data of a node wipes its genesis blobs
+        rt.block_on(builder.clean_node_data("node-1")).unwrap();
+        assert_eq!(
+            distribute(&mut rt, "/opt/libra/etc/genesis2.blob"),
+            vec!["node-1"]
+        );
+        assert_eq!(
+            distribute(&mut rt, "/opt/libra/etc/distributed.blob"),
+            vec!["node-1"]
+        );
+
+        builder.forget_distributed_genesis(None);
+        assert_eq!(distribute(&mut rt, "/opt/libra/etc/genesis2.blob").len(), 2);
+        assert_eq!(swarm.files.lock().unwrap().len(), 8);
+    }
+
+    #[test]
+    pub fn test_put_fullnode_waypoint() {
+        let swarm = MockSwarm::default();
+        let waypoint = Waypoint::from_str(&format!("0:{}", "a".repeat(64))).unwrap();
+        let targets = vec![
+            (fullnode_pod_name(0, 0), "node-0".to_string()),
+            (fullnode_pod_name(1, 0), "node-1".to_string()),
+        ];
+        block_on(ClusterBuilder::put_fullnode_waypoint(
+            &swarm,
+            waypoint,
+            "/opt/libra/etc/waypoint.yaml",
+            &targets,
+        ))
+        .unwrap();
+        assert_eq!(
+            swarm.files.into_inner().unwrap(),
+            vec![
+                (
+                    "node-0".to_string(),
+                    "/opt/libra/etc/waypoint.yaml".to_string()
+                ),
+                (
+                    "node-1".to_string(),
+                    "/opt/libra/etc/waypoint.yaml".to_string()
+                ),
+            ]
+        );
+
+        let config: BTreeMap<String, BTreeMap<String, WaypointConfig>> =
+            serde_yaml::from_str(&fullnode_waypoint_config(waypoint).unwrap()).unwrap();
+        assert_eq!(
+            config["base"]["waypoint"].waypoint_from_config(),
+            Some(waypoint)
+        );
+    }
+
+    #[test]
+    pub fn test_genesis_file_names() {
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        assert_eq!(
+            params.distributed_genesis_path(),
+            "/opt/libra/etc/genesis2.blob"
+        );
+        assert_eq!(
+            ConfigOverride::render(&params.cfg_overrides_for(&validator_pod_name(0))),
+            vec!["prune_window=50000"]
+        );
+
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--genesis-file-name",
+            "distributed.blob",
+            "--active-genesis-file-name",
+            "active.blob",
+            "--node-cfg",
+            "val-1:genesis_file_location=\"genesis.blob\"",
+        ]);
+        assert_eq!(
+            params.distributed_genesis_path(),
+            "/opt/libra/etc/distributed.blob"
+        );
+        assert_eq!(
+            ConfigOverride::render(&params.cfg_overrides_for(&validator_pod_name(0))),
+            vec![
+                "prune_window=50000",
+                "genesis_file_location=\"active.blob\""
+            ]
+        );
+        // --node-cfg wins, fullnodes don't get the distributed genesis
+        assert_eq!(
+            params.cfg_overrides_for(&validator_pod_name(1))[1].value,
+            toml::Value::String("genesis.blob".to_string())
+        );
+        assert_eq!(params.cfg_overrides_for(&fullnode_pod_name(0, 0)).len(), 1);
+        for name in &["", "etc/genesis.blob"] {
+            assert!(ClusterBuilderParams::from_iter_safe(vec![
+                "cluster-builder",
+                "--genesis-file-name",
+                name
+            ])
+            .is_err());
+        }
+
+        let swarm = MockSwarm::default();
+        let targets = vec![(validator_pod_name(0), "node-0".to_string())];
+        block_on(ClusterBuilder::put_genesis(
+            &swarm,
+            &Mutex::new(HashMap::new()),
+            b"genesis",
+            &params.distributed_genesis_path(),
+            &targets,
+        ))
+        .unwrap();
+        assert_eq!(
+            *swarm.files.lock().unwrap(),
+            vec![(
+                "node-0".to_string(),
+                "/opt/libra/etc/distributed.blob".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    pub fn test_redistribute_genesis() {
+        let instance = |pod_name: String| {
+            Instance::new(
+                pod_name,
+                "127.0.0.1".to_string(),
+                8080,
+                None,
+                reqwest::Client::new(),
+            )
+        };
+        let cluster = Cluster::new(
+            vec![
+                instance(validator_pod_name(0)),
+                instance(validator_pod_name(1)),
+            ],
+            vec![instance(fullnode_pod_name(0, 0))],
+            vec![],
+            vec![],
+        );
+        let validators = validators_named(&cluster, &[validator_pod_name(1)]).unwrap();
+        assert_eq!(validators[0].peer_name(), &validator_pod_name(1));
+        let error = validators_named(&cluster, &[validator_pod_name(0), fullnode_pod_name(0, 0)])
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "fn-0-0 is not a validator of the cluster");
+
+        let swarm = MockSwarm::default();
+        let distributed = Mutex::new(HashMap::new());
+        let targets = vec![(validator_pod_name(1), "node-1".to_string())];
+        for _ in 0..2 {
+            block_on(ClusterBuilder::put_genesis(
+                &swarm,
+                &distributed,
+                b"genesis",
+                "/opt/libra/etc/genesis2.blob",
+                &targets,
+            ))
+            .unwrap();
+        }
+        // Unlike distribute_genesis, the blob is put again on nodes that already got it
+        assert_eq!(swarm.files.lock().unwrap().len(), 2);
+        assert_eq!(
+            distributed.lock().unwrap().get("node-1"),
+            Some(&HashValue::sha3_256_of(b"genesis"))
+        );
+    }
+
+    #[test]
+    pub fn test_verify_waypoints() {
+        let waypoint = |c: &str| Waypoint::from_str(&format!("0:{}", c.repeat(64))).unwrap();
+        let expected = waypoint("a");
+        let work_dir = libra_temppath::TempPath::new();
+        work_dir.create_as_dir().unwrap();
+        // val-0 and val-1 share a storage, val-2 has its own
+        let shared = KeySource::Disk {
+            path: work_dir.path().join("shared.json"),
+        };
+        let own = KeySource::Disk {
+            path: work_dir.path().join("val-2.json"),
+        };
+        let sources = vec![shared.clone(), shared, own];
+        for (i, source) in sources.iter().enumerate() {
+            source
+                .set_waypoint(&validator_pod_name(i as u32), expected)
+                .unwrap();
+        }
+        verify_waypoints(expected, &stored_waypoints(&sources).unwrap()).unwrap();
+
+        // The waypoints are read back from the storages, not taken from the tool
+        sources[1]
+            .set_waypoint(&validator_pod_name(1), waypoint("b"))
+            .unwrap();
+        let stored = stored_waypoints(&sources).unwrap();
+        assert_eq!(stored[0], (validator_pod_name(0), expected));
+        let err = verify_waypoints(expected, &stored).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            format!(
+                "Waypoints diverge from genesis waypoint {}: [val-1: {}]",
+                expected,
+                waypoint("b")
+            )
+        );
+        // A validator without a waypoint fails the check too
+        let missing = KeySource::Disk {
+            path: work_dir.path().join("missing.json"),
+        };
+        assert!(stored_waypoints(&[missing]).is_err());
+    }
+
+    #[test]
+    pub fn test_generate_genesis_only() {
+        let work_dir = libra_temppath::TempPath::new();
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "4",
+            "--work-dir",
+            work_dir.path().to_str().unwrap(),
+        ]);
+        let paths = params.build_paths();
+        let genesis_count = counters::GENESIS_DURATION_S.get_sample_count();
+        let artifacts = block_on(ClusterBuilder::generate_genesis_only(&params))
+            .expect("Failed to generate genesis");
+        assert!(counters::GENESIS_DURATION_S.get_sample_count() > genesis_count);
+        assert!(!artifacts.blob.is_empty());
+        assert_eq!(artifacts.waypoint.version(), 0);
+        // Every artifact is written under --work-dir
+        assert_eq!(fs::read(paths.genesis()).unwrap(), artifacts.blob);
+        assert!(paths.genesis_json().exists());
+        assert!(paths.layout(params.layout_format).exists());
+        let written = fs::read_to_string(paths.waypoint()).unwrap();
+        assert_eq!(written, artifacts.waypoint.to_string());
+        assert_eq!(artifacts.layout.owners.len(), 4);
+
+        // Recomputing the blob and waypoint from the shared storage yields the returned ones
+        let genesis_helper = GenesisHelper::new(paths.genesis_json());
+        let regenerated_path = paths.genesis_only_dir().join("regenerated.blob");
+        block_on(genesis_helper.genesis(params.genesis_chain_id, &regenerated_path)).unwrap();
+        assert_eq!(fs::read(&regenerated_path).unwrap(), artifacts.blob);
+        let waypoint = block_on(genesis_helper.create_waypoint(params.genesis_chain_id)).unwrap();
+        assert_eq!(waypoint, artifacts.waypoint);
+
+        // The mint key is the libra root key used in genesis
+        let libra_root_storage = NamespacedStorage::new(
+            Box::new(OnDiskStorage::new(
+                paths
+                    .genesis_only_dir()
+                    .join(format!("{}.json", LIBRA_ROOT_NAME)),
+            )),
+            LIBRA_ROOT_NAME.to_string(),
+        );
+        assert_eq!(
+            libra_root_storage
+                .get_public_key(LIBRA_ROOT_KEY)
+                .unwrap()
+                .public_key,
+            libra_crypto::ed25519::Ed25519PublicKey::from(&artifacts.mint_key)
+        );
+    }
+
+    #[test]
+    pub fn test_verify_genesis() {
+        let work_dir = libra_temppath::TempPath::new();
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "2",
+            "--work-dir",
+            work_dir.path().to_str().unwrap(),
+        ]);
+        let artifacts = block_on(ClusterBuilder::generate_genesis_only(&params))
+            .expect("Failed to generate genesis");
+
+        let report = block_on(verify_genesis(&artifacts.blob, &artifacts.layout)).unwrap();
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(report.waypoint, artifacts.waypoint);
+        assert_eq!(report.validators.len(), 2);
+
+        // A layout with fewer owners than genesis has validators
+        let mut layout = artifacts.layout.clone();
+        layout.owners.pop();
+        let report = block_on(verify_genesis(&artifacts.blob, &layout)).unwrap();
+        assert!(!report.is_valid());
+        assert!(report.problems[0].contains("2 validators, the layout has 1 owners"));
+
+        // A tampered blob does not decode
+        let tampered = &artifacts.blob[..artifacts.blob.len() / 2];
+        assert!(block_on(verify_genesis(tampered, &artifacts.layout)).is_err());
+    }
+
+    #[test]
+    pub fn test_generate_genesis_role_naming() {
+        assert_eq!(
+            GenesisNaming::from_str("role").unwrap(),
+            GenesisNaming::Role
+        );
+        assert!(GenesisNaming::from_str("pods").is_err());
+
+        let work_dir = libra_temppath::TempPath::new();
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "2",
+            "--genesis-naming",
+            "role",
+            "--work-dir",
+            work_dir.path().to_str().unwrap(),
+        ]);
+        let artifacts = block_on(ClusterBuilder::generate_genesis_only(&params))
+            .expect("Failed to generate genesis");
+        assert_eq!(artifacts.layout.owners, vec!["owner-0", "owner-1"]);
+        assert_eq!(artifacts.layout.operators, vec!["op-0", "op-1"]);
+        // The validator keys are still read from the storage of the pods
+        let storage = NamespacedStorage::new(
+            Box::new(OnDiskStorage::new(
+                params
+                    .build_paths()
+                    .genesis_only_dir()
+                    .join(format!("{}.json", validator_pod_name(1))),
+            )),
+            validator_pod_name(1),
+        );
+        assert_eq!(
+            storage.get(WAYPOINT).unwrap().value.string().unwrap(),
+            artifacts.waypoint.to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_generate_genesis_accounts() {
+        let work_dir = libra_temppath::TempPath::new();
+        let vasp = test_genesis_account(GenesisAccountType::ParentVasp, 1, 1000).to_string();
+        let dd = test_genesis_account(GenesisAccountType::DesignatedDealer, 2, 2000).to_string();
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "2",
+            "--genesis-account",
+            &vasp,
+            "--genesis-account",
+            &dd,
+            "--work-dir",
+            work_dir.path().to_str().unwrap(),
+        ]);
+        assert_eq!(params.genesis_account.len(), 2);
+        params.validate().unwrap();
+        let artifacts = block_on(ClusterBuilder::generate_genesis_only(&params))
+            .expect("Failed to generate genesis");
+        let report = block_on(verify_genesis(&artifacts.blob, &artifacts.layout)).unwrap();
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(report.waypoint, artifacts.waypoint);
+        // The validators get the waypoint of the genesis with the accounts
+        let storage = NamespacedStorage::new(
+            Box::new(OnDiskStorage::new(
+                params
+                    .build_paths()
+                    .genesis_only_dir()
+                    .join(format!("{}.json", validator_pod_name(0))),
+            )),
+            validator_pod_name(0),
+        );
+        assert_eq!(
+            storage.get(WAYPOINT).unwrap().value.string().unwrap(),
+            artifacts.waypoint.to_string()
+        );
+
+        let duplicate = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--genesis-account",
+            &vasp,
+            "--genesis-account",
+            &vasp.replacen("vasp", "dd", 1),
+        ]);
+        assert!(duplicate.validate().is_err());
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--genesis-account",
+            "vasp:0xdd:1000",
+        ])
+        .is_err());
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--genesis-account",
+            &vasp,
+            "--skip-genesis",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_generate_genesis_mixed_key_sources() {
+        let work_dir = libra_temppath::TempPath::new();
+        work_dir.create_as_dir().unwrap();
+        let paths = BuildPaths::new(work_dir.path());
+        let shared_path = work_dir.path().join("shared.json");
+        let own_path = work_dir.path().join("val-1.json");
+        let create_keys = |path: &Path, namespace: &str, keys: &[&str]| {
+            let mut storage = NamespacedStorage::new(
+                Box::new(OnDiskStorage::new(path.to_path_buf())),
+                namespace.to_string(),
+            );
+            for key in keys {
+                storage.create_key(key).unwrap();
+            }
+        };
+        // val-0 keeps its keys next to the libra root key, val-1 in its own storage
+        create_keys(&shared_path, LIBRA_ROOT_NAME, &[LIBRA_ROOT_KEY]);
+        create_keys(&shared_path, &validator_pod_name(0), &VALIDATOR_KEYS);
+        create_keys(&own_path, &validator_pod_name(1), &VALIDATOR_KEYS);
+        let shared = KeySource::Disk { path: shared_path };
+        let own = KeySource::Disk { path: own_path };
+        let addresses: Vec<_> = (0..2)
+            .map(|i| network_address(&validator_pod_name(i), "127.0.0.1", 6180 + i).unwrap())
+            .collect();
+
+        // Fails if the waypoints stored in the validators' storages diverge
+        let artifacts = block_on(ClusterBuilder::generate_genesis(
+            2,
+            ChainId::new(1),
+            NetworkMode::Open,
+            GenesisNaming::Pod,
+            LayoutFormat::Toml,
+            &paths,
+            &shared,
+            &[shared.clone(), own],
+            &addresses,
+            &addresses,
+            None,
+            &[],
+        ))
+        .expect("Failed to generate genesis");
+        assert_eq!(artifacts.layout.owners.len(), 2);
+        assert_eq!(fs::read(paths.genesis()).unwrap(), artifacts.blob);
+
+        let vault = KeySource::Vault {
+            addr: "10.0.0.1".to_string(),
+            token_path: PathBuf::from("/tmp/token"),
+        };
+        assert_eq!(
+            vault.backend(),
+            "backend=vault;server=http://10.0.0.1:8200;token=/tmp/token"
+        );
+    }
+
+    #[test]
+    pub fn test_waypoint_only() {
+        assert!(
+            ClusterBuilderParams::from_iter_safe(vec!["cluster-builder", "--waypoint-only"])
+                .is_err()
+        );
+
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--waypoint-only",
+            "--genesis-url",
+            "https://genesis.example/genesis.blob",
+            "--validator-env",
+            "RUST_LOG=debug",
+        ]);
+        assert!(params.waypoint_only);
+        assert_eq!(
+            params.validator_pod_env(),
+            vec![
+                (
+                    "CFG_GENESIS_URL".to_string(),
+                    "https://genesis.example/genesis.blob".to_string()
+                ),
+                (
+                    "CFG_GENESIS_PATH".to_string(),
+                    "/opt/libra/etc/genesis2.blob".to_string()
+                ),
+                ("RUST_LOG".to_string(), "debug".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_fetch_genesis_script() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let blob = b"genesis".to_vec();
+        let work_dir = libra_temppath::TempPath::new();
+        work_dir.create_as_dir().unwrap();
+        let genesis_path = work_dir.path().join("etc").join("genesis.blob");
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let status = rt.block_on(async {
+            let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let genesis_url = format!("http://{}/genesis.blob", listener.local_addr().unwrap());
+            let response = blob.clone();
+            tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await;
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.len()
+                );
+                socket.write_all(header.as_bytes()).await.unwrap();
+                socket.write_all(&response).await.unwrap();
+            });
+            // The environment the validator pods get with --waypoint-only
+            tokio::process::Command::new("bash")
+                .arg("-c")
+                .arg(include_str!(
+                    "../../../docker/validator-dynamic/fetch-genesis.sh"
+                ))
+                .env("CFG_GENESIS_URL", genesis_url)
+                .env("CFG_GENESIS_PATH", &genesis_path)
+                .status()
+                .await
+                .unwrap()
+        });
+        assert!(status.success());
+        assert_eq!(fs::read(&genesis_path).unwrap(), blob);
+    }
+
+    #[test]
+    pub fn test_download_genesis() {
+        let waypoint = format!("0:{}", "a".repeat(64));
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--download-genesis",
+            "--genesis-url",
+            "http://127.0.0.1/genesis.blob",
+            "--genesis-waypoint",
+            &waypoint,
+        ]);
+        assert!(params.download_genesis);
+        assert_eq!(
+            params.genesis_waypoint,
+            Some(Waypoint::from_str(&waypoint).unwrap())
+        );
+        // The waypoint can't be derived from the blob, it has to be given
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--download-genesis",
+            "--genesis-url",
+            "http://127.0.0.1/genesis.blob",
+        ])
+        .is_err());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/genesis.blob", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request);
+            let _ = socket.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\ngenesis",
+            );
+        });
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(rt.block_on(fetch_genesis(&url)).unwrap(), b"genesis");
+    }
+
+    #[test]
+    pub fn test_write_layout() {
+        let layout = Layout {
+            owners: vec![validator_pod_name(0), validator_pod_name(1)],
+            operators: vec![validator_pod_name(0), validator_pod_name(1)],
+            libra_root: vec![LIBRA_ROOT_NAME.to_string()],
+        };
+        let work_dir = libra_temppath::TempPath::new();
+        work_dir.create_as_dir().unwrap();
+        let paths = BuildPaths::new(work_dir.path());
+        for format in &[LayoutFormat::Toml, LayoutFormat::Yaml, LayoutFormat::Json] {
+            let path = paths.layout(*format);
+            write_layout(&layout, *format, &path).unwrap();
+            assert_eq!(path.extension().unwrap(), format.extension());
+            let contents = fs::read_to_string(&path).unwrap();
+            let owners = match format {
+                LayoutFormat::Toml => toml::from_str::<Layout>(&contents).unwrap().owners,
+                LayoutFormat::Yaml => serde_yaml::from_str::<Layout>(&contents).unwrap().owners,
+                LayoutFormat::Json => serde_json::from_str::<Layout>(&contents).unwrap().owners,
+            };
+            assert_eq!(owners, layout.owners);
+            // The genesis tool picks the format from the extension
+            assert_eq!(Layout::from_disk(&path).unwrap().owners, layout.owners);
+        }
+    }
+}
@@ -0,0 +1,4001 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+mod genesis;
+mod pod_env;
+mod vault;
+
+pub(crate) use self::vault::fullnode_peer_id_name;
+use self::{
+    genesis::{check_validator_set, validator_set_accounts, GenesisArchive},
+    pod_env::{parse_key_val, parse_node_cfg},
+    vault::{
+        fullnode_network_key, fullnode_seed_peer_address, resolve_vault_token,
+        verify_vault_kv_version,
+    },
+};
+pub use self::{
+    genesis::{verify_genesis, GenesisArtifacts, KeySource, VerificationReport},
+    pod_env::{ConfigOverride, ResolvedConfig},
+};
+use crate::{
+    aws,
+    cluster::{network_public_key, Cluster, ClusterInfo, VaultToken},
+    cluster_swarm::{
+        cluster_swarm_federated::FederatedClusterSwarm,
+        cluster_swarm_kube::{
+            AddressSource, ClusterSwarmKube, KubeNode, NodeFilter, DEFAULT_NAMESPACE,
+            FULLNODE_CPU_MILLIS, VALIDATOR_CPU_MILLIS,
+        },
+        ClusterSwarm, Fault, PodPhase, ProvisioningSwarm,
+    },
+    counters,
+    genesis_helper::GenesisHelper,
+    genesis_script::{
+        apply_genesis_accounts, apply_genesis_script, validate_genesis_accounts,
+        verify_genesis_script, GenesisAccount,
+    },
+    instance::{
+        fullnode_pod_name, lsr_replica_pod_name, pod_validator_index, validator_pod_name,
+        vault_index, vault_pod_name,
+        ApplicationConfig::{Fullnode, Validator, Vault, LSR},
+        FullnodeConfig, ImagePullPolicy, Instance, InstanceConfig, LSRConfig, PodMetadata,
+        ReadinessProbe, SafetyRulesServiceType, Toleration, ValidatorConfig, ValidatorGroup,
+        VaultConfig,
+    },
+    spawn_plan::SpawnPlan,
+};
+use anyhow::{bail, format_err, Result};
+use executor::db_bootstrapper;
+use futures::{
+    future::{join_all, try_join_all, Future},
+    stream::{self, StreamExt},
+};
+use libra_logger::{debug, info, warn};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    env, fmt,
+    fs::{self, File},
+    io::Write,
+    iter,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+use libra_config::config::{WaypointConfig, DEFAULT_JSON_RPC_PORT, HANDSHAKE_VERSION};
+use libra_crypto::{
+    ed25519::{Ed25519PrivateKey, Ed25519PublicKey},
+    HashValue,
+};
+use libra_genesis_tool::{
+    layout::{Layout, LayoutFormat},
+    PublishingOption,
+};
+use libra_global_constants::{
+    CONSENSUS_KEY, EXECUTION_KEY, FULLNODE_NETWORK_KEY, LIBRA_ROOT_KEY, OPERATOR_KEY, OWNER_KEY,
+    VALIDATOR_NETWORK_KEY, WAYPOINT,
+};
+use libra_json_rpc_client::{
+    views::{AccountStateWithProofView, BlockMetadata},
+    JsonRpcBatch, ResponseAsView,
+};
+use libra_management::secure_backend::DISK;
+use libra_network_address::NetworkAddress;
+use libra_secure_storage::{
+    CryptoStorage, KVStorage, NamespacedStorage, OnDiskStorage, Storage, Value, VaultStorage,
+};
+use libra_types::{
+    account_address::AccountAddress,
+    account_state::AccountState,
+    account_state_blob::AccountStateBlob,
+    chain_id::ChainId,
+    on_chain_config::{config_address, ValidatorSet},
+    transaction::Transaction,
+    waypoint::Waypoint,
+    PeerId,
+};
+use libra_vm::LibraVM;
+use libradb::LibraDB;
+use rand::Rng;
+use std::str::FromStr;
+use storage_interface::DbReaderWriter;
+use tokio::{sync::Semaphore, task::spawn_blocking};
+
+pub(crate) const VAULT_TOKEN: &str = "root";
+const VAULT_TOKEN_ENV: &str = "LIBRA_VAULT_TOKEN";
+// Directory of the node config in the validator image, genesis_file_location is relative to it
+const NODE_CONFIG_DIR: &str = "/opt/libra/etc";
+pub(crate) const VAULT_PORT: u32 = 8200;
+const LIBRA_ROOT_NAME: &str = "libra";
+const VAULT_BACKEND: &str = "vault";
+const ASG_VERIFY_TIMEOUT: Duration = Duration::from_secs(600);
+const ASG_VERIFY_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const QUORUM_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const VERSION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const VALIDATOR_KEYS: [&str; 6] = [
+    OWNER_KEY,
+    OPERATOR_KEY,
+    CONSENSUS_KEY,
+    EXECUTION_KEY,
+    VALIDATOR_NETWORK_KEY,
+    FULLNODE_NETWORK_KEY,
+];
+
+#[derive(Clone, StructOpt, Debug, PartialEq)]
+pub struct ClusterBuilderParams {
+    #[structopt(long, default_value = "1")]
+    pub fullnodes_per_validator: u32,
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "Config overrides of every validator and fullnode as KEY=VALUE, the value is a toml \
+                value, e.g. prune_window=100 or genesis_file_location=\"genesis.blob\""
+    )]
+    cfg: Vec<ConfigOverride>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        parse(try_from_str = parse_node_cfg),
+        help = "Config override of a single validator or fullnode as POD:KEY=VALUE, e.g. \
+                val-1:prune_window=100, applied on top of --cfg. Can be repeated"
+    )]
+    node_cfg: Vec<(String, ConfigOverride)>,
+    #[structopt(
+        long,
+        use_delimiter = true,
+        parse(try_from_str = parse_key_val),
+        help = "Extra labels added to every pod, e.g. team=libra,env=ci"
+    )]
+    pod_labels: Vec<(String, String)>,
+    #[structopt(
+        long,
+        use_delimiter = true,
+        parse(try_from_str = parse_key_val),
+        help = "Extra annotations added to every pod, e.g. prometheus.io/port=9101"
+    )]
+    pod_annotations: Vec<(String, String)>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        parse(try_from_str = parse_key_val),
+        help = "Environment variable set on validator, fullnode and lsr pods, can be repeated. \
+                The per type variables (--validator-env etc.) take precedence"
+    )]
+    pub pod_env: Vec<(String, String)>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        parse(try_from_str = parse_key_val),
+        help = "Environment variable set on validator pods, can be repeated, e.g. RUST_LOG=debug"
+    )]
+    pub validator_env: Vec<(String, String)>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        parse(try_from_str = parse_key_val),
+        help = "Environment variable set on fullnode pods, can be repeated"
+    )]
+    pub fullnode_env: Vec<(String, String)>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        parse(try_from_str = parse_key_val),
+        help = "Environment variable set on lsr pods, can be repeated"
+    )]
+    pub lsr_env: Vec<(String, String)>,
+    #[structopt(long, parse(try_from_str), default_value = "30")]
+    pub num_validators: u32,
+    #[structopt(long)]
+    pub enable_lsr: Option<bool>,
+    #[structopt(
+        long,
+        help = "Backend used by lsr. Possible Values are in-memory, on-disk, vault",
+        default_value = "vault"
+    )]
+    pub lsr_backend: String,
+    #[structopt(
+        long,
+        help = "How validators run safety rules. Possible values are local, thread, process, \
+                serializer. Defaults to process with LSR and thread without"
+    )]
+    pub safety_rules_service: Option<SafetyRulesServiceType>,
+    #[structopt(
+        long,
+        help = "IP used for seed peers and genesis addresses. Possible Values are internal, external",
+        default_value = "internal"
+    )]
+    pub address_source: AddressSource,
+    #[structopt(
+        long,
+        help = "Number of LSRs spawned for each validator, the validator uses the first reachable \
+                one at startup",
+        default_value = "1"
+    )]
+    pub lsr_replicas_per_validator: u32,
+    #[structopt(
+        long,
+        help = "Number of consecutive validators sharing an LSR, the LSR is set up with the safety \
+                rules data of the first validator of the group. Only useful for tests not \
+                relying on safety rules",
+        default_value = "1",
+        parse(try_from_str = parse_validators_per_lsr)
+    )]
+    pub validators_per_lsr: u32,
+    #[structopt(
+        long,
+        help = "Port the LSRs listen on, validators connect to safety rules on this port",
+        default_value = "6185"
+    )]
+    pub lsr_port: u32,
+    #[structopt(
+        long,
+        help = "Port the fullnodes listen on, also the port of their addresses in genesis. \
+                Validators listen on 6180 and 6181",
+        default_value = "6180",
+        parse(try_from_str = parse_fullnode_port)
+    )]
+    pub fullnode_port: u32,
+    #[structopt(
+        long,
+        help = "Include private keys (mint.key) when archiving genesis artifacts"
+    )]
+    pub include_secrets: bool,
+    #[structopt(
+        long,
+        help = "Keep the instances that spawned successfully instead of tearing down the cluster when some of them fail"
+    )]
+    pub best_effort_spawn: bool,
+    #[structopt(
+        long,
+        help = "Only declare the cluster deployed once a quorum (2f+1) of the validators serves \
+                JSON-RPC and committed past genesis"
+    )]
+    pub require_quorum: bool,
+    #[structopt(
+        long,
+        default_value = "600",
+        help = "Time in seconds --require-quorum waits for the quorum"
+    )]
+    pub quorum_timeout_secs: u64,
+    #[structopt(
+        long,
+        help = "Skip vault initialization and reuse the keys created by a previous run"
+    )]
+    pub skip_vault_init: bool,
+    #[structopt(
+        long,
+        help = "Skip genesis generation and reuse the genesis.blob created by a previous run"
+    )]
+    pub skip_genesis: bool,
+    #[structopt(
+        long,
+        conflicts_with_all = &["skip-genesis", "download-genesis"],
+        help = "Compiled Move script run as libra root on top of the genesis the tool generates, \
+                e.g. to create accounts the tool has no command for. It must take the libra root \
+                &signer as its only argument and is verified before anything is deployed"
+    )]
+    pub extra_genesis_script: Option<PathBuf>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["skip-genesis", "download-genesis"],
+        help = "Account pre-created in the genesis the tool generates and funded with Coin1 by the \
+                testnet designated dealer, as TYPE:ADDRESS:BALANCE:PUBLIC_KEY with TYPE vasp or \
+                dd and BALANCE in microunits. The hex ed25519 PUBLIC_KEY is the key the account \
+                authenticates with and ADDRESS must be the one derived from it. Created before \
+                --extra-genesis-script runs. Can be repeated"
+    )]
+    pub genesis_account: Vec<GenesisAccount>,
+    #[structopt(
+        long,
+        help = "Time in seconds after which the deployment is aborted and torn down"
+    )]
+    pub deploy_timeout: Option<u64>,
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "Indices of the validators whose data is cleaned, the others (and their fullnodes, lsrs and vaults) keep their data"
+    )]
+    pub clean_data_indices: Option<Vec<u32>>,
+    #[structopt(
+        long,
+        help = "Iterate on a running cluster: keep the data of the instances, don't scale the ASG \
+                and don't clean up at startup. The existing pods are replaced on the node they \
+                run on, this assumes a compatible cluster (same number of validators, fullnodes, \
+                lsrs and vaults) is already running"
+    )]
+    pub fast: bool,
+    #[structopt(
+        long,
+        help = "Create a PodDisruptionBudget for the validators of the run so that node \
+                maintenance (e.g. drains during node upgrades) evicts at most one at a time"
+    )]
+    pub create_pdb: bool,
+    #[structopt(
+        long,
+        help = "Pin the deployed tag (e.g. stable) to the digests of the images it currently points to in ECR"
+    )]
+    pub resolve_image_tag: bool,
+    #[structopt(
+        long,
+        help = "Number of retries when allocating a node to a pod",
+        default_value = "15"
+    )]
+    pub allocate_retries: usize,
+    #[structopt(
+        long,
+        help = "Interval in milliseconds between node allocation retries",
+        default_value = "5000"
+    )]
+    pub allocate_retry_interval_ms: u64,
+    #[structopt(
+        long,
+        help = "Create a separate network key in vault for every fullnode instead of sharing the validator's fullnode network key"
+    )]
+    pub fullnode_network_keys: bool,
+    #[structopt(
+        long,
+        help = "Set readiness probes on validator, fullnode and lsr pods so that k8s reports when they are ready"
+    )]
+    pub readiness_probes: bool,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Toleration added to validator pods as key[=value][:effect], e.g. \
+                dedicated=validators:NoSchedule, so they schedule onto tainted nodes. Can be \
+                repeated"
+    )]
+    pub validator_toleration: Vec<Toleration>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Toleration added to fullnode pods as key[=value][:effect]. Can be repeated"
+    )]
+    pub fullnode_toleration: Vec<Toleration>,
+    #[structopt(
+        long,
+        help = "Pull policy of the libra images of validator, fullnode and lsr pods. Possible values \
+                are Always, IfNotPresent, Never. By default the init containers pull if not \
+                present and the main containers always pull"
+    )]
+    pub image_pull_policy: Option<ImagePullPolicy>,
+    #[structopt(
+        long,
+        requires = "genesis-url",
+        help = "Only insert the waypoint into the validators' storage, without copying genesis.blob \
+                to them. The validators download it from --genesis-url when they start"
+    )]
+    pub waypoint_only: bool,
+    #[structopt(
+        long,
+        help = "URL validators fetch genesis.blob from, passed to them as CFG_GENESIS_URL along \
+                with the path to put it at as CFG_GENESIS_PATH"
+    )]
+    pub genesis_url: Option<String>,
+    #[structopt(
+        long,
+        requires_all = &["genesis-url", "genesis-waypoint"],
+        help = "Deploy with the genesis.blob at --genesis-url instead of generating genesis: it is \
+                downloaded once and put on the validators, vault keys are not created and must \
+                be the ones the blob was generated with"
+    )]
+    pub download_genesis: bool,
+    #[structopt(
+        long,
+        help = "Waypoint of the genesis.blob downloaded with --download-genesis, inserted into \
+                the validators' storage"
+    )]
+    pub genesis_waypoint: Option<Waypoint>,
+    #[structopt(
+        long,
+        help = "Put a config snippet setting the genesis waypoint on every fullnode, at \
+                --fullnode-waypoint-path"
+    )]
+    pub waypoint_to_fullnodes: bool,
+    #[structopt(
+        long,
+        help = "Path of the waypoint config snippet put on the fullnodes with --waypoint-to-fullnodes",
+        default_value = "/opt/libra/etc/waypoint.yaml"
+    )]
+    pub fullnode_waypoint_path: String,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_genesis_file_name),
+        help = "Name the genesis blob is put on the validator nodes under, in /opt/libra/etc. It \
+                differs from the genesis.blob the image generates there so that it isn't \
+                overwritten when the node starts",
+        default_value = "genesis2.blob"
+    )]
+    pub genesis_file_name: String,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_genesis_file_name),
+        help = "Name of the genesis file in /opt/libra/etc the validators load, set as their \
+                genesis_file_location. Usually --genesis-file-name, a different name only works \
+                if something in the pod, e.g. an init process, moves the distributed file to it. \
+                If not set, the validator config decides, e.g. through --cfg"
+    )]
+    pub active_genesis_file_name: Option<String>,
+    #[structopt(
+        long,
+        help = "Tag the ASG instances launched for the run with its run id, chain id and image tag"
+    )]
+    pub tag_asg_instances: bool,
+    #[structopt(
+        long,
+        help = "The ASG is managed externally: never resize it, only check that it runs enough \
+                instances for the cluster. The ASG is also kept at its size on teardown"
+    )]
+    pub fixed_asg_size: bool,
+    #[structopt(
+        long,
+        default_value = "10",
+        help = "Consecutive failed AWS calls after which cluster-test stops calling AWS and fails"
+    )]
+    pub aws_failure_threshold: u32,
+    #[structopt(
+        long,
+        default_value = "5000",
+        help = "Delay before the AWS call following a throttled one, doubled for every \
+                consecutive throttled call"
+    )]
+    pub aws_throttle_backoff_ms: u64,
+    #[structopt(
+        long,
+        default_value = "120000",
+        help = "Upper bound of the delay between throttled AWS calls"
+    )]
+    pub aws_max_throttle_backoff_ms: u64,
+    #[structopt(
+        long,
+        default_value = "60000",
+        help = "Time AWS calls fail right away once the circuit breaker opened, after which a \
+                single trial call decides whether it closes again"
+    )]
+    pub aws_open_duration_ms: u64,
+    #[structopt(
+        long,
+        help = "Network mode set in genesis. Possible values are open, permissioned",
+        default_value = "open"
+    )]
+    pub network_mode: NetworkMode,
+    #[structopt(
+        long,
+        help = "Names of the validator owners and operators in genesis. Possible values are pod \
+                (val-0 for both) and role (owner-0 and op-0), vault keys stay under the pod name",
+        default_value = "pod"
+    )]
+    pub genesis_naming: GenesisNaming,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_vault_kv_version),
+        help = "Version of the KV secrets engine mounted at secret/ in vault",
+        default_value = "2"
+    )]
+    pub vault_kv_version: u32,
+    #[structopt(
+        long,
+        help = "Token the builder accesses the vaults with. Visible in the process list, prefer \
+                --vault-token-file or the LIBRA_VAULT_TOKEN environment variable"
+    )]
+    pub vault_token: Option<String>,
+    #[structopt(
+        long,
+        help = "File holding the token the builder accesses the vaults with, takes precedence \
+                over LIBRA_VAULT_TOKEN"
+    )]
+    pub vault_token_file: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Wait for all node allocations and report every failure instead of aborting on the first one"
+    )]
+    pub collect_all_errors: bool,
+    #[structopt(
+        long,
+        help = "Fail before spawning anything if the swarm has fewer schedulable nodes than \
+                instances. Leave unset where node capacity grows on demand"
+    )]
+    pub check_node_capacity: bool,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Shell command run in the main container of every validator and fullnode once it \
+                is spawned, can be repeated to run several commands in order"
+    )]
+    pub post_spawn_hook: Option<Vec<String>>,
+    #[structopt(
+        long,
+        help = "Maximum number of instances the post spawn hook runs on at the same time",
+        default_value = "10"
+    )]
+    pub post_spawn_hook_concurrency: usize,
+    #[structopt(
+        long,
+        help = "Maximum number of validators and fullnodes spawned at the same time, unbounded if not set"
+    )]
+    pub spawn_concurrency: Option<usize>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_max_in_flight_spawns),
+        help = "Maximum number of instances of any kind, vaults and LSRs included, spawned at the \
+                same time across all phases, unbounded if not set"
+    )]
+    pub max_in_flight_spawns: Option<usize>,
+    #[structopt(
+        long,
+        help = "Fail on the params that would only log a warning, e.g. a validator count that is \
+                not 3f + 1"
+    )]
+    pub strict: bool,
+    #[structopt(
+        long,
+        help = "Maximum number of validator vaults initialized or verified at the same time. Each \
+                one holds a thread of the blocking pool for the whole time it talks to its vault",
+        default_value = "32"
+    )]
+    pub vault_init_concurrency: usize,
+    #[structopt(
+        long,
+        help = "Number of validators, taken from the first ones, fullnodes get as seeds on top of \
+                their own validator. Clamped to --num-validators",
+        default_value = "0"
+    )]
+    pub fullnode_seed_count: u32,
+    #[structopt(
+        long,
+        help = "Format of the genesis layout file. Possible values are toml, yaml, json",
+        default_value = "toml"
+    )]
+    pub layout_format: LayoutFormat,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory genesis.blob, the waypoint, the layout and the other genesis artifacts \
+                are written to. Runs sharing a host need distinct directories",
+        default_value = "/tmp"
+    )]
+    pub work_dir: PathBuf,
+    #[structopt(
+        long,
+        help = "Kubernetes namespace validators and their vault and LSR pods are spawned in, the \
+                default namespace if not set"
+    )]
+    pub validator_namespace: Option<String>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Node pods may be allocated to, a node name or a key=value node label, can be \
+                repeated. Any node is allowed if not set"
+    )]
+    pub allow_node: Vec<String>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Node pods are never allocated to, a node name or a key=value node label, can be \
+                repeated. Takes precedence over --allow-node"
+    )]
+    pub deny_node: Vec<String>,
+    #[structopt(
+        long,
+        help = "Allocates pods to nodes one at a time, considering the nodes sorted by name and \
+                shuffled with this seed, so the same params and nodes always give the same pod \
+                to node mapping"
+    )]
+    pub deterministic_placement: Option<u64>,
+    #[structopt(
+        long,
+        help = "Kubernetes namespace fullnodes are spawned in, the default namespace if not set"
+    )]
+    pub fullnode_namespace: Option<String>,
+    #[structopt(
+        long,
+        help = "Number of validators sharing a vault with the vault lsr backend, their keys are \
+                kept apart by the validator's namespace",
+        default_value = "1",
+        parse(try_from_str = parse_validators_per_vault)
+    )]
+    pub validators_per_vault: u32,
+    #[structopt(
+        long = "chain-id",
+        help = "Chain id genesis is generated with, a number from 1 to 255 or a named chain \
+                (MAINNET, TESTNET, DEVNET, TESTING)",
+        default_value = "1",
+        parse(try_from_str = parse_chain_id)
+    )]
+    pub genesis_chain_id: ChainId,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        parse(try_from_str = parse_key_type),
+        help = "Algorithm of a key created in vault as KEY=TYPE, e.g. consensus=ed25519, can be \
+                repeated. Keys that are not listed are ed25519 keys"
+    )]
+    pub key_type: Vec<(String, KeyType)>,
+}
+
+impl ClusterBuilderParams {
+    /// Starts building params programmatically from the command line defaults
+    pub fn builder() -> ClusterBuilderParamsBuilder {
+        ClusterBuilderParamsBuilder::default()
+    }
+
+    /// Whether the instances of the given validator get clean data, `clean_data` applies to all
+    /// validators unless --clean-data-indices is set
+    pub fn clean_data_for(&self, validator_index: u32, clean_data: bool) -> bool {
+        match &self.clean_data_indices {
+            Some(indices) => indices.contains(&validator_index),
+            None => clean_data,
+        }
+    }
+
+    /// Whether vault `vault_index` gets clean data, which is the case as soon as one of the
+    /// validators sharing it does
+    pub fn clean_vault_data_for(&self, vault_index: u32, clean_data: bool) -> bool {
+        let validators_per_vault = self.validators_per_vault.max(1);
+        let first = vault_index * validators_per_vault;
+        (first..(first + validators_per_vault).min(self.num_validators))
+            .any(|validator_index| self.clean_data_for(validator_index, clean_data))
+    }
+
+    pub fn enable_lsr(&self) -> bool {
+        self.enable_lsr.unwrap_or(true)
+    }
+
+    /// Safety rules service of the validators, only the process service uses the LSRs
+    pub fn safety_rules_service(&self) -> SafetyRulesServiceType {
+        self.safety_rules_service.unwrap_or_else(|| {
+            if self.enable_lsr() {
+                SafetyRulesServiceType::Process
+            } else {
+                SafetyRulesServiceType::Thread
+            }
+        })
+    }
+
+    /// Circuit breaker thresholds of the AWS calls
+    pub fn aws_circuit_breaker(&self) -> aws::CircuitBreakerConfig {
+        aws::CircuitBreakerConfig {
+            failure_threshold: self.aws_failure_threshold,
+            throttle_backoff: Duration::from_millis(self.aws_throttle_backoff_ms),
+            max_throttle_backoff: Duration::from_millis(self.aws_max_throttle_backoff_ms),
+            open_duration: Duration::from_millis(self.aws_open_duration_ms),
+        }
+    }
+
+    /// Nodes permitted by --allow-node and --deny-node
+    pub fn node_filter(&self) -> NodeFilter {
+        NodeFilter {
+            allow: self.allow_node.clone(),
+            deny: self.deny_node.clone(),
+        }
+    }
+
+    /// Namespaces set with --validator-namespace and --fullnode-namespace
+    pub fn namespaces(&self) -> Vec<&str> {
+        self.validator_namespace
+            .iter()
+            .chain(self.fullnode_namespace.iter())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Paths of the artifacts written under --work-dir
+    pub fn build_paths(&self) -> BuildPaths {
+        BuildPaths::new(&self.work_dir)
+    }
+
+    /// Chain id genesis is generated with, genesis is only generated with the vault lsr backend
+    pub fn chain_id(&self) -> Option<ChainId> {
+        if self.enable_lsr() && self.lsr_backend == VAULT_BACKEND {
+            Some(self.genesis_chain_id)
+        } else {
+            None
+        }
+    }
+
+    /// Checks for params that are valid but likely unintended, logging a warning for each of
+    /// them, or failing on the first one with --strict
+    pub fn validate(&self) -> Result<()> {
+        if let Some(warning) = validator_count_warning(self.num_validators) {
+            if self.strict {
+                bail!("{}", warning);
+            }
+            warn!("{}", warning);
+        }
+        self.extra_genesis_script()?;
+        validate_genesis_accounts(&self.genesis_account)?;
+        Ok(())
+    }
+
+    /// Bytecode of --extra-genesis-script, verified
+    pub fn extra_genesis_script(&self) -> Result<Option<Vec<u8>>> {
+        self.extra_genesis_script
+            .as_ref()
+            .map(|path| {
+                let code = fs::read(path)
+                    .map_err(|e| format_err!("Failed to read {} : {}", path.display(), e))?;
+                verify_genesis_script(&code)
+                    .map_err(|e| format_err!("Invalid {} : {}", path.display(), e))?;
+                Ok(code)
+            })
+            .transpose()
+    }
+
+    /// Path the genesis blob is put at on the validator nodes
+    pub fn distributed_genesis_path(&self) -> String {
+        format!("{}/{}", NODE_CONFIG_DIR, self.genesis_file_name)
+    }
+
+    /// Token the builder accesses the vaults with, taken from --vault-token, --vault-token-file,
+    /// LIBRA_VAULT_TOKEN or the root token of the dev vaults, in that order
+    pub fn vault_token(&self) -> Result<String> {
+        resolve_vault_token(
+            self.vault_token.as_deref(),
+            self.vault_token_file.as_deref(),
+            env::var(VAULT_TOKEN_ENV).ok(),
+        )
+    }
+
+    /// Algorithm of every key set with --key-type, the last one wins when a key is repeated
+    pub fn key_types(&self) -> HashMap<String, KeyType> {
+        self.key_type.iter().cloned().collect()
+    }
+
+    /// Number of vaults holding the keys of the validators, --validators-per-vault validators
+    /// share each of them
+    pub fn num_vaults(&self) -> u32 {
+        (self.num_validators + self.validators_per_vault - 1) / self.validators_per_vault
+    }
+
+    /// Indices of the validators the LSRs are set up for, the first validator of every group of
+    /// --validators-per-lsr validators
+    pub fn lsr_validators(&self) -> Vec<u32> {
+        (0..self.num_validators)
+            .step_by(self.validators_per_lsr.max(1) as usize)
+            .collect()
+    }
+
+    /// Number of k8s nodes needed to host all pods of the cluster
+    pub fn instance_count(&self) -> u32 {
+        let mut instance_count =
+            self.num_validators + (self.fullnodes_per_validator * self.num_validators);
+        if self.enable_lsr() {
+            instance_count += self.lsr_validators().len() as u32 * self.lsr_replicas_per_validator;
+            if self.lsr_backend == "vault" {
+                instance_count += self.num_vaults();
+            }
+        }
+        instance_count
+    }
+
+    /// Resources requested by all pods of the cluster, useful to estimate its cost
+    pub fn resource_footprint(&self) -> ResourceFootprint {
+        let num_fullnodes = self.fullnodes_per_validator * self.num_validators;
+        ResourceFootprint {
+            cpu_millis: self.num_validators as u64 * VALIDATOR_CPU_MILLIS
+                + num_fullnodes as u64 * FULLNODE_CPU_MILLIS,
+            // None of the pods set memory requests
+            memory_mib: 0,
+            instance_count: self.instance_count(),
+        }
+    }
+}
+
+/// Policy of the network genesis is generated for: open networks accept any script while
+/// permissioned ones only accept the allowlisted stdlib scripts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetworkMode {
+    Open,
+    Permissioned,
+}
+
+impl NetworkMode {
+    pub fn publishing_option(self) -> PublishingOption {
+        match self {
+            NetworkMode::Open => PublishingOption::Open,
+            NetworkMode::Permissioned => PublishingOption::Locked,
+        }
+    }
+}
+
+impl FromStr for NetworkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "open" => Ok(NetworkMode::Open),
+            "permissioned" => Ok(NetworkMode::Permissioned),
+            _ => bail!("Invalid network mode {}, expected open or permissioned", s),
+        }
+    }
+}
+
+/// Names the owners and operators of the validators have in genesis, in the layout and the shared
+/// storage. The keys of the validators are read from the namespace of their pod either way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GenesisNaming {
+    Pod,
+    Role,
+}
+
+impl GenesisNaming {
+    pub fn owner_name(self, validator_index: u32) -> String {
+        match self {
+            GenesisNaming::Pod => validator_pod_name(validator_index),
+            GenesisNaming::Role => format!("owner-{}", validator_index),
+        }
+    }
+
+    pub fn operator_name(self, validator_index: u32) -> String {
+        match self {
+            GenesisNaming::Pod => validator_pod_name(validator_index),
+            GenesisNaming::Role => format!("op-{}", validator_index),
+        }
+    }
+}
+
+impl FromStr for GenesisNaming {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pod" => Ok(GenesisNaming::Pod),
+            "role" => Ok(GenesisNaming::Role),
+            _ => bail!("Invalid genesis naming {}, expected pod or role", s),
+        }
+    }
+}
+
+/// Signature scheme of a key created in secure storage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyType {
+    Ed25519,
+}
+
+impl Default for KeyType {
+    fn default() -> Self {
+        KeyType::Ed25519
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ed25519" => Ok(KeyType::Ed25519),
+            // CryptoStorage::create_key has no algorithm argument, any other scheme needs
+            // support in secure storage first
+            _ => bail!(
+                "Unsupported key type {}, secure storage only creates ed25519 keys",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyType::Ed25519 => write!(f, "ed25519"),
+        }
+    }
+}
+
+/// Chainable setters for ClusterBuilderParams, params that are not set keep the default they
+/// have on the command line
+pub struct ClusterBuilderParamsBuilder {
+    params: ClusterBuilderParams,
+}
+
+impl Default for ClusterBuilderParamsBuilder {
+    fn default() -> Self {
+        Self {
+            params: ClusterBuilderParams::from_iter(vec!["cluster-builder"]),
+        }
+    }
+}
+
+impl ClusterBuilderParamsBuilder {
+    pub fn num_validators(mut self, num_validators: u32) -> Self {
+        self.params.num_validators = num_validators;
+        self
+    }
+
+    pub fn fullnodes_per_validator(mut self, fullnodes_per_validator: u32) -> Self {
+        self.params.fullnodes_per_validator = fullnodes_per_validator;
+        self
+    }
+
+    /// Config overrides of every validator and fullnode, like --cfg
+    pub fn cfg(mut self, cfg: Vec<ConfigOverride>) -> Self {
+        self.params.cfg = cfg;
+        self
+    }
+
+    /// Config override of a single pod, like --node-cfg
+    pub fn node_cfg(mut self, pod_name: &str, config_override: ConfigOverride) -> Self {
+        self.params
+            .node_cfg
+            .push((pod_name.to_string(), config_override));
+        self
+    }
+
+    pub fn enable_lsr(mut self, enable_lsr: bool) -> Self {
+        self.params.enable_lsr = Some(enable_lsr);
+        self
+    }
+
+    pub fn lsr_backend(mut self, lsr_backend: &str) -> Self {
+        self.params.lsr_backend = lsr_backend.to_string();
+        self
+    }
+
+    pub fn lsr_replicas_per_validator(mut self, lsr_replicas_per_validator: u32) -> Self {
+        self.params.lsr_replicas_per_validator = lsr_replicas_per_validator;
+        self
+    }
+
+    pub fn validators_per_lsr(mut self, validators_per_lsr: u32) -> Self {
+        self.params.validators_per_lsr = validators_per_lsr;
+        self
+    }
+
+    pub fn build(self) -> ClusterBuilderParams {
+        self.params
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResourceFootprint {
+    pub cpu_millis: u64,
+    pub memory_mib: u64,
+    pub instance_count: u32,
+}
+
+/// Paths of the files produced while building a cluster, all under one working directory so
+/// concurrent builds on the same host don't overwrite each other
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildPaths {
+    dir: PathBuf,
+}
+
+impl BuildPaths {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn genesis(&self) -> PathBuf {
+        self.dir.join("genesis.blob")
+    }
+
+    /// Shared storage of the genesis tool
+    pub fn genesis_json(&self) -> PathBuf {
+        self.dir.join("genesis.json")
+    }
+
+    pub fn token(&self) -> PathBuf {
+        self.dir.join("token")
+    }
+
+    pub fn mint_key(&self) -> PathBuf {
+        self.dir.join("mint.key")
+    }
+
+    pub fn waypoint(&self) -> PathBuf {
+        self.dir.join("waypoint.txt")
+    }
+
+    /// Path the layout is written to, its extension matches the format so the genesis tool reads
+    /// it back in the same format
+    pub fn layout(&self, format: LayoutFormat) -> PathBuf {
+        self.dir.join(format!("layout.{}", format.extension()))
+    }
+
+    /// On-disk key storage of generate_genesis_only
+    pub fn genesis_only_dir(&self) -> PathBuf {
+        self.dir.join("genesis-only")
+    }
+
+    fn create_dir(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format_err!("Failed to create {} : {}", self.dir.display(), e))
+    }
+}
+
+/// Instances spawned by `spawn_validator_and_fullnode_set`. When some of the spawns failed,
+/// `errors` is non-empty and the instance lists only contain the ones that were created.
+#[derive(Default)]
+pub struct SpawnedInstances {
+    pub validators: Vec<Instance>,
+    pub lsrs: Vec<Instance>,
+    pub vaults: Vec<Instance>,
+    pub fullnodes: Vec<Instance>,
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl SpawnedInstances {
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn all_instances(&self) -> impl Iterator<Item = &Instance> {
+        self.validators
+            .iter()
+            .chain(self.fullnodes.iter())
+            .chain(self.lsrs.iter())
+            .chain(self.vaults.iter())
+    }
+
+    pub fn into_cluster(self) -> Cluster {
+        Cluster::new(self.validators, self.fullnodes, self.lsrs, self.vaults)
+    }
+}
+
+/// Phases of setup_cluster, tracked so that a timed out deployment can report where it hung
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeployPhase {
+    Cleanup,
+    ScaleAsg,
+    SpawnSafetyRules,
+    AllocateNodes,
+    InitVault,
+    Genesis,
+    SpawnNodes,
+    WaitQuorum,
+    Done,
+}
+
+impl fmt::Display for DeployPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DeployPhase::Cleanup => "cleanup",
+            DeployPhase::ScaleAsg => "scale asg",
+            DeployPhase::SpawnSafetyRules => "spawn lsr/vault",
+            DeployPhase::AllocateNodes => "allocate nodes",
+            DeployPhase::InitVault => "initialize vault",
+            DeployPhase::Genesis => "genesis",
+            DeployPhase::SpawnNodes => "spawn validators and fullnodes",
+            DeployPhase::WaitQuorum => "wait for quorum",
+            DeployPhase::Done => "done",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Returned by setup_cluster when the deployment does not complete within --deploy-timeout
+#[derive(Debug)]
+pub struct DeployTimeout {
+    pub timeout: Duration,
+    pub phase: DeployPhase,
+}
+
+impl fmt::Display for DeployTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Timeout: deployment did not complete in {:?}, phase in progress: {}",
+            self.timeout, self.phase
+        )
+    }
+}
+
+impl std::error::Error for DeployTimeout {}
+
+/// Notified by ClusterBuilder as setup_cluster moves through its phases, e.g. to post progress
+/// or record timings
+pub trait DeploymentObserver: Send + Sync {
+    fn on_phase_start(&self, _phase: DeployPhase) {}
+
+    fn on_phase_end(&self, _phase: DeployPhase, _duration: Duration) {}
+}
+
+/// Moves `state` to `phase`, ending the phase in progress (if any) on the observer
+fn transition_deploy_phase(
+    state: &Mutex<(DeployPhase, Instant)>,
+    observer: Option<&dyn DeploymentObserver>,
+    phase: DeployPhase,
+) {
+    let (previous, started) = {
+        let mut state = state.lock().expect("deploy_phase lock poisoned");
+        std::mem::replace(&mut *state, (phase, Instant::now()))
+    };
+    if let Some(observer) = observer {
+        if previous != DeployPhase::Done {
+            observer.on_phase_end(previous, started.elapsed());
+        }
+        if phase != DeployPhase::Done {
+            observer.on_phase_start(phase);
+        }
+    }
+}
+
+/// ECR repositories of the libra images the pods of a cluster run
+pub const LIBRA_IMAGE_REPOSITORIES: &[&str] =
+    &["libra_init", "libra_validator", "libra_safety_rules"];
+
+/// The images a (possibly symbolic) tag pointed to when it was resolved
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolvedImage {
+    pub tag: String,
+    /// Digest of the image of `tag` in each repository, the pods are pinned to these digests
+    /// instead of the tag. Empty if the tag was not resolved.
+    pub digests: BTreeMap<String, String>,
+}
+
+/// Resolves the tag passed to ClusterBuilder to the images every instance is deployed with
+#[async_trait::async_trait]
+pub trait ImageResolver: Send + Sync {
+    async fn resolve(&self, tag: &str) -> Result<ResolvedImage>;
+}
+
+/// Looks the tag up in each of the LIBRA_IMAGE_REPOSITORIES, so that a moving tag can't change
+/// the images mid-deploy
+pub struct EcrImageResolver;
+
+#[async_trait::async_trait]
+impl ImageResolver for EcrImageResolver {
+    async fn resolve(&self, tag: &str) -> Result<ResolvedImage> {
+        let digests = try_join_all(
+            LIBRA_IMAGE_REPOSITORIES
+                .iter()
+                .map(|repository| async move {
+                    aws::image_digest(repository, tag)
+                        .await
+                        .map(|digest| (repository.to_string(), digest))
+                }),
+        )
+        .await?;
+        Ok(ResolvedImage {
+            tag: tag.to_string(),
+            digests: digests.into_iter().collect(),
+        })
+    }
+}
+
+pub struct ClusterBuilder {
+    pub current_tag: String,
+    pub cluster_swarm: Arc<dyn ProvisioningSwarm>,
+    deploy_phase: Arc<Mutex<(DeployPhase, Instant)>>,
+    observer: Option<Arc<dyn DeploymentObserver>>,
+    image_resolver: Option<Arc<dyn ImageResolver>>,
+    // Image current_tag was last resolved to, instances running its tag are pinned to it
+    resolved_image: Arc<Mutex<Option<ResolvedImage>>>,
+    // Set by prewarm, the next setup_cluster then skips scaling the ASG
+    asg_prewarmed: Arc<AtomicBool>,
+    // Hash of the genesis blob last copied to each node, by node name and path. Entries are
+    // dropped once the file may be gone: on cleanup, ASG rescale and clean data
+    distributed_genesis: Arc<Mutex<HashMap<(String, String), HashValue>>>,
+    // Set once setup_cluster has produced or reused a genesis
+    genesis_archive: Arc<Mutex<Option<GenesisArchive>>>,
+}
+
+impl ClusterBuilder {
+    pub fn new(current_tag: String, cluster_swarm: impl ProvisioningSwarm + 'static) -> Self {
+        Self::shared(current_tag, Arc::new(cluster_swarm))
+    }
+
+    /// Like new, with a swarm the caller keeps using
+    pub fn shared(current_tag: String, cluster_swarm: Arc<dyn ProvisioningSwarm>) -> Self {
+        Self {
+            current_tag,
+            cluster_swarm,
+            deploy_phase: Arc::new(Mutex::new((DeployPhase::Done, Instant::now()))),
+            observer: None,
+            image_resolver: None,
+            resolved_image: Arc::new(Mutex::new(None)),
+            asg_prewarmed: Arc::new(AtomicBool::new(false)),
+            distributed_genesis: Arc::new(Mutex::new(HashMap::new())),
+            genesis_archive: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Builder spreading the clusters it sets up over several k8s clusters, one swarm each, see
+    /// FederatedClusterSwarm
+    pub fn federated(current_tag: String, cluster_swarms: Vec<ClusterSwarmKube>) -> Result<Self> {
+        Ok(Self::new(
+            current_tag,
+            FederatedClusterSwarm::new(cluster_swarms)?,
+        ))
+    }
+
+    /// Registers an observer notified of every phase of setup_cluster
+    pub fn with_observer(mut self, observer: Arc<dyn DeploymentObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Overrides how current_tag is resolved, by default it is only resolved with
+    /// EcrImageResolver if --resolve-image-tag is set
+    pub fn with_image_resolver(mut self, image_resolver: Arc<dyn ImageResolver>) -> Self {
+        self.image_resolver = Some(image_resolver);
+        self
+    }
+
+    /// Resolves current_tag, the instances spawned with it from then on are pinned to the
+    /// resolved images
+    async fn resolve_image(&self, params: &ClusterBuilderParams) -> Result<ResolvedImage> {
+        let resolved = match &self.image_resolver {
+            Some(resolver) => resolver.resolve(&self.current_tag).await?,
+            None if params.resolve_image_tag => EcrImageResolver.resolve(&self.current_tag).await?,
+            None => ResolvedImage {
+                tag: self.current_tag.clone(),
+                digests: BTreeMap::new(),
+            },
+        };
+        if !resolved.digests.is_empty() {
+            info!("Resolved tag {} to {:?}", resolved.tag, resolved.digests);
+        }
+        *self
+            .resolved_image
+            .lock()
+            .expect("resolved_image lock poisoned") = Some(resolved.clone());
+        Ok(resolved)
+    }
+
+    /// Digests the libra images of pods running `image_tag` are pinned to, by repository. Only
+    /// the tag last resolved is pinned, other tags are deployed as is.
+    fn image_digests(&self, image_tag: &str) -> BTreeMap<String, String> {
+        match &*self
+            .resolved_image
+            .lock()
+            .expect("resolved_image lock poisoned")
+        {
+            Some(resolved) if resolved.tag == image_tag => resolved.digests.clone(),
+            _ => BTreeMap::new(),
+        }
+    }
+
+    async fn asg_name(&self) -> String {
+        format!(
+            "{}-k8s-testnet-validators",
+            self.cluster_swarm
+                .get_workspace()
+                .await
+                .expect("Failed to get workspace")
+        )
+    }
+
+    /// Scales the ASG up to the size needed by `params` without allocating pods or generating
+    /// genesis, so that the next setup_cluster doesn't have to wait for instances to come up
+    pub async fn prewarm(&self, params: &ClusterBuilderParams) -> Result<()> {
+        aws::configure_circuit_breaker(params.aws_circuit_breaker());
+        let asg_name = self.asg_name().await;
+        if params.fixed_asg_size {
+            return check_fixed_asg_size(&asg_name, params.instance_count(), || {
+                aws::get_asg_running_count(&asg_name)
+            })
+            .await;
+        }
+        prewarm_asg(
+            &asg_name,
+            params.instance_count(),
+            |count, buffer_percent, scaling_down| {
+                aws::set_asg_size(count, buffer_percent, &asg_name, true, scaling_down)
+            },
+        )
+        .await?;
+        self.forget_distributed_genesis(None);
+        self.asg_prewarmed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn deploy_phase(&self) -> DeployPhase {
+        self.deploy_phase
+            .lock()
+            .expect("deploy_phase lock poisoned")
+            .0
+    }
+
+    fn set_deploy_phase(&self, phase: DeployPhase) {
+        transition_deploy_phase(&self.deploy_phase, self.observer.as_deref(), phase);
+    }
+
+    /// Wipes the libra data on `node`, genesis blobs included
+    async fn clean_node_data(&self, node: &str) -> Result<()> {
+        self.cluster_swarm.clean_data(node).await?;
+        self.forget_distributed_genesis(Some(node));
+        Ok(())
+    }
+
+    pub async fn setup_cluster(
+        &self,
+        params: &ClusterBuilderParams,
+        clean_data: bool,
+    ) -> Result<Cluster> {
+        let timeout = match params.deploy_timeout {
+            Some(secs) => Duration::from_secs(secs),
+            None => return self.setup_cluster_impl(params, clean_data).await,
+        };
+        match tokio::time::timeout(timeout, self.setup_cluster_impl(params, clean_data)).await {
+            Ok(result) => result,
+            Err(_) => {
+                let phase = self.deploy_phase();
+                warn!(
+                    "Deployment timed out after {:?} during {}, tearing down",
+                    timeout, phase
+                );
+                if let Err(e) = self.cluster_swarm.cleanup().await {
+                    warn!("cleanup after deployment timeout failed: {}", e);
+                }
+                self.forget_distributed_genesis(None);
+                Err(DeployTimeout { timeout, phase }.into())
+            }
+        }
+    }
+
+    async fn setup_cluster_impl(
+        &self,
+        params: &ClusterBuilderParams,
+        clean_data: bool,
+    ) -> Result<Cluster> {
+        params.validate()?;
+        let effective_configs = params.effective_configs()?;
+        let mut pod_names: Vec<_> = effective_configs.keys().collect();
+        pod_names.sort();
+        for pod_name in pod_names {
+            debug!(
+                "{} config overrides: {}",
+                pod_name, effective_configs[pod_name]
+            );
+        }
+        self.set_deploy_phase(DeployPhase::Cleanup);
+        // Leftovers of a previous run in the configured namespaces are cleaned up too
+        for namespace in params.namespaces() {
+            self.cluster_swarm.register_namespace(namespace).await;
+        }
+        self.cluster_swarm
+            .set_node_filter(params.node_filter())
+            .await;
+        self.cluster_swarm
+            .set_placement_seed(params.deterministic_placement)
+            .await;
+        aws::configure_circuit_breaker(params.aws_circuit_breaker());
+        // Without clean data the ASG is not scaled either
+        let clean_data = clean_data && !params.fast;
+        if params.fast {
+            let adopted = self
+                .cluster_swarm
+                .adopt_pods()
+                .await
+                .map_err(|e| format_err!("Failed to adopt existing pods: {}", e))?;
+            info!(
+                "Fast mode, skipping cleanup and reusing the nodes of {} existing pods",
+                adopted.len()
+            );
+        } else {
+            self.cluster_swarm
+                .cleanup()
+                .await
+                .map_err(|e| format_err!("cleanup on startup failed: {}", e))?;
+            self.forget_distributed_genesis(None);
+        }
+        // Resolved once so that every instance runs the same image even if the tag moves
+        let resolved_image = self
+            .resolve_image(params)
+            .await
+            .map_err(|e| format_err!("Failed to resolve image tag {}: {}", self.current_tag, e))?;
+        let current_tag = &resolved_image.tag;
+        info!(
+            "Deploying with {} tag for validators and fullnodes",
+            current_tag
+        );
+        let asg_name = self.asg_name().await;
+        let instance_count = params.instance_count();
+        if self.asg_prewarmed.swap(false, Ordering::SeqCst) {
+            info!("{} was prewarmed, skipping scaling", asg_name);
+        } else if params.fixed_asg_size {
+            check_fixed_asg_size(&asg_name, instance_count, || {
+                aws::get_asg_running_count(&asg_name)
+            })
+            .await?;
+        } else if clean_data && params.clean_data_indices.is_none() {
+            // Scaling the ASG replaces all instances, which would wipe the data of the validators
+            // that are meant to keep it
+            self.set_deploy_phase(DeployPhase::ScaleAsg);
+            // The instances are replaced, along with the genesis blobs they held
+            self.forget_distributed_genesis(None);
+            // First scale down to zero instances and wait for it to complete so that we don't schedule pods on
+            // instances which are going into termination state
+            aws::set_asg_size(0, 0.0, &asg_name, true, true)
+                .await
+                .map_err(|err| format_err!("{} scale down failed: {}", asg_name, err))?;
+            // Tags only propagate to instances launched after they are set, so tag before scaling up
+            if params.tag_asg_instances {
+                let tags = run_tags(
+                    env::var("RUN_ID").ok().as_deref(),
+                    params.chain_id(),
+                    current_tag,
+                );
+                aws::tag_asg_instances(&asg_name, &tags)
+                    .await
+                    .map_err(|err| format_err!("{} tagging failed: {}", asg_name, err))?;
+            }
+            // Then scale up and bring up new instances
+            aws::set_asg_size(instance_count as i64, 5.0, &asg_name, true, false)
+                .await
+                .map_err(|err| format_err!("{} scale up failed: {}", asg_name, err))?;
+            // Instances can stay pending indefinitely (e.g. capacity issues) even though the scale
+            // up reported success, so bounce the ASG once if they never show up
+            ensure_asg_size(
+                &asg_name,
+                instance_count as i64,
+                ASG_VERIFY_TIMEOUT,
+                ASG_VERIFY_POLL_INTERVAL,
+                || aws::get_asg_running_count(&asg_name),
+                || async {
+                    aws::set_asg_size(0, 0.0, &asg_name, true, true).await?;
+                    aws::set_asg_size(instance_count as i64, 5.0, &asg_name, true, false).await
+                },
+            )
+            .await?;
+        }
+        if params.check_node_capacity {
+            check_node_capacity(&*self.cluster_swarm, instance_count).await?;
+        }
+        let spawned = self
+            .spawn_validator_and_fullnode_set(params, current_tag, clean_data)
+            .await
+            .map_err(|e| format_err!("Failed to spawn_validator_and_fullnode_set: {}", e))?;
+        if !spawned.is_complete() {
+            for e in spawned.errors.iter() {
+                warn!("Failed to spawn instance: {}", e);
+            }
+            if !params.best_effort_spawn {
+                let num_errors = spawned.errors.len();
+                self.teardown_instances(spawned.all_instances()).await;
+                if params.collect_all_errors {
+                    bail!(
+                        "Failed to spawn_validator_and_fullnode_set: {}",
+                        combined_error(&spawned.errors)
+                    );
+                }
+                bail!(
+                    "Failed to spawn_validator_and_fullnode_set: {} instances failed, first error: {}",
+                    num_errors,
+                    spawned.errors[0]
+                );
+            }
+        }
+        if let Some(commands) = &params.post_spawn_hook {
+            let instances = spawned
+                .validators
+                .iter()
+                .chain(spawned.fullnodes.iter())
+                .collect();
+            run_post_spawn_hook(
+                instances,
+                commands,
+                params.post_spawn_hook_concurrency,
+                |instance, command| async move {
+                    // The container may still be starting, exec fails until it runs
+                    libra_retrier::retry_async(
+                        libra_retrier::fixed_retry_strategy(5000, 24),
+                        || Box::pin(async move { instance.exec(command, false).await }),
+                    )
+                    .await
+                },
+            )
+            .await?;
+        }
+        if params.require_quorum {
+            self.set_deploy_phase(DeployPhase::WaitQuorum);
+            let validators = &spawned.validators;
+            wait_for_quorum(
+                params.num_validators,
+                Duration::from_secs(params.quorum_timeout_secs),
+                QUORUM_POLL_INTERVAL,
+                || async move {
+                    join_all(validators.iter().map(is_live_validator))
+                        .await
+                        .into_iter()
+                        .filter(|live| *live)
+                        .count() as u32
+                },
+            )
+            .await?;
+        }
+        if params.create_pdb {
+            let namespace = params
+                .validator_namespace
+                .as_deref()
+                .unwrap_or(DEFAULT_NAMESPACE);
+            self.cluster_swarm
+                .create_validator_pdb(namespace, 1)
+                .await
+                .map_err(|e| format_err!("Failed to create validator PDB: {}", e))?;
+        }
+        let vault_genesis = params.chain_id().is_some();
+        let cluster = spawned.into_cluster().with_info(ClusterInfo {
+            chain_id: params.chain_id(),
+            lsr_backend: if params.enable_lsr() {
+                Some(params.lsr_backend.clone())
+            } else {
+                None
+            },
+            image_tag: Some(current_tag.to_string()),
+            image_digest: resolved_image.digests.get("libra_validator").cloned(),
+            waypoint: if vault_genesis {
+                fs::read_to_string(params.build_paths().waypoint())
+                    .ok()
+                    .and_then(|waypoint| Waypoint::from_str(waypoint.trim()).ok())
+            } else {
+                None
+            },
+            run_id: env::var("RUN_ID").ok(),
+            validators_per_vault: if vault_genesis {
+                Some(params.validators_per_vault)
+            } else {
+                None
+            },
+            // Read back like the waypoint so that --skip-genesis reports the reused layout
+            layout: if vault_genesis {
+                Layout::from_disk(params.build_paths().layout(params.layout_format)).ok()
+            } else {
+                None
+            },
+            vault_token: Some(VaultToken::new(params.vault_token()?)),
+        });
+        self.set_deploy_phase(DeployPhase::Done);
+        counters::CLUSTERS_CREATED.inc();
+
+        info!("{}", cluster.summary());
+        Ok(cluster)
+    }
+
+    async fn allocate_node(
+        &self,
+        params: &ClusterBuilderParams,
+        pod_name: &str,
+    ) -> Result<KubeNode> {
+        self.cluster_swarm
+            .allocate_node(
+                pod_name,
+                params.allocate_retries,
+                params.allocate_retry_interval_ms,
+            )
+            .await
+            .map_err(|e| format_err!("Failed to allocate node for {} : {}", pod_name, e))
+    }
+
+    /// Deletes the pods of the cluster that ended up in the Failed phase, leaving the others
+    /// alone so that only the broken instances need to be spawned again. Returns the deleted pods
+    pub async fn cleanup_failed(
+        cluster: &Cluster,
+        swarm: &(impl ClusterSwarm + ?Sized),
+    ) -> Result<Vec<String>> {
+        let phases = try_join_all(cluster.all_instances().map(|instance| async move {
+            swarm
+                .get_pod_phase(instance.peer_name())
+                .await
+                .map(|phase| (instance.peer_name().clone(), phase))
+        }))
+        .await?;
+        let failed: Vec<_> = phases
+            .into_iter()
+            .filter(|(_, phase)| *phase == PodPhase::Failed)
+            .map(|(pod_name, _)| pod_name)
+            .collect();
+        for pod_name in failed.iter() {
+            info!("Deleting failed pod {}", pod_name);
+            swarm
+                .delete_pod(pod_name)
+                .await
+                .map_err(|e| format_err!("Failed to delete failed pod {} : {}", pod_name, e))?;
+        }
+        Ok(failed)
+    }
+
+    /// Replaces the validator `index` of the cluster with one running `new_image_tag`, keeping
+    /// its validator group and node so that rolling upgrades can go one validator at a time.
+    /// The data of the node is kept unless `clean_data` is set. Returns the new instance
+    pub async fn replace_instance(
+        swarm: &(impl ClusterSwarm + ?Sized),
+        cluster: &Cluster,
+        index: u32,
+        new_image_tag: &str,
+        clean_data: bool,
+    ) -> Result<Instance> {
+        let pod_name = validator_pod_name(index);
+        let instance = cluster
+            .get_validator_instance(&pod_name)
+            .ok_or_else(|| format_err!("{} is not part of the cluster", pod_name))?;
+        let node = instance
+            .k8s_node()
+            .ok_or_else(|| format_err!("Can not replace {}, it is not a k8s instance", pod_name))?;
+        Self::replace_pod(
+            swarm,
+            instance.instance_config(),
+            node,
+            new_image_tag,
+            clean_data,
+        )
+        .await
+    }
+
+    async fn replace_pod(
+        swarm: &(impl ClusterSwarm + ?Sized),
+        instance_config: &InstanceConfig,
+        node: &str,
+        new_image_tag: &str,
+        clean_data: bool,
+    ) -> Result<Instance> {
+        let mut instance_config = instance_config.clone();
+        instance_config.replace_tag(new_image_tag.to_string())?;
+        let pod_name = instance_config.pod_name();
+        info!("Replacing {} with image tag {}", pod_name, new_image_tag);
+        swarm
+            .delete_pod(&pod_name)
+            .await
+            .map_err(|e| format_err!("Failed to delete pod {} : {}", pod_name, e))?;
+        if clean_data {
+            swarm.clean_data(node).await?;
+        }
+        swarm
+            .spawn_new_instance(instance_config)
+            .await
+            .map_err(|e| format_err!("Failed to spawn replacement of {} : {}", pod_name, e))
+    }
+
+    /// Waits until every validator of the cluster committed at least version `target`, e.g. as a
+    /// checkpoint tests synchronize on. Fails with the validators lagging behind and the version
+    /// they reached if that doesn't happen within `timeout`
+    pub async fn wait_for_version(
+        &self,
+        cluster: &Cluster,
+        target: u64,
+        timeout: Duration,
+    ) -> Result<()> {
+        wait_for_versions(
+            cluster.validator_instances(),
+            target,
+            timeout,
+            VERSION_POLL_INTERVAL,
+            committed_version,
+        )
+        .await
+    }
+
+    /// Checks that the validator set on chain, as seen by the first reachable validator, is made
+    /// of the owner accounts of the validators the genesis was generated for
+    pub async fn verify_validator_set(&self, cluster: &Cluster) -> Result<()> {
+        if cluster.validator_instances().is_empty() {
+            bail!("Can not verify the validator set of a cluster without validators");
+        }
+        let owners_cluster = cluster.clone();
+        let expected =
+            tokio::task::spawn_blocking(move || owners_cluster.validator_owner_accounts())
+                .await??;
+        let mut errors = vec![];
+        for validator in cluster.validator_instances() {
+            match on_chain_validator_set(validator).await {
+                Ok(on_chain) => return check_validator_set(&expected, &on_chain),
+                Err(e) => {
+                    warn!("{}, trying the next validator", e);
+                    errors.push(e);
+                }
+            }
+        }
+        bail!(
+            "No validator could serve the validator set: {}",
+            combined_error(&errors)
+        )
+    }
+
+    /// Partitions the network between the two groups of instances, each instance drops the
+    /// traffic from and to the instances of the other group
+    pub async fn partition(
+        swarm: &(impl ClusterSwarm + ?Sized),
+        group_a: &[Instance],
+        group_b: &[Instance],
+    ) -> Result<()> {
+        let ips = |group: &[Instance]| -> Vec<String> {
+            group.iter().map(|instance| instance.ip().clone()).collect()
+        };
+        let faults = group_a
+            .iter()
+            .map(|instance| (instance, ips(group_b)))
+            .chain(group_b.iter().map(|instance| (instance, ips(group_a))));
+        try_join_all(faults.map(|(instance, ips)| {
+            swarm.inject_fault(instance.peer_name(), Fault::NetworkPartition(ips))
+        }))
+        .await?;
+        Ok(())
+    }
+
+    /// Injects `fault` into each of the instances
+    pub async fn inject_fault(
+        swarm: &(impl ClusterSwarm + ?Sized),
+        instances: &[Instance],
+        fault: Fault,
+    ) -> Result<()> {
+        try_join_all(
+            instances
+                .iter()
+                .map(|instance| swarm.inject_fault(instance.peer_name(), fault.clone())),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Stops the given instances, logging the ones that could not be stopped
+    async fn teardown_instances<'a>(&self, instances: impl Iterator<Item = &'a Instance>) {
+        for result in join_all(instances.map(|instance| async move {
+            instance
+                .stop()
+                .await
+                .map_err(|e| format_err!("Failed to stop {} : {}", instance, e))
+        }))
+        .await
+        {
+            if let Err(e) = result {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    /// Creates a set of validators and fullnodes with the given parameters.
+    /// Failures to spawn individual instances do not abort the whole set, instead they are
+    /// collected into `SpawnedInstances::errors` together with the instances that were created.
+    pub async fn spawn_validator_and_fullnode_set(
+        &self,
+        params: &ClusterBuilderParams,
+        image_tag: &str,
+        clean_data: bool,
+    ) -> Result<SpawnedInstances> {
+        let num_validators = params.num_validators;
+        let enable_lsr = params.enable_lsr();
+        let lsr_replicas_per_validator = params.lsr_replicas_per_validator;
+        let lsr_port = params.lsr_port;
+        let lsr_backend = params.lsr_backend.as_str();
+        let pod_metadata = params.pod_metadata();
+        let pod_metadata = &pod_metadata;
+        // Checked before spawning the LSRs, which are useless to validators not using them
+        params.safety_rules_service().validate(enable_lsr)?;
+        let spawn_limit = params.max_in_flight_spawns.map(Semaphore::new);
+        let spawn_limit = spawn_limit.as_ref();
+        let vault_nodes;
+        let mut lsrs_nodes = vec![];
+        let mut vaults = vec![];
+        let mut lsrs = vec![];
+
+        if enable_lsr {
+            self.set_deploy_phase(DeployPhase::SpawnSafetyRules);
+            let vault_token = &VaultToken::new(params.vault_token()?);
+            if lsr_backend == "vault" {
+                vault_nodes = join_allocations(
+                    (0..params.num_vaults()).map(|i| async move {
+                        let pod_name = vault_pod_name(i);
+                        self.allocate_node(params, &pod_name).await
+                    }),
+                    params.collect_all_errors,
+                    params.deterministic_placement.is_some(),
+                )
+                .await?;
+                let mut vault_instances: Vec<_> = vault_nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, node)| async move {
+                        let vault_config = VaultConfig {};
+                        if params.clean_vault_data_for(i as u32, clean_data) {
+                            self.clean_node_data(&node.name).await?;
+                        }
+                        spawn_limited(
+                            &*self.cluster_swarm,
+                            spawn_limit,
+                            InstanceConfig {
+                                validator_group: ValidatorGroup::new_for_index(i as u32),
+                                application_config: Vault(vault_config),
+                                pod_metadata: pod_metadata.clone(),
+                                env: vec![],
+                                namespace: params.validator_namespace.clone(),
+                                // The vault image is not built with the cluster
+                                image_pull_policy: None,
+                                image_digests: BTreeMap::new(),
+                            },
+                        )
+                        .await
+                    })
+                    .collect();
+                vaults.append(&mut vault_instances);
+            } else {
+                vault_nodes = vec![];
+            }
+            let lsr_validators = params.lsr_validators();
+            lsrs_nodes = join_allocations(
+                lsr_validators.iter().copied().flat_map(move |i| {
+                    (0..lsr_replicas_per_validator).map(move |replica_index| async move {
+                        let pod_name = lsr_replica_pod_name(i, replica_index);
+                        self.allocate_node(params, &pod_name).await
+                    })
+                }),
+                params.collect_all_errors,
+                params.deterministic_placement.is_some(),
+            )
+            .await?;
+            let mut lsr_instances: Vec<_> = lsrs_nodes
+                .iter()
+                .enumerate()
+                .map(|(i, node)| async move {
+                    let i = i as u32;
+                    let validator_index =
+                        (i / lsr_replicas_per_validator) * params.validators_per_lsr;
+                    let lsr_config = LSRConfig {
+                        replica_index: i % lsr_replicas_per_validator,
+                        port: lsr_port,
+                        num_validators,
+                        validators_per_vault: params.validators_per_vault,
+                        validators_per_lsr: params.validators_per_lsr,
+                        image_tag: image_tag.to_string(),
+                        lsr_backend: lsr_backend.to_string(),
+                        vault_token: vault_token.clone(),
+                        readiness_probe: if params.readiness_probes {
+                            Some(ReadinessProbe::tcp(lsr_port))
+                        } else {
+                            None
+                        },
+                    };
+                    if params.clean_data_for(validator_index, clean_data) {
+                        self.clean_node_data(&node.name).await?;
+                    }
+                    spawn_limited(
+                        &*self.cluster_swarm,
+                        spawn_limit,
+                        InstanceConfig {
+                            validator_group: ValidatorGroup::new_for_index(validator_index),
+                            application_config: LSR(lsr_config),
+                            pod_metadata: pod_metadata.clone(),
+                            env: params.lsr_pod_env(),
+                            namespace: params.validator_namespace.clone(),
+                            image_pull_policy: params.image_pull_policy,
+                            image_digests: self.image_digests(image_tag),
+                        },
+                    )
+                    .await
+                })
+                .collect();
+            lsrs.append(&mut lsr_instances);
+        } else {
+            vault_nodes = vec![];
+        }
+
+        let mut spawned = SpawnedInstances::default();
+        spawned.lsrs = collect_spawn_results(join_all(lsrs).await, &mut spawned.errors);
+        spawned.vaults = collect_spawn_results(join_all(vaults).await, &mut spawned.errors);
+        if !spawned.is_complete() {
+            return Ok(spawned);
+        }
+
+        if let Err(e) = self
+            .spawn_validators_and_fullnodes(
+                &mut spawned,
+                params,
+                image_tag,
+                clean_data,
+                &vault_nodes,
+                &lsrs_nodes,
+                spawn_limit,
+            )
+            .await
+        {
+            spawned.errors.push(e);
+        }
+        counters::SPAWN_FAILURES.inc_by(spawned.errors.len() as i64);
+        Ok(spawned)
+    }
+
+    async fn spawn_validators_and_fullnodes(
+        &self,
+        spawned: &mut SpawnedInstances,
+        params: &ClusterBuilderParams,
+        image_tag: &str,
+        clean_data: bool,
+        vault_nodes: &[KubeNode],
+        lsrs_nodes: &[KubeNode],
+        spawn_limit: Option<&Semaphore>,
+    ) -> Result<()> {
+        let num_validators = params.num_validators;
+        let num_fullnodes_per_validator = params.fullnodes_per_validator;
+        let enable_lsr = params.enable_lsr();
+        let lsr_replicas_per_validator = params.lsr_replicas_per_validator;
+        let address_source = params.address_source;
+        let pod_metadata = params.pod_metadata();
+        let pod_metadata = &pod_metadata;
+        let fullnode_network_keys = params.fullnode_network_keys;
+        if fullnode_network_keys && vault_nodes.is_empty() {
+            bail!("--fullnode-network-keys requires the vault lsr backend");
+        }
+        if params.download_genesis && vault_nodes.is_empty() {
+            bail!("--download-genesis requires the vault lsr backend");
+        }
+        if params.waypoint_to_fullnodes && vault_nodes.is_empty() {
+            bail!("--waypoint-to-fullnodes requires the vault lsr backend");
+        }
+        let vault_token = params.vault_token()?;
+        let vault_token = vault_token.as_str();
+        // Vault node of every validator, validators sharing a vault get the same node
+        let validator_vaults: Vec<_> = if vault_nodes.is_empty() {
+            vec![]
+        } else {
+            (0..num_validators)
+                .map(|i| vault_nodes[vault_index(i, params.validators_per_vault) as usize].clone())
+                .collect()
+        };
+        let num_fullnode_keys = if fullnode_network_keys {
+            num_fullnodes_per_validator
+        } else {
+            0
+        };
+        self.set_deploy_phase(DeployPhase::AllocateNodes);
+        let validator_nodes = join_allocations(
+            (0..num_validators).map(|i| async move {
+                let pod_name = validator_pod_name(i);
+                self.allocate_node(params, &pod_name).await
+            }),
+            params.collect_all_errors,
+            params.deterministic_placement.is_some(),
+        )
+        .await?;
+
+        let fullnode_nodes = join_allocations(
+            (0..num_validators).flat_map(move |validator_index| {
+                (0..num_fullnodes_per_validator).map(move |fullnode_index| async move {
+                    let pod_name = fullnode_pod_name(validator_index, fullnode_index);
+                    self.allocate_node(params, &pod_name).await
+                })
+            }),
+            params.collect_all_errors,
+            params.deterministic_placement.is_some(),
+        )
+        .await?;
+
+        if !vault_nodes.is_empty() {
+            self.set_deploy_phase(DeployPhase::InitVault);
+            try_join_all(
+                vault_nodes.iter().map(|node| {
+                    verify_vault_kv_version(node, params.vault_kv_version, vault_token)
+                }),
+            )
+            .await?;
+            if params.skip_vault_init || params.download_genesis {
+                info!("Skipping vault initialization, verifying existing keys.");
+                try_join_bounded(
+                    validator_vaults.iter().enumerate().map(|(i, node)| {
+                        self.verify_vault_keys(i as u32, node, num_fullnode_keys, vault_token)
+                    }),
+                    params.vault_init_concurrency,
+                )
+                .await?;
+            } else {
+                try_join_bounded(
+                    validator_vaults
+                        .iter()
+                        .enumerate()
+                        .map(|(i, node)| async move {
+                            libra_retrier::retry_async(
+                                jittered_retry_strategy(5000, 2500, 15),
+                                || {
+                                    Box::pin(async move {
+                                        self.initialize_vault(
+                                            i as u32,
+                                            node,
+                                            num_fullnode_keys,
+                                            params.key_types(),
+                                            vault_token,
+                                        )
+                                        .await
+                                    })
+                                },
+                            )
+                            .await
+                        }),
+                    params.vault_init_concurrency,
+                )
+                .await?;
+            }
+
+            self.set_deploy_phase(DeployPhase::Genesis);
+            let paths = params.build_paths();
+            if let (true, Some(genesis_url), Some(waypoint)) = (
+                params.download_genesis,
+                &params.genesis_url,
+                params.genesis_waypoint,
+            ) {
+                self.download_genesis(
+                    &paths,
+                    genesis_url,
+                    waypoint,
+                    &validator_vaults,
+                    vault_token,
+                )
+                .await?;
+            } else if params.skip_genesis {
+                let genesis_path = paths.genesis();
+                if !genesis_path.exists() {
+                    bail!(
+                        "--skip-genesis is set but {} does not exist",
+                        genesis_path.display()
+                    );
+                }
+                info!(
+                    "Skipping genesis generation, reusing {}",
+                    genesis_path.display()
+                );
+            } else {
+                info!("Generating genesis with management tool.");
+                self.generate_vault_genesis(
+                    num_validators,
+                    params.genesis_chain_id,
+                    address_source,
+                    params.network_mode,
+                    params.genesis_naming,
+                    params.layout_format,
+                    &paths,
+                    &validator_vaults,
+                    &validator_nodes,
+                    &fullnode_nodes,
+                    params.fullnode_port,
+                    vault_token,
+                    params.extra_genesis_script()?.as_deref(),
+                    &params.genesis_account,
+                )
+                .await?;
+                info!("Done generating genesis.");
+            }
+            *self
+                .genesis_archive
+                .lock()
+                .expect("genesis_archive lock poisoned") = Some(GenesisArchive {
+                paths: paths.clone(),
+                layout_format: params.layout_format,
+                include_secrets: params.include_secrets,
+            });
+            if params.waypoint_only {
+                info!(
+                    "Skipping genesis.blob copy, validators fetch it from {}",
+                    params.genesis_url.as_deref().unwrap_or_default()
+                );
+            } else {
+                self.copy_genesis(&paths, &params.distributed_genesis_path(), &validator_nodes)
+                    .await?;
+            }
+            if params.waypoint_to_fullnodes {
+                self.copy_fullnode_waypoint(
+                    &paths,
+                    &params.fullnode_waypoint_path,
+                    &fullnode_nodes,
+                    num_fullnodes_per_validator,
+                )
+                .await?;
+            }
+        }
+
+        self.set_deploy_phase(DeployPhase::SpawnNodes);
+        let json_rpc_probe = if params.readiness_probes {
+            Some(ReadinessProbe::json_rpc())
+        } else {
+            None
+        };
+        let mut configs = vec![];
+        // Node of every pod and whether its data is cleaned before spawning it
+        let mut pod_nodes = HashMap::new();
+        for i in 0..num_validators {
+            let seed_peer_ip = validator_nodes[0].ip(address_source)?;
+            let safety_rules_addrs = if enable_lsr {
+                safety_rules_addrs(
+                    lsrs_nodes,
+                    i,
+                    lsr_replicas_per_validator,
+                    params.validators_per_lsr,
+                    params.lsr_port,
+                )
+            } else {
+                vec![]
+            };
+            let validator_config = ValidatorConfig {
+                num_validators,
+                num_fullnodes: num_fullnodes_per_validator,
+                enable_lsr,
+                image_tag: image_tag.to_string(),
+                config_overrides: ConfigOverride::render(
+                    &params.cfg_overrides_for(&validator_pod_name(i)),
+                ),
+                seed_peer_ip,
+                safety_rules_addrs,
+                safety_rules_service: params.safety_rules_service(),
+                readiness_probe: json_rpc_probe.clone(),
+                tolerations: params.validator_toleration.clone(),
+            };
+            pod_nodes.insert(
+                validator_pod_name(i),
+                (
+                    &validator_nodes[i as usize],
+                    params.clean_data_for(i, clean_data),
+                ),
+            );
+            configs.push(InstanceConfig {
+                validator_group: ValidatorGroup::new_for_index(i),
+                application_config: Validator(validator_config),
+                pod_metadata: pod_metadata.clone(),
+                env: params.validator_pod_env(),
+                namespace: params.validator_namespace.clone(),
+                image_pull_policy: params.image_pull_policy,
+                image_digests: self.image_digests(image_tag),
+            });
+        }
+        let validator_seed_addresses = try_join_all((0..num_validators).map(|i| {
+            fullnode_seed_peer_address(
+                i,
+                &validator_nodes[i as usize],
+                validator_vaults.get(i as usize),
+                address_source,
+                vault_token,
+            )
+        }))
+        .await?;
+        for validator_index in 0..num_validators {
+            let seed_peer_addresses = fullnode_seed_addresses(
+                &validator_seed_addresses,
+                validator_index,
+                params.fullnode_seed_count,
+            );
+            for fullnode_index in 0..num_fullnodes_per_validator {
+                let (network_key, vault_addr, fullnode_vault_token) = if fullnode_network_keys {
+                    (
+                        Some(fullnode_network_key(validator_index, fullnode_index)),
+                        Some(
+                            validator_vaults[validator_index as usize]
+                                .internal_ip
+                                .clone(),
+                        ),
+                        Some(VaultToken::new(vault_token.to_string())),
+                    )
+                } else {
+                    (None, None, None)
+                };
+                let fullnode_config = FullnodeConfig {
+                    fullnode_index,
+                    num_fullnodes_per_validator,
+                    num_validators,
+                    image_tag: image_tag.to_string(),
+                    config_overrides: ConfigOverride::render(
+                        &params
+                            .cfg_overrides_for(&fullnode_pod_name(validator_index, fullnode_index)),
+                    ),
+                    seed_peer_addresses: seed_peer_addresses.clone(),
+                    network_key,
+                    vault_addr,
+                    vault_token: fullnode_vault_token,
+                    listen_port: params.fullnode_port,
+                    readiness_probe: json_rpc_probe.clone(),
+                    tolerations: params.fullnode_toleration.clone(),
+                };
+                pod_nodes.insert(
+                    fullnode_pod_name(validator_index, fullnode_index),
+                    (
+                        &fullnode_nodes[(validator_index * num_fullnodes_per_validator
+                            + fullnode_index) as usize],
+                        params.clean_data_for(validator_index, clean_data),
+                    ),
+                );
+                configs.push(InstanceConfig {
+                    validator_group: ValidatorGroup::new_for_index(validator_index),
+                    application_config: Fullnode(fullnode_config),
+                    pod_metadata: pod_metadata.clone(),
+                    env: params.fullnode_pod_env(),
+                    namespace: params.fullnode_namespace.clone(),
+                    image_pull_policy: params.image_pull_policy,
+                    image_digests: self.image_digests(image_tag),
+                });
+            }
+        }
+
+        let pod_nodes = &pod_nodes;
+        let results = SpawnPlan::new(configs.clone())
+            .execute(params.spawn_concurrency, |instance_config| async move {
+                let (node, clean) = pod_nodes[&instance_config.pod_name()];
+                if clean {
+                    self.clean_node_data(&node.name).await?;
+                }
+                spawn_limited(&*self.cluster_swarm, spawn_limit, instance_config).await
+            })
+            .await?;
+        let (validator_results, fullnode_results): (Vec<_>, Vec<_>) = configs
+            .iter()
+            .zip(results)
+            .partition(|(config, _)| matches!(config.application_config, Validator(_)));
+        spawned.validators = collect_spawn_results(
+            validator_results.into_iter().map(|(_, r)| r).collect(),
+            &mut spawned.errors,
+        );
+        spawned.fullnodes = collect_spawn_results(
+            fullnode_results.into_iter().map(|(_, r)| r).collect(),
+            &mut spawned.errors,
+        );
+        Ok(())
+    }
+}
+
+/// Fallible ChainId::from_str, which panics on an empty string and on 0
+fn parse_chain_id(s: &str) -> Result<ChainId> {
+    if s.is_empty() {
+        bail!("Chain id can't be empty");
+    }
+    if s.parse::<u8>().ok() == Some(0) {
+        bail!("Invalid chain id {}, 0 is reserved", s);
+    }
+    ChainId::from_str(s).map_err(|e| format_err!("Invalid chain id {} : {}", s, e))
+}
+
+fn parse_key_type(s: &str) -> Result<(String, KeyType)> {
+    let (key, key_type) = parse_key_val(s)?;
+    if key != LIBRA_ROOT_KEY && !VALIDATOR_KEYS.contains(&key.as_str()) {
+        bail!(
+            "Unknown key {}, expected {} or one of {}",
+            key,
+            LIBRA_ROOT_KEY,
+            VALIDATOR_KEYS.join(", ")
+        );
+    }
+    Ok((key, key_type.parse()?))
+}
+
+/// Ports the fullnode pods listen on besides the fullnode network
+const FULLNODE_RESERVED_PORTS: &[(&str, u32)] = &[
+    ("JSON-RPC", DEFAULT_JSON_RPC_PORT as u32),
+    ("debug interface", 6191),
+    ("metrics", 9101),
+];
+
+fn parse_fullnode_port(s: &str) -> Result<u32> {
+    let port = s.parse()?;
+    if let Some((name, _)) = FULLNODE_RESERVED_PORTS.iter().find(|(_, p)| *p == port) {
+        bail!(
+            "--fullnode-port {} conflicts with the {} port of the fullnodes",
+            port,
+            name
+        );
+    }
+    Ok(port)
+}
+
+/// BFT tolerates f faulty validators out of 3f + 1, other validator counts tolerate as many
+/// faults as the 3f + 1 count below them
+fn validator_count_warning(num_validators: u32) -> Option<String> {
+    if num_validators == 0 || (num_validators - 1) % 3 == 0 {
+        return None;
+    }
+    let f = (num_validators - 1) / 3;
+    let lower = 3 * f + 1;
+    Some(format!(
+        "{} validators only tolerate {} faulty validators, like {} do. Use {} or {} validators \
+         (3f + 1) instead",
+        num_validators,
+        f,
+        lower,
+        lower,
+        lower + 3
+    ))
+}
+
+fn parse_genesis_file_name(s: &str) -> Result<String> {
+    if s.is_empty() || s.contains('/') {
+        bail!(
+            "Invalid genesis file name {}, expected a file name without directory",
+            s
+        );
+    }
+    Ok(s.to_string())
+}
+
+fn parse_max_in_flight_spawns(s: &str) -> Result<usize> {
+    let max_in_flight_spawns = s.parse()?;
+    if max_in_flight_spawns == 0 {
+        bail!("--max-in-flight-spawns must be at least 1");
+    }
+    Ok(max_in_flight_spawns)
+}
+
+fn parse_validators_per_lsr(s: &str) -> Result<u32> {
+    let validators_per_lsr = s.parse()?;
+    if validators_per_lsr == 0 {
+        bail!("--validators-per-lsr must be at least 1");
+    }
+    Ok(validators_per_lsr)
+}
+
+fn parse_validators_per_vault(s: &str) -> Result<u32> {
+    let validators_per_vault = s.parse()?;
+    if validators_per_vault == 0 {
+        bail!("--validators-per-vault must be at least 1");
+    }
+    Ok(validators_per_vault)
+}
+
+fn parse_vault_kv_version(s: &str) -> Result<u32> {
+    let version = s.parse()?;
+    // VaultStorage reads and writes secrets through the KV v2 API (secret/data/..), there is no
+    // way to point it at a v1 engine
+    if version != 2 {
+        bail!(
+            "Unsupported vault KV version {}, VaultStorage only supports KV v2",
+            version
+        );
+    }
+    Ok(version)
+}
+
+/// Validators of the cluster with the given pod names, failing on names that are not validators
+fn validators_named<'a>(cluster: &'a Cluster, pod_names: &[String]) -> Result<Vec<&'a Instance>> {
+    pod_names
+        .iter()
+        .map(|pod_name| {
+            cluster
+                .get_validator_instance(pod_name)
+                .ok_or_else(|| format_err!("{} is not a validator of the cluster", pod_name))
+        })
+        .collect()
+}
+
+/// Accounts of the validator set stored on chain, as seen by `instance`
+async fn on_chain_validator_set(instance: &Instance) -> Result<Vec<AccountAddress>> {
+    let mut batch = JsonRpcBatch::new();
+    batch.add_get_account_state_with_proof_request(config_address(), None, None);
+    let mut responses = instance
+        .json_rpc_client()
+        .execute(batch)
+        .await
+        .map_err(|e| format_err!("Failed to get the validator set from {} : {}", instance, e))?;
+    if responses.len() != 1 {
+        bail!(
+            "Expected 1 response from {}, got {}",
+            instance,
+            responses.len()
+        );
+    }
+    let view = AccountStateWithProofView::from_response(responses.remove(0)?)?;
+    let blob = view
+        .blob
+        .ok_or_else(|| format_err!("{} has no config account state", instance))?;
+    validator_set_accounts(&lcs::from_bytes(&blob.into_bytes()?)?)
+}
+
+/// AWS tags identifying the instances of a run
+fn run_tags(
+    run_id: Option<&str>,
+    chain_id: Option<ChainId>,
+    image_tag: &str,
+) -> Vec<(String, String)> {
+    let mut tags = vec![("cluster-test/image-tag".to_string(), image_tag.to_string())];
+    if let Some(run_id) = run_id {
+        tags.push(("cluster-test/run-id".to_string(), run_id.to_string()));
+    }
+    if let Some(chain_id) = chain_id {
+        tags.push(("cluster-test/chain-id".to_string(), chain_id.to_string()));
+    }
+    tags
+}
+
+/// Scales `asg_name` up to `instance_count` with `set_asg_size(count, buffer_percent,
+/// scaling_down)`. Unlike setup_cluster it doesn't scale down to zero first, the instances already
+/// running are kept
+async fn prewarm_asg<F, Fut>(asg_name: &str, instance_count: u32, set_asg_size: F) -> Result<()>
+where
+    F: FnOnce(i64, f64, bool) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    info!("Prewarming {} to {} instances", asg_name, instance_count);
+    set_asg_size(instance_count as i64, 5.0, false)
+        .await
+        .map_err(|err| format_err!("{} prewarm failed: {}", asg_name, err))
+}
+
+/// Checks that an ASG that is not resized by cluster-test already runs `instance_count`
+/// instances
+async fn check_fixed_asg_size<F, Fut>(
+    asg_name: &str,
+    instance_count: u32,
+    running_count: F,
+) -> Result<()>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<i64>>,
+{
+    let running = running_count().await.map_err(|e| {
+        format_err!(
+            "Failed to get running instance count of {}: {}",
+            asg_name,
+            e
+        )
+    })?;
+    if running < instance_count as i64 {
+        bail!(
+            "{} runs {} instances but the cluster needs {}, it is not resized with --fixed-asg-size",
+            asg_name,
+            running,
+            instance_count
+        );
+    }
+    info!(
+        "{} runs {} instances, enough for {} without resizing",
+        asg_name, running, instance_count
+    );
+    Ok(())
+}
+
+/// Number of validators needed for a quorum, more than two thirds of them like the voting power
+/// of the validator verifier
+fn quorum_size(num_validators: u32) -> u32 {
+    num_validators * 2 / 3 + 1
+}
+
+/// Whether the validator serves JSON-RPC and its consensus committed past genesis
+async fn is_live_validator(instance: &Instance) -> bool {
+    if instance.try_json_rpc().await.is_err() {
+        return false;
+    }
+    match instance.consensus_status().await {
+        Ok(status) => status.last_committed_round > 0,
+        Err(_) => false,
+    }
+}
+
+/// Latest version the instance committed, from its JSON-RPC metadata
+async fn committed_version(instance: &Instance) -> Result<u64> {
+    let mut batch = JsonRpcBatch::new();
+    batch.add_get_metadata_request(None);
+    let mut responses = instance
+        .json_rpc_client()
+        .execute(batch)
+        .await
+        .map_err(|e| format_err!("Failed to get the metadata of {} : {}", instance, e))?;
+    if responses.len() != 1 {
+        bail!(
+            "Expected 1 response from {}, got {}",
+            instance,
+            responses.len()
+        );
+    }
+    Ok(BlockMetadata::from_response(responses.remove(0)?)?.version)
+}
+
+/// Polls the version of every instance with `version` until all of them reached `target`, fails
+/// with the instances lagging behind if that doesn't happen within `timeout`
+async fn wait_for_versions<'a, F, Fut>(
+    instances: &'a [Instance],
+    target: u64,
+    timeout: Duration,
+    poll_interval: Duration,
+    version: F,
+) -> Result<()>
+where
+    F: Fn(&'a Instance) -> Fut,
+    Fut: Future<Output = Result<u64>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        let versions = join_all(instances.iter().map(&version)).await;
+        let laggards: Vec<_> = instances
+            .iter()
+            .zip(versions)
+            .filter_map(|(instance, version)| match version {
+                Ok(version) if version >= target => None,
+                Ok(version) => Some(format!("{} at {}", instance.peer_name(), version)),
+                Err(e) => Some(format!("{} unknown ({})", instance.peer_name(), e)),
+            })
+            .collect();
+        if laggards.is_empty() {
+            info!(
+                "All {} validators committed version {}",
+                instances.len(),
+                target
+            );
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "{} of {} validators did not commit version {} within {:?}: {}",
+                laggards.len(),
+                instances.len(),
+                target,
+                timeout,
+                laggards.join(", ")
+            );
+        }
+        tokio::time::delay_for(poll_interval).await;
+    }
+}
+
+/// Waits until a quorum of the `num_validators` validators is live according to `live_count`,
+/// fails with the last count if it isn't reached within `timeout`
+async fn wait_for_quorum<F, Fut>(
+    num_validators: u32,
+    timeout: Duration,
+    poll_interval: Duration,
+    live_count: F,
+) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = u32>,
+{
+    let required = quorum_size(num_validators);
+    let deadline = Instant::now() + timeout;
+    loop {
+        let live = live_count().await;
+        if live >= required {
+            info!(
+                "Quorum reached: {} of {} validators are live",
+                live, num_validators
+            );
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "Quorum not reached after {:?}: {} of {} validators are live, {} required",
+                timeout,
+                live,
+                num_validators,
+                required
+            );
+        }
+        tokio::time::delay_for(poll_interval).await;
+    }
+}
+
+/// Waits until at least `target` instances of the ASG are running. If that doesn't happen within
+/// `timeout`, `rescale` is attempted once and the wait is repeated before giving up
+async fn ensure_asg_size<C, CF, R, RF>(
+    asg_name: &str,
+    target: i64,
+    timeout: Duration,
+    poll_interval: Duration,
+    running_count: C,
+    rescale: R,
+) -> Result<()>
+where
+    C: Fn() -> CF,
+    CF: Future<Output = Result<i64>>,
+    R: FnOnce() -> RF,
+    RF: Future<Output = Result<()>>,
+{
+    let mut rescale = Some(rescale);
+    loop {
+        let deadline = Instant::now() + timeout;
+        let mut running = None;
+        loop {
+            match running_count().await {
+                Ok(count) if count >= target => return Ok(()),
+                Ok(count) => running = Some(count),
+                Err(e) => warn!(
+                    "Failed to get running instance count of {}: {}",
+                    asg_name, e
+                ),
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::delay_for(poll_interval).await;
+        }
+        let running = running.map_or_else(|| "unknown".to_string(), |c| c.to_string());
+        match rescale.take() {
+            Some(rescale) => {
+                warn!(
+                    "{} stuck at {}/{} running instances, re-scaling",
+                    asg_name, running, target
+                );
+                rescale()
+                    .await
+                    .map_err(|e| format_err!("{} corrective re-scale failed: {}", asg_name, e))?;
+            }
+            None => bail!(
+                "{} stuck at {}/{} running instances after corrective re-scale",
+                asg_name,
+                running,
+                target
+            ),
+        }
+    }
+}
+
+/// Like `libra_retrier::fixed_retry_strategy` but every delay gets a random extra of up to
+/// `jitter_ms`, so that nodes retrying at the same time (e.g. against vault) don't stay in lockstep
+fn jittered_retry_strategy(
+    delay_ms: u64,
+    jitter_ms: u64,
+    tries: usize,
+) -> impl Iterator<Item = Duration> {
+    let mut rng = rand::thread_rng();
+    let delays: Vec<_> = (0..tries)
+        .map(|_| Duration::from_millis(delay_ms + rng.gen_range(0, jitter_ms + 1)))
+        .collect();
+    delays.into_iter()
+}
+
+/// Builds the /ip4 or /ip6 address of `pod_name` depending on the family of `ip`, failing instead
+/// of panicking on an invalid ip (e.g. a node that has no ip yet)
+fn network_address(pod_name: &str, ip: &str, port: u32) -> Result<NetworkAddress> {
+    let protocol = match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => "ip6",
+        _ => "ip4",
+    };
+    let addr = format!("/{}/{}/tcp/{}", protocol, ip, port);
+    NetworkAddress::from_str(&addr)
+        .map_err(|e| format_err!("Invalid network address {} for {} : {}", addr, pod_name, e))
+}
+
+/// Seeds of the fullnodes of `validator_index`: their own validator followed by the first
+/// `seed_count` validators, clamped to the number of validators
+fn fullnode_seed_addresses(
+    validator_seed_addresses: &[NetworkAddress],
+    validator_index: u32,
+    seed_count: u32,
+) -> Vec<NetworkAddress> {
+    let own = validator_seed_addresses[validator_index as usize].clone();
+    let others = validator_seed_addresses
+        .iter()
+        .take(seed_count as usize)
+        .enumerate()
+        .filter(|(i, _)| *i != validator_index as usize)
+        .map(|(_, address)| address.clone());
+    iter::once(own).chain(others).collect()
+}
+
+/// host:port addresses of the LSR replicas of the given validator, `lsrs_nodes` holds the nodes
+/// of all replicas ordered by the group of `validators_per_lsr` validators sharing them
+fn safety_rules_addrs(
+    lsrs_nodes: &[KubeNode],
+    validator_index: u32,
+    lsr_replicas_per_validator: u32,
+    validators_per_lsr: u32,
+    lsr_port: u32,
+) -> Vec<String> {
+    let group = validator_index / validators_per_lsr.max(1);
+    let start = (group * lsr_replicas_per_validator) as usize;
+    lsrs_nodes[start..start + lsr_replicas_per_validator as usize]
+        .iter()
+        .map(|node| format!("{}:{}", node.internal_ip, lsr_port))
+        .collect()
+}
+
+/// Splits spawn results into the instances that were created, moving failures into `errors`
+fn collect_spawn_results<T>(results: Vec<Result<T>>, errors: &mut Vec<anyhow::Error>) -> Vec<T> {
+    let mut instances = vec![];
+    for result in results {
+        match result {
+            Ok(instance) => instances.push(instance),
+            Err(e) => errors.push(e),
+        }
+    }
+    instances
+}
+
+/// Fails with a clear error if the swarm can't fit `required` instances, instead of letting
+/// allocate_node fail halfway through the spawn
+async fn check_node_capacity(swarm: &(impl ClusterSwarm + ?Sized), required: u32) -> Result<()> {
+    let available = swarm
+        .node_capacity()
+        .await
+        .map_err(|e| format_err!("Failed to get node capacity : {}", e))?;
+    if available < required {
+        bail!(
+            "Not enough nodes for the cluster: need {} nodes, only {} available",
+            required,
+            available
+        );
+    }
+    Ok(())
+}
+
+/// Runs the allocations, concurrently unless `sequential` is set. Allocating one pod at a time
+/// makes the pod to node mapping independent of which allocation completes first. With
+/// `collect_all_errors` every failed allocation is reported in the returned error, otherwise the
+/// first failure aborts the others like try_join_all
+async fn join_allocations<I, F, T>(
+    allocations: I,
+    collect_all_errors: bool,
+    sequential: bool,
+) -> Result<Vec<T>>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = Result<T>>,
+{
+    let nodes = if sequential || collect_all_errors {
+        let results: Vec<_> = if sequential {
+            stream::iter(allocations).buffered(1).collect().await
+        } else {
+            join_all(allocations).await
+        };
+        if collect_all_errors {
+            let mut errors = vec![];
+            let nodes = collect_spawn_results(results, &mut errors);
+            if !errors.is_empty() {
+                bail!("{}", combined_error(&errors));
+            }
+            nodes
+        } else {
+            results.into_iter().collect::<Result<_>>()?
+        }
+    } else {
+        try_join_all(allocations).await?
+    };
+    counters::NODES_ALLOCATED.inc_by(nodes.len() as i64);
+    Ok(nodes)
+}
+
+/// Spawns the instance once `spawn_limit`, sized by --max-in-flight-spawns, has a free permit.
+/// The permit is held until the swarm is done spawning.
+async fn spawn_limited(
+    swarm: &(impl ClusterSwarm + ?Sized),
+    spawn_limit: Option<&Semaphore>,
+    instance_config: InstanceConfig,
+) -> Result<Instance> {
+    let _permit = match spawn_limit {
+        Some(spawn_limit) => Some(spawn_limit.acquire().await),
+        None => None,
+    };
+    swarm.spawn_new_instance(instance_config).await
+}
+
+/// Like try_join_all but with at most `concurrency` of the futures running at a time, the results
+/// are in the order the futures complete
+async fn try_join_bounded<I, F, T>(futures: I, concurrency: usize) -> Result<Vec<T>>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = Result<T>>,
+{
+    stream::iter(futures)
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// Runs `commands` in order on every instance with `exec`, on at most `concurrency` instances at
+/// a time. The commands of an instance stop at the first failure, the other instances still run.
+async fn run_post_spawn_hook<'a, F, Fut>(
+    instances: Vec<&'a Instance>,
+    commands: &'a [String],
+    concurrency: usize,
+    exec: F,
+) -> Result<()>
+where
+    F: Fn(&'a Instance, &'a str) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let exec = &exec;
+    let errors: Vec<_> = stream::iter(instances)
+        .map(|instance| async move {
+            for command in commands {
+                exec(instance, command).await.map_err(|e| {
+                    format_err!(
+                        "Post spawn hook {} failed on {}: {}",
+                        command,
+                        instance.peer_name(),
+                        e
+                    )
+                })?;
+            }
+            Ok::<(), anyhow::Error>(())
+        })
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(|result| async move { result.err() })
+        .collect()
+        .await;
+    if !errors.is_empty() {
+        bail!("{}", combined_error(&errors));
+    }
+    Ok(())
+}
+
+fn combined_error(errors: &[anyhow::Error]) -> String {
+    format!(
+        "{} failures:\n  {}",
+        errors.len(),
+        errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n  ")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{block_on, MockSwarm};
+
+    #[test]
+    pub fn test_collect_spawn_results_keeps_successful_instances() {
+        let results = vec![
+            Ok(validator_pod_name(0)),
+            Err(format_err!("Failed to spawn {}", validator_pod_name(1))),
+            Ok(validator_pod_name(2)),
+        ];
+        let mut errors = vec![];
+        let instances = collect_spawn_results(results, &mut errors);
+        assert_eq!(
+            instances,
+            vec![validator_pod_name(0), validator_pod_name(2)]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Failed to spawn val-1");
+    }
+
+    #[test]
+    pub fn test_ensure_asg_size_rescales_stuck_asg() {
+        let polls = std::sync::atomic::AtomicUsize::new(0);
+        let rescales = std::sync::atomic::AtomicUsize::new(0);
+        let result = block_on(ensure_asg_size(
+            "test-asg",
+            4,
+            Duration::from_millis(0),
+            Duration::from_millis(0),
+            || {
+                polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async { Ok(2) }
+            },
+            || {
+                rescales.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async { Ok(()) }
+            },
+        ));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "test-asg stuck at 2/4 running instances after corrective re-scale"
+        );
+        assert_eq!(rescales.into_inner(), 1);
+        assert_eq!(polls.into_inner(), 2);
+    }
+
+    #[test]
+    pub fn test_spawn_returns_partial_results() {
+        let swarm = MockSwarm {
+            failing: vec![validator_pod_name(1)].into_iter().collect(),
+            ..Default::default()
+        };
+        let builder = ClusterBuilder::new("test".to_string(), swarm);
+        let params = ClusterBuilderParamsBuilder::default()
+            .num_validators(3)
+            .fullnodes_per_validator(1)
+            .enable_lsr(false)
+            .build();
+        let spawned =
+            block_on(builder.spawn_validator_and_fullnode_set(&params, "test", false)).unwrap();
+        let names = |instances: &[Instance]| {
+            let mut names: Vec<_> = instances.iter().map(|i| i.peer_name().clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(&spawned.validators),
+            vec![validator_pod_name(0), validator_pod_name(2)]
+        );
+        assert_eq!(
+            names(&spawned.fullnodes),
+            vec![fullnode_pod_name(0, 0), fullnode_pod_name(2, 0)]
+        );
+        assert!(!spawned.is_complete());
+        assert!(spawned
+            .errors
+            .iter()
+            .any(|e| e.to_string() == "Failed to spawn val-1"));
+    }
+
+    #[test]
+    pub fn test_spawn_failures_counter() {
+        let swarm = MockSwarm {
+            failing: vec![validator_pod_name(1)].into_iter().collect(),
+            ..Default::default()
+        };
+        let builder = ClusterBuilder::new("test".to_string(), swarm);
+        let params = ClusterBuilderParamsBuilder::default()
+            .num_validators(2)
+            .fullnodes_per_validator(1)
+            .enable_lsr(false)
+            .build();
+        let failures = counters::SPAWN_FAILURES.get();
+        let spawned =
+            block_on(builder.spawn_validator_and_fullnode_set(&params, "test", false)).unwrap();
+        assert!(!spawned.is_complete());
+        // The fullnode of val-1 is not spawned without its validator
+        assert_eq!(spawned.errors.len(), 2);
+        assert_eq!(spawned.validators.len(), 1);
+        assert_eq!(spawned.fullnodes.len(), 1);
+        // Other tests may fail spawns concurrently
+        assert!(counters::SPAWN_FAILURES.get() >= failures + 2);
+    }
+
+    #[test]
+    pub fn test_federated_cluster_builder() {
+        let swarm = Arc::new(
+            FederatedClusterSwarm::new(vec![MockSwarm::default(), MockSwarm::default()]).unwrap(),
+        );
+        let builder = ClusterBuilder::shared("test".to_string(), swarm.clone());
+        let params = ClusterBuilderParamsBuilder::default()
+            .num_validators(4)
+            .fullnodes_per_validator(1)
+            .enable_lsr(false)
+            .build();
+        let spawned =
+            block_on(builder.spawn_validator_and_fullnode_set(&params, "test", false)).unwrap();
+        assert!(spawned.is_complete());
+        assert_eq!(spawned.validators.len(), 4);
+
+        // The instances of validator i go to swarm i % 2
+        let spawned_on = |i: usize| {
+            let mut pod_names: Vec<_> = swarm.swarms()[i]
+                .spawned
+                .lock()
+                .unwrap()
+                .iter()
+                .map(InstanceConfig::pod_name)
+                .collect();
+            pod_names.sort();
+            pod_names
+        };
+        assert_eq!(spawned_on(0), vec!["fn-0-0", "fn-2-0", "val-0", "val-2"]);
+        assert_eq!(spawned_on(1), vec!["fn-1-0", "fn-3-0", "val-1", "val-3"]);
+        // Nodes are routed to the swarm that allocated them
+        block_on(swarm.clean_data("node-val-3")).unwrap();
+        assert!(swarm.swarms()[0].cleaned.lock().unwrap().is_empty());
+        assert_eq!(
+            *swarm.swarms()[1].cleaned.lock().unwrap(),
+            vec!["node-val-3".to_string()]
+        );
+    }
+
+    #[test]
+    pub fn test_cleanup_failed() {
+        let instance = |pod_name: String| {
+            Instance::new(
+                pod_name,
+                "127.0.0.1".to_string(),
+                8080,
+                None,
+                reqwest::Client::new(),
+            )
+        };
+        let cluster = Cluster::new(
+            vec![
+                instance(validator_pod_name(0)),
+                instance(validator_pod_name(1)),
+            ],
+            vec![
+                instance(fullnode_pod_name(0, 0)),
+                instance(fullnode_pod_name(1, 0)),
+            ],
+            vec![],
+            vec![],
+        );
+        let swarm = MockSwarm {
+            phases: vec![
+                (validator_pod_name(0), PodPhase::Running),
+                (validator_pod_name(1), PodPhase::Running),
+                (fullnode_pod_name(0, 0), PodPhase::Failed),
+                (fullnode_pod_name(1, 0), PodPhase::Pending),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let deleted = block_on(ClusterBuilder::cleanup_failed(&cluster, &swarm)).unwrap();
+        assert_eq!(deleted, vec![fullnode_pod_name(0, 0)]);
+        assert_eq!(swarm.deleted.into_inner().unwrap(), deleted);
+    }
+
+    #[test]
+    pub fn test_replace_pod() {
+        let config = InstanceConfig {
+            validator_group: ValidatorGroup::new_for_index(1),
+            application_config: Validator(ValidatorConfig {
+                num_validators: 2,
+                num_fullnodes: 0,
+                enable_lsr: false,
+                image_tag: "old".to_string(),
+                config_overrides: vec![],
+                seed_peer_ip: "10.0.0.1".to_string(),
+                safety_rules_addrs: vec![],
+                safety_rules_service: SafetyRulesServiceType::Process,
+                readiness_probe: None,
+                tolerations: vec![],
+            }),
+            pod_metadata: Default::default(),
+            env: vec![],
+            namespace: None,
+            image_pull_policy: None,
+            image_digests: Default::default(),
+        };
+        let swarm = MockSwarm::default();
+        let instance = block_on(ClusterBuilder::replace_pod(
+            &swarm, &config, "node-1", "new", false,
+        ))
+        .unwrap();
+        assert_eq!(instance.peer_name(), &validator_pod_name(1));
+        assert_eq!(*swarm.deleted.lock().unwrap(), vec![validator_pod_name(1)]);
+        assert!(swarm.cleaned.lock().unwrap().is_empty());
+        let spawned = swarm.spawned.lock().unwrap().remove(0);
+        assert_eq!(spawned.validator_group, config.validator_group);
+        match spawned.application_config {
+            Validator(validator_config) => assert_eq!(validator_config.image_tag, "new"),
+            _ => panic!("Replacement of a validator is not a validator"),
+        }
+
+        block_on(ClusterBuilder::replace_pod(
+            &swarm, &config, "node-1", "new", true,
+        ))
+        .unwrap();
+        assert_eq!(*swarm.cleaned.lock().unwrap(), vec!["node-1".to_string()]);
+    }
+
+    #[test]
+    pub fn test_partition() {
+        let instances: Vec<_> = (0..3)
+            .map(|i| {
+                Instance::new(
+                    validator_pod_name(i),
+                    format!("10.0.0.{}", i),
+                    8080,
+                    None,
+                    reqwest::Client::new(),
+                )
+            })
+            .collect();
+        let swarm = MockSwarm::default();
+        block_on(ClusterBuilder::partition(
+            &swarm,
+            &instances[..1],
+            &instances[1..],
+        ))
+        .unwrap();
+        block_on(ClusterBuilder::inject_fault(
+            &swarm,
+            &instances[..1],
+            Fault::HealPartition,
+        ))
+        .unwrap();
+
+        let partition =
+            |ips: &[&str]| Fault::NetworkPartition(ips.iter().map(|ip| ip.to_string()).collect());
+        assert_eq!(
+            swarm.faults.into_inner().unwrap(),
+            vec![
+                (validator_pod_name(0), partition(&["10.0.0.1", "10.0.0.2"])),
+                (validator_pod_name(1), partition(&["10.0.0.0"])),
+                (validator_pod_name(2), partition(&["10.0.0.0"])),
+                (validator_pod_name(0), Fault::HealPartition),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_wait_for_pod() {
+        let swarm = MockSwarm {
+            phases: vec![
+                (validator_pod_name(0), PodPhase::Running),
+                (fullnode_pod_name(0, 0), PodPhase::Failed),
+                (fullnode_pod_name(1, 0), PodPhase::Pending),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let timeout = Duration::from_secs(0);
+        block_on(swarm.wait_for_pod(&validator_pod_name(0), PodPhase::Running, timeout)).unwrap();
+        let error =
+            block_on(swarm.wait_for_pod(&fullnode_pod_name(1, 0), PodPhase::Running, timeout))
+                .unwrap_err()
+                .to_string();
+        assert!(error.contains("last phase Pending"), "{}", error);
+        // A failed pod doesn't wait for the timeout
+        let error = block_on(swarm.wait_for_pod(
+            &fullnode_pod_name(0, 0),
+            PodPhase::Running,
+            Duration::from_secs(3600),
+        ))
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("last phase Failed"), "{}", error);
+    }
+
+    #[test]
+    pub fn test_run_post_spawn_hook() {
+        let instances: Vec<_> = (0..3)
+            .map(|i| {
+                Instance::new(
+                    validator_pod_name(i),
+                    format!("10.0.0.{}", i),
+                    8080,
+                    None,
+                    reqwest::Client::new(),
+                )
+            })
+            .collect();
+        let commands = vec!["apt-get install -y gdb".to_string(), "true".to_string()];
+        let executed = Mutex::new(vec![]);
+        let exec = |instance: &Instance, command: &str| {
+            executed
+                .lock()
+                .unwrap()
+                .push(format!("{}: {}", instance.peer_name(), command));
+            let result = if instance.peer_name() == "val-1" {
+                Err(format_err!("exit code 1"))
+            } else {
+                Ok(())
+            };
+            async move { result }
+        };
+        let err = block_on(run_post_spawn_hook(
+            instances.iter().collect(),
+            &commands,
+            2,
+            exec,
+        ))
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Post spawn hook apt-get install -y gdb failed on val-1: exit code 1"));
+
+        let mut executed = executed.into_inner().unwrap();
+        executed.sort();
+        assert_eq!(
+            executed,
+            vec![
+                "val-0: apt-get install -y gdb",
+                "val-0: true",
+                "val-1: apt-get install -y gdb",
+                "val-2: apt-get install -y gdb",
+                "val-2: true",
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_try_join_bounded() {
+        let running = std::sync::atomic::AtomicUsize::new(0);
+        let max_running = std::sync::atomic::AtomicUsize::new(0);
+        let futures = (0..10).map(|i| {
+            let (running, max_running) = (&running, &max_running);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                if i == 7 {
+                    bail!("Failed to initialize vault {}", i);
+                }
+                Ok(i)
+            }
+        });
+        let err = block_on(try_join_bounded(futures, 3)).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to initialize vault 7");
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+
+        let mut results =
+            block_on(try_join_bounded((0..4).map(|i| async move { Ok(i) }), 0)).unwrap();
+        results.sort_unstable();
+        assert_eq!(results, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    pub fn test_verify_validator_set() {
+        use libra_crypto::{PrivateKey, Uniform};
+        use libra_types::{
+            on_chain_config::{OnChainConfig, ValidatorSet},
+            validator_info::ValidatorInfo,
+        };
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::from_seed([0u8; 32]);
+        let accounts: Vec<_> = (0..3).map(|_| AccountAddress::random()).collect();
+        let validator_set = ValidatorSet::new(
+            accounts[..2]
+                .iter()
+                .map(|account| {
+                    let consensus_key = Ed25519PrivateKey::generate(&mut rng).public_key();
+                    ValidatorInfo::new_with_test_network_keys(*account, consensus_key, 1)
+                })
+                .collect(),
+        );
+        let mut state = AccountState::default();
+        state.insert(
+            ValidatorSet::CONFIG_ID.access_path().path,
+            lcs::to_bytes(&validator_set).unwrap(),
+        );
+        let on_chain =
+            validator_set_accounts(&AccountStateBlob::try_from(&state).unwrap()).unwrap();
+        assert_eq!(on_chain, accounts[..2].to_vec());
+
+        check_validator_set(&[accounts[1], accounts[0]], &on_chain).unwrap();
+        let error = check_validator_set(&[accounts[0], accounts[2]], &on_chain)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains(&format!("missing: [{}]", accounts[2])),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains(&format!("unexpected: [{}]", accounts[1])),
+            "{}",
+            error
+        );
+
+        let empty = AccountStateBlob::try_from(&AccountState::default()).unwrap();
+        assert!(validator_set_accounts(&empty).is_err());
+
+        let builder = ClusterBuilder::new("test".to_string(), MockSwarm::default());
+        let error =
+            block_on(builder.verify_validator_set(&Cluster::new(vec![], vec![], vec![], vec![])))
+                .unwrap_err();
+        assert!(
+            error.to_string().contains("without validators"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    pub fn test_check_node_capacity() {
+        let swarm = MockSwarm {
+            node_capacity: 8,
+            ..Default::default()
+        };
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "4",
+            "--check-node-capacity",
+        ]);
+        block_on(check_node_capacity(&swarm, 8)).unwrap();
+        let err = block_on(check_node_capacity(&swarm, params.instance_count())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Not enough nodes for the cluster: need {} nodes, only 8 available",
+                params.instance_count()
+            )
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl DeploymentObserver for RecordingObserver {
+        fn on_phase_start(&self, phase: DeployPhase) {
+            self.events.lock().unwrap().push(format!("start {}", phase));
+        }
+
+        fn on_phase_end(&self, phase: DeployPhase, _duration: Duration) {
+            self.events.lock().unwrap().push(format!("end {}", phase));
+        }
+    }
+
+    #[test]
+    pub fn test_deployment_observer() {
+        let state = Mutex::new((DeployPhase::Done, Instant::now()));
+        let observer = RecordingObserver::default();
+        for phase in [
+            DeployPhase::Cleanup,
+            DeployPhase::Genesis,
+            DeployPhase::Done,
+        ]
+        .iter()
+        {
+            transition_deploy_phase(&state, Some(&observer), *phase);
+        }
+        assert_eq!(
+            observer.events.into_inner().unwrap(),
+            vec![
+                "start cleanup",
+                "end cleanup",
+                "start genesis",
+                "end genesis"
+            ]
+        );
+        assert_eq!(state.into_inner().unwrap().0, DeployPhase::Done);
+    }
+
+    #[test]
+    pub fn test_jittered_retry_strategy() {
+        let node_0: Vec<_> = jittered_retry_strategy(5000, 2500, 15).collect();
+        let node_1: Vec<_> = jittered_retry_strategy(5000, 2500, 15).collect();
+        assert_eq!(node_0.len(), 15);
+        assert!(node_0.iter().chain(node_1.iter()).all(|delay| {
+            *delay >= Duration::from_millis(5000) && *delay <= Duration::from_millis(7500)
+        }));
+        assert_ne!(node_0, node_1);
+    }
+
+    #[test]
+    pub fn test_network_address() {
+        let addr = network_address("val-0", "10.0.0.1", 6180).unwrap();
+        assert_eq!(addr.to_string(), "/ip4/10.0.0.1/tcp/6180");
+        let addr = network_address("val-0", "fd00::1", 6180).unwrap();
+        assert_eq!(addr.to_string(), "/ip6/fd00::1/tcp/6180");
+        let err = network_address("val-1", "", 6180).unwrap_err().to_string();
+        assert!(err.starts_with("Invalid network address /ip4//tcp/6180 for val-1"));
+    }
+
+    #[test]
+    pub fn test_prewarm_asg_only_scales() {
+        let params =
+            ClusterBuilderParams::from_iter(vec!["cluster-builder", "--num-validators", "4"]);
+        let calls = Mutex::new(vec![]);
+        block_on(prewarm_asg(
+            "test-asg",
+            params.instance_count(),
+            |count, _buffer_percent, scaling_down| {
+                calls.lock().unwrap().push((count, scaling_down));
+                async { Ok(()) }
+            },
+        ))
+        .unwrap();
+        // A single scale up to the instances of 4 validators, their LSRs, vaults and fullnodes
+        assert_eq!(calls.into_inner().unwrap(), vec![(16, false)]);
+
+        let err = block_on(prewarm_asg("test-asg", 16, |_, _, _| async {
+            Err(format_err!("throttled"))
+        }))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "test-asg prewarm failed: throttled");
+    }
+
+    #[test]
+    pub fn test_check_fixed_asg_size() {
+        block_on(check_fixed_asg_size("test-asg", 16, || async { Ok(20) })).unwrap();
+        let err = block_on(check_fixed_asg_size("test-asg", 16, || async { Ok(12) })).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "test-asg runs 12 instances but the cluster needs 16, it is not resized with \
+             --fixed-asg-size"
+        );
+    }
+
+    #[test]
+    pub fn test_wait_for_quorum() {
+        assert_eq!(quorum_size(1), 1);
+        assert_eq!(quorum_size(4), 3);
+        assert_eq!(quorum_size(5), 4);
+        assert_eq!(quorum_size(100), 67);
+
+        let polls = std::sync::atomic::AtomicU32::new(0);
+        // The live count grows by one on every poll
+        block_on(wait_for_quorum(
+            4,
+            Duration::from_secs(60),
+            Duration::from_millis(0),
+            || async { polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) },
+        ))
+        .unwrap();
+        assert_eq!(polls.into_inner(), 4);
+
+        let err = block_on(wait_for_quorum(
+            4,
+            Duration::from_millis(0),
+            Duration::from_millis(0),
+            || async { 2 },
+        ))
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("2 of 4 validators are live, 3 required"));
+    }
+
+    #[test]
+    pub fn test_wait_for_versions() {
+        let instances: Vec<_> = (0..3)
+            .map(|i| {
+                Instance::new(
+                    validator_pod_name(i),
+                    "127.0.0.1".to_string(),
+                    8080,
+                    None,
+                    reqwest::Client::new(),
+                )
+            })
+            .collect();
+        let polls = std::sync::atomic::AtomicU64::new(0);
+        // val-1 lags 5 versions behind, val-2 catches up after a few polls
+        let version = |instance: &Instance| {
+            let pod_name = instance.peer_name().clone();
+            let poll = polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) / 3;
+            async move {
+                match pod_name.as_str() {
+                    "val-1" => Ok(5),
+                    "val-2" if poll < 2 => bail!("val-2 is unreachable"),
+                    _ => Ok(10),
+                }
+            }
+        };
+        block_on(wait_for_versions(
+            &instances,
+            5,
+            Duration::from_secs(60),
+            Duration::from_millis(0),
+            version,
+        ))
+        .unwrap();
+        assert_eq!(polls.load(std::sync::atomic::Ordering::SeqCst), 9);
+
+        let error = block_on(wait_for_versions(
+            &instances,
+            10,
+            Duration::from_millis(0),
+            Duration::from_millis(0),
+            version,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1 of 3 validators did not commit version 10 within 0ns: val-1 at 5"
+        );
+    }
+
+    #[test]
+    pub fn test_resource_footprint() {
+        let params =
+            ClusterBuilderParams::from_iter(vec!["cluster-builder", "--num-validators", "4"]);
+        assert_eq!(
+            params.resource_footprint(),
+            ResourceFootprint {
+                cpu_millis: 8 * 7800,
+                memory_mib: 0,
+                instance_count: 16,
+            }
+        );
+
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "3",
+            "--fullnodes-per-validator",
+            "0",
+            "--enable-lsr",
+            "false",
+        ]);
+        assert_eq!(
+            params.resource_footprint(),
+            ResourceFootprint {
+                cpu_millis: 3 * 7800,
+                memory_mib: 0,
+                instance_count: 3,
+            }
+        );
+
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "2",
+            "--fullnodes-per-validator",
+            "2",
+            "--lsr-backend",
+            "on-disk",
+        ]);
+        assert_eq!(params.resource_footprint().cpu_millis, 6 * 7800);
+        // 2 validators, 4 fullnodes and 2 lsrs
+        assert_eq!(params.resource_footprint().instance_count, 8);
+    }
+
+    #[test]
+    pub fn test_clean_data_indices() {
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        assert!(params.clean_data_for(3, true));
+        assert!(!params.clean_data_for(3, false));
+
+        let params =
+            ClusterBuilderParams::from_iter(vec!["cluster-builder", "--clean-data-indices", "0,3"]);
+        assert_eq!(params.clean_data_indices, Some(vec![0, 3]));
+        assert!(params.clean_data_for(3, false));
+        assert!(!params.clean_data_for(1, true));
+        assert!(params.clean_vault_data_for(3, false));
+        assert!(!params.clean_vault_data_for(1, true));
+
+        // Validator 3 shares vault 1 with validator 2
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "5",
+            "--validators-per-vault",
+            "2",
+            "--clean-data-indices",
+            "3",
+        ]);
+        assert!(!params.clean_vault_data_for(0, true));
+        assert!(params.clean_vault_data_for(1, false));
+        assert!(!params.clean_vault_data_for(2, true));
+    }
+
+    #[test]
+    pub fn test_lsr_replicas() {
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "4",
+            "--lsr-replicas-per-validator",
+            "2",
+            "--lsr-port",
+            "7185",
+        ]);
+        // 4 validators, 4 fullnodes, 8 lsrs and 4 vaults
+        assert_eq!(params.instance_count(), 20);
+
+        let lsrs_nodes: Vec<_> = (0..8)
+            .map(|i| KubeNode {
+                name: format!("node-{}", i),
+                provider_id: format!("provider-{}", i),
+                internal_ip: format!("10.0.0.{}", i),
+                external_ip: None,
+                unschedulable: false,
+                labels: Default::default(),
+            })
+            .collect();
+        assert_eq!(
+            safety_rules_addrs(
+                &lsrs_nodes,
+                1,
+                params.lsr_replicas_per_validator,
+                params.validators_per_lsr,
+                params.lsr_port
+            ),
+            vec!["10.0.0.2:7185".to_string(), "10.0.0.3:7185".to_string()]
+        );
+    }
+
+    #[test]
+    pub fn test_validators_per_lsr() {
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "5",
+            "--validators-per-lsr",
+            "2",
+        ]);
+        assert_eq!(params.lsr_validators(), vec![0, 2, 4]);
+        // 5 validators, 5 fullnodes, 3 lsrs and 5 vaults
+        assert_eq!(params.instance_count(), 18);
+
+        let lsrs_nodes: Vec<_> = (0..3)
+            .map(|i| KubeNode {
+                name: format!("node-{}", i),
+                provider_id: format!("provider-{}", i),
+                internal_ip: format!("10.0.0.{}", i),
+                external_ip: None,
+                unschedulable: false,
+                labels: Default::default(),
+            })
+            .collect();
+        let addrs: Vec<_> = (0..5)
+            .map(|i| safety_rules_addrs(&lsrs_nodes, i, 1, params.validators_per_lsr, 6185))
+            .collect();
+        assert_eq!(addrs[0], addrs[1]);
+        assert_eq!(addrs[2], vec!["10.0.0.1:6185".to_string()]);
+        assert_eq!(addrs[3], addrs[2]);
+        assert_eq!(addrs[4], vec!["10.0.0.2:6185".to_string()]);
+
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--validators-per-lsr",
+            "0"
+        ])
+        .is_err());
+    }
+
+    struct StaticImageResolver;
+
+    #[async_trait::async_trait]
+    impl ImageResolver for StaticImageResolver {
+        async fn resolve(&self, tag: &str) -> Result<ResolvedImage> {
+            Ok(ResolvedImage {
+                tag: tag.to_string(),
+                digests: LIBRA_IMAGE_REPOSITORIES
+                    .iter()
+                    .map(|repository| (repository.to_string(), format!("sha256:{}", repository)))
+                    .collect(),
+            })
+        }
+    }
+
+    #[test]
+    pub fn test_resolved_image_digests() {
+        let swarm = Arc::new(MockSwarm::default());
+        let builder = ClusterBuilder::shared("stable".to_string(), swarm.clone())
+            .with_image_resolver(Arc::new(StaticImageResolver));
+        let params = ClusterBuilderParamsBuilder::default()
+            .num_validators(1)
+            .fullnodes_per_validator(1)
+            .enable_lsr(false)
+            .build();
+        let resolved = block_on(builder.resolve_image(&params)).unwrap();
+        assert_eq!(resolved.tag, "stable");
+        assert_eq!(resolved.digests.len(), LIBRA_IMAGE_REPOSITORIES.len());
+
+        block_on(builder.spawn_validator_and_fullnode_set(&params, "stable", false)).unwrap();
+        block_on(builder.spawn_validator_and_fullnode_set(&params, "other", false)).unwrap();
+        let spawned = swarm.spawned.lock().unwrap();
+        assert_eq!(spawned.len(), 4);
+        for config in spawned.iter() {
+            match &config.application_config {
+                Validator(ValidatorConfig { image_tag, .. })
+                | Fullnode(FullnodeConfig { image_tag, .. })
+                    if image_tag == "stable" =>
+                {
+                    assert_eq!(config.image_digests, resolved.digests)
+                }
+                // Only the resolved tag is pinned
+                _ => assert!(config.image_digests.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_params_builder() {
+        let params = ClusterBuilderParams::builder()
+            .num_validators(4)
+            .fullnodes_per_validator(2)
+            .cfg(vec!["prune_window=100".parse().unwrap()])
+            .enable_lsr(true)
+            .lsr_backend("on-disk")
+            .build();
+        let parsed = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "4",
+            "--fullnodes-per-validator",
+            "2",
+            "--cfg",
+            "prune_window=100",
+            "--enable-lsr",
+            "true",
+            "--lsr-backend",
+            "on-disk",
+        ]);
+        assert_eq!(params, parsed);
+
+        assert_eq!(
+            ClusterBuilderParams::builder().build(),
+            ClusterBuilderParams::from_iter(vec!["cluster-builder"])
+        );
+    }
+
+    #[test]
+    pub fn test_parse_chain_id() {
+        assert_eq!(parse_chain_id("1").unwrap(), ChainId::new(1));
+        assert_eq!(parse_chain_id("TESTNET").unwrap(), ChainId::new(2));
+        for invalid in &["", "0", "256", "-1", "NOT_A_CHAIN"] {
+            assert!(parse_chain_id(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            parse_chain_id("0").unwrap_err().to_string(),
+            "Invalid chain id 0, 0 is reserved"
+        );
+        let error =
+            ClusterBuilderParams::from_iter_safe(vec!["cluster-builder", "--chain-id", "0"])
+                .unwrap_err();
+        assert!(error.message.contains("0 is reserved"), "{}", error.message);
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder", "--chain-id", "4"]);
+        assert_eq!(params.chain_id(), Some(ChainId::test()));
+    }
+
+    #[test]
+    pub fn test_fullnode_port() {
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        assert_eq!(params.fullnode_port, 6180);
+        let params =
+            ClusterBuilderParams::from_iter(vec!["cluster-builder", "--fullnode-port", "7180"]);
+        assert_eq!(params.fullnode_port, 7180);
+        assert_eq!(
+            parse_fullnode_port("9101").unwrap_err().to_string(),
+            "--fullnode-port 9101 conflicts with the metrics port of the fullnodes"
+        );
+        assert!(parse_fullnode_port(&DEFAULT_JSON_RPC_PORT.to_string()).is_err());
+    }
+
+    #[test]
+    pub fn test_key_types() {
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--key-type",
+            "consensus=ed25519",
+        ]);
+        assert_eq!(
+            params.key_types().get(CONSENSUS_KEY),
+            Some(&KeyType::Ed25519)
+        );
+        assert_eq!(
+            params
+                .key_types()
+                .get(OWNER_KEY)
+                .copied()
+                .unwrap_or_default(),
+            KeyType::Ed25519
+        );
+        assert!(parse_key_type("consensus=bls12381")
+            .unwrap_err()
+            .to_string()
+            .contains("Unsupported key type bls12381"));
+        assert!(parse_key_type("not_a_key=ed25519")
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown key not_a_key"));
+        assert!(parse_key_type("consensus").is_err());
+    }
+
+    #[test]
+    pub fn test_safety_rules_service() {
+        use libra_config::config::{NodeConfig, SafetyRulesService};
+
+        let params = |args: &[&str]| {
+            let mut argv = vec!["cluster-builder"];
+            argv.extend_from_slice(args);
+            ClusterBuilderParams::from_iter(argv)
+        };
+        assert_eq!(
+            params(&[]).safety_rules_service(),
+            SafetyRulesServiceType::Process
+        );
+        assert_eq!(
+            params(&["--enable-lsr", "false"]).safety_rules_service(),
+            SafetyRulesServiceType::Thread
+        );
+
+        let modes = vec![
+            ("local", false, Some(SafetyRulesService::Local)),
+            ("thread", false, Some(SafetyRulesService::Thread)),
+            ("serializer", false, Some(SafetyRulesService::Serializer)),
+            // config-builder sets up the process service from the LSR address
+            ("process", true, None),
+        ];
+        for (mode, enable_lsr, expected) in modes {
+            let service = params(&["--safety-rules-service", mode]).safety_rules_service();
+            assert_eq!(service.to_string(), mode);
+            assert!(service.validate(enable_lsr).is_ok(), "{}", mode);
+            assert!(service.validate(!enable_lsr).is_err(), "{}", mode);
+            let config = service
+                .base_config()
+                .map(|base_config| serde_yaml::from_str::<NodeConfig>(&base_config).unwrap());
+            assert_eq!(
+                config
+                    .as_ref()
+                    .map(|config| config.consensus.safety_rules.service.clone()),
+                expected,
+                "{}",
+                mode
+            );
+            if let Some(config) = config {
+                assert!(config.validator_network.is_some());
+            }
+        }
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--safety-rules-service",
+            "spawned_process"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_namespaces() {
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        assert!(params.namespaces().is_empty());
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--validator-namespace",
+            "validators",
+            "--fullnode-namespace",
+            "fullnodes",
+        ]);
+        assert_eq!(params.namespaces(), vec!["validators", "fullnodes"]);
+    }
+
+    #[test]
+    pub fn test_fullnode_seed_addresses() {
+        let addresses: Vec<_> = (0..4)
+            .map(|i| network_address("val", &format!("10.0.0.{}", i), 6181).unwrap())
+            .collect();
+        assert_eq!(
+            fullnode_seed_addresses(&addresses, 2, 0),
+            vec![addresses[2].clone()]
+        );
+        assert_eq!(
+            fullnode_seed_addresses(&addresses, 2, 2),
+            vec![
+                addresses[2].clone(),
+                addresses[0].clone(),
+                addresses[1].clone()
+            ]
+        );
+        // The own validator is not repeated and the count is clamped
+        assert_eq!(
+            fullnode_seed_addresses(&addresses, 1, 10),
+            vec![
+                addresses[1].clone(),
+                addresses[0].clone(),
+                addresses[2].clone(),
+                addresses[3].clone()
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_run_tags() {
+        assert_eq!(
+            run_tags(Some("run-42"), Some(ChainId::new(1)), "master_0123abcd"),
+            vec![
+                (
+                    "cluster-test/image-tag".to_string(),
+                    "master_0123abcd".to_string()
+                ),
+                ("cluster-test/run-id".to_string(), "run-42".to_string()),
+                (
+                    "cluster-test/chain-id".to_string(),
+                    ChainId::new(1).to_string()
+                ),
+            ]
+        );
+        assert_eq!(run_tags(None, None, "master").len(), 1);
+    }
+
+    #[test]
+    pub fn test_network_mode() {
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        assert_eq!(params.network_mode, NetworkMode::Open);
+        assert_eq!(
+            params.network_mode.publishing_option(),
+            PublishingOption::Open
+        );
+
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--network-mode",
+            "permissioned",
+        ]);
+        assert_eq!(
+            params.network_mode.publishing_option(),
+            PublishingOption::Locked
+        );
+        // The genesis tool parses the flag back to the same option
+        assert_eq!(
+            PublishingOption::from_str(&params.network_mode.publishing_option().to_string())
+                .unwrap(),
+            PublishingOption::Locked
+        );
+
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--network-mode",
+            "closed"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_validator_count_warning() {
+        for num_validators in &[0, 1, 4, 7, 100] {
+            assert_eq!(validator_count_warning(*num_validators), None);
+        }
+        assert_eq!(
+            validator_count_warning(5).unwrap(),
+            "5 validators only tolerate 1 faulty validators, like 4 do. Use 4 or 7 validators \
+             (3f + 1) instead"
+        );
+        assert!(validator_count_warning(3)
+            .unwrap()
+            .contains("Use 1 or 4 validators"));
+
+        let params = |strict: bool| {
+            let mut args = vec!["cluster-builder", "--num-validators", "6"];
+            if strict {
+                args.push("--strict");
+            }
+            ClusterBuilderParams::from_iter(args)
+        };
+        assert!(params(false).validate().is_ok());
+        assert!(params(true).validate().is_err());
+    }
+
+    #[test]
+    pub fn test_max_in_flight_spawns() {
+        use futures::FutureExt;
+
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--max-in-flight-spawns",
+            "0"
+        ])
+        .is_err());
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        assert_eq!(params.max_in_flight_spawns, None);
+
+        let swarm = MockSwarm::default();
+        let vault = |i| InstanceConfig {
+            validator_group: ValidatorGroup::new_for_index(i),
+            application_config: Vault(VaultConfig {}),
+            pod_metadata: Default::default(),
+            env: vec![],
+            namespace: None,
+            image_pull_policy: None,
+            image_digests: Default::default(),
+        };
+        let spawn_limit = Semaphore::new(1);
+        block_on(async {
+            // Waits for the permit held by another spawn
+            let permit = spawn_limit.acquire().await;
+            assert!(spawn_limited(&swarm, Some(&spawn_limit), vault(0))
+                .now_or_never()
+                .is_none());
+            drop(permit);
+            spawn_limited(&swarm, Some(&spawn_limit), vault(1))
+                .await
+                .unwrap();
+            // The permit is given back once spawned
+            assert_eq!(spawn_limit.available_permits(), 1);
+            spawn_limited(&swarm, None, vault(2)).await.unwrap();
+        });
+        let spawned: Vec<_> = swarm
+            .spawned
+            .lock()
+            .unwrap()
+            .iter()
+            .map(InstanceConfig::pod_name)
+            .collect();
+        assert_eq!(spawned, vec!["vault-1", "vault-2"]);
+    }
+
+    #[test]
+    pub fn test_join_allocations_collects_all_errors() {
+        let allocations = || {
+            (0..4).map(|i| async move {
+                if i % 2 == 0 {
+                    Ok(i)
+                } else {
+                    Err(format_err!(
+                        "Failed to allocate node for {}",
+                        validator_pod_name(i)
+                    ))
+                }
+            })
+        };
+        let error = block_on(join_allocations(allocations(), true, false))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("2 failures"));
+        assert!(error.contains(&validator_pod_name(1)));
+        assert!(error.contains(&validator_pod_name(3)));
+
+        let error = block_on(join_allocations(allocations(), false, false))
+            .unwrap_err()
+            .to_string();
+        assert!(!error.contains("failures"));
+
+        let allocated = counters::NODES_ALLOCATED.get();
+        let nodes = block_on(join_allocations(
+            (0..3).map(|i| async move { Ok(i) }),
+            true,
+            false,
+        ))
+        .unwrap();
+        assert_eq!(nodes, vec![0, 1, 2]);
+        // Other tests may allocate nodes concurrently
+        assert!(counters::NODES_ALLOCATED.get() >= allocated + 3);
+
+        // Sequential allocations report errors the same way
+        let error = block_on(join_allocations(allocations(), true, true))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("2 failures"));
+        let error = block_on(join_allocations(allocations(), false, true))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Failed to allocate node for val-1");
+    }
+}
@@ -0,0 +1,367 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Environment of the pods: the config overrides rendered into CFG_OVERRIDES and the
+//! variables set with --pod-env and the per type env flags
+
+use super::*;
+
+/// Override of a single node config value, written KEY=VALUE where VALUE is a toml value.
+/// Bare words are taken as strings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigOverride {
+    pub path: String,
+    pub value: toml::Value,
+}
+
+impl ConfigOverride {
+    pub fn new(path: &str, value: toml::Value) -> Self {
+        Self {
+            path: path.to_string(),
+            value,
+        }
+    }
+
+    /// Deduplicates `overrides` by path, the last value of a path wins and takes the place of
+    /// the first override of the path
+    pub fn merge(overrides: impl IntoIterator<Item = ConfigOverride>) -> Vec<ConfigOverride> {
+        let mut merged: Vec<ConfigOverride> = vec![];
+        for config_override in overrides {
+            match merged.iter_mut().find(|o| o.path == config_override.path) {
+                Some(existing) => existing.value = config_override.value,
+                None => merged.push(config_override),
+            }
+        }
+        merged
+    }
+
+    /// Renders `overrides` back to the KEY=VALUE strings the node configs are overridden with
+    pub fn render(overrides: &[ConfigOverride]) -> Vec<String> {
+        overrides.iter().map(ToString::to_string).collect()
+    }
+}
+
+impl FromStr for ConfigOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (path, value) =
+            parse_key_val(s).map_err(|_| format_err!("Invalid config override: {}", s))?;
+        let mut table: toml::value::Table = match toml::from_str(&format!("value = {}", value)) {
+            Ok(table) => table,
+            Err(_) if is_bare_word(&value) => {
+                return Ok(Self::new(&path, toml::Value::String(value)))
+            }
+            Err(e) => bail!("Invalid value of config override {}: {}", s, e),
+        };
+        match table.remove("value") {
+            Some(value) if table.is_empty() => Ok(Self::new(&path, value)),
+            _ => bail!("Invalid value of config override {}", s),
+        }
+    }
+}
+
+impl fmt::Display for ConfigOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.path, self.value)
+    }
+}
+
+fn is_bare_word(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '/')
+}
+
+/// Node config overrides after merging, overrides are applied in order so later ones win
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResolvedConfig {
+    pub overrides: BTreeMap<String, String>,
+}
+
+impl ResolvedConfig {
+    fn from_overrides(overrides: &[ConfigOverride]) -> Self {
+        Self {
+            overrides: overrides
+                .iter()
+                .map(|o| (o.path.clone(), o.value.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for ResolvedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.overrides
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+pub(super) fn parse_node_cfg(s: &str) -> Result<(String, ConfigOverride)> {
+    let mut parts = s.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(pod_name), Some(config_override)) if !pod_name.is_empty() => {
+            Ok((pod_name.to_string(), config_override.parse()?))
+        }
+        _ => bail!("Invalid node config override {}, expected POD:KEY=VALUE", s),
+    }
+}
+
+pub(super) fn parse_key_val(s: &str) -> Result<(String, String)> {
+    let mut kv = s.splitn(2, '=');
+    match (kv.next(), kv.next()) {
+        (Some(k), Some(v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+        _ => bail!("Invalid key=value pair: {}", s),
+    }
+}
+
+impl ClusterBuilderParams {
+    pub fn cfg_overrides(&self) -> Vec<ConfigOverride> {
+        // Default overrides
+        let defaults = vec![ConfigOverride::new(
+            "prune_window",
+            toml::Value::Integer(50000),
+        )];
+
+        // overrides from the command line
+        ConfigOverride::merge(defaults.into_iter().chain(self.cfg.iter().cloned()))
+    }
+
+    /// Overrides of the given pod: the --node-cfg overrides of the pod merged on top of
+    /// cfg_overrides and, for validators, --active-genesis-file-name, so that they win
+    pub fn cfg_overrides_for(&self, pod_name: &str) -> Vec<ConfigOverride> {
+        // Only the validators get the distributed genesis, fullnodes keep their own
+        let is_validator = pod_validator_index(pod_name)
+            .map_or(false, |index| validator_pod_name(index) == pod_name);
+        let genesis_override = self
+            .active_genesis_file_name
+            .as_ref()
+            .filter(|_| is_validator)
+            .map(|name| {
+                ConfigOverride::new("genesis_file_location", toml::Value::String(name.clone()))
+            });
+        ConfigOverride::merge(
+            self.cfg_overrides()
+                .into_iter()
+                .chain(genesis_override)
+                .chain(
+                    self.node_cfg
+                        .iter()
+                        .filter(|(pod, _)| pod == pod_name)
+                        .map(|(_, config_override)| config_override.clone()),
+                ),
+        )
+    }
+
+    /// Config values each validator and fullnode pod ends up with once CFG_OVERRIDES is applied
+    /// on top of the config generated by config-builder, keyed by pod name
+    pub fn effective_configs(&self) -> Result<HashMap<String, ResolvedConfig>> {
+        let mut pod_names = vec![];
+        for validator_index in 0..self.num_validators {
+            pod_names.push(validator_pod_name(validator_index));
+            for fullnode_index in 0..self.fullnodes_per_validator {
+                pod_names.push(fullnode_pod_name(validator_index, fullnode_index));
+            }
+        }
+        if let Some((pod_name, _)) = self
+            .node_cfg
+            .iter()
+            .find(|(pod, _)| !pod_names.contains(pod))
+        {
+            bail!(
+                "--node-cfg for {}, which is not a validator or fullnode of the cluster",
+                pod_name
+            );
+        }
+        Ok(pod_names
+            .into_iter()
+            .map(|pod_name| {
+                let resolved = ResolvedConfig::from_overrides(&self.cfg_overrides_for(&pod_name));
+                (pod_name, resolved)
+            })
+            .collect())
+    }
+
+    /// Environment of validator pods: --pod-env, the genesis source and --validator-env, later
+    /// variables override earlier ones
+    pub fn validator_pod_env(&self) -> Vec<(String, String)> {
+        let mut env = self.pod_env.clone();
+        if let Some(genesis_url) = &self.genesis_url {
+            env.push(("CFG_GENESIS_URL".to_string(), genesis_url.clone()));
+            env.push((
+                "CFG_GENESIS_PATH".to_string(),
+                self.distributed_genesis_path(),
+            ));
+        }
+        env.extend(self.validator_env.iter().cloned());
+        env
+    }
+
+    /// Environment of fullnode pods, --fullnode-env on top of --pod-env
+    pub fn fullnode_pod_env(&self) -> Vec<(String, String)> {
+        let mut env = self.pod_env.clone();
+        env.extend(self.fullnode_env.iter().cloned());
+        env
+    }
+
+    /// Environment of lsr pods, --lsr-env on top of --pod-env
+    pub fn lsr_pod_env(&self) -> Vec<(String, String)> {
+        let mut env = self.pod_env.clone();
+        env.extend(self.lsr_env.iter().cloned());
+        env
+    }
+
+    pub fn pod_metadata(&self) -> PodMetadata {
+        PodMetadata {
+            labels: self.pod_labels.iter().cloned().collect(),
+            annotations: self.pod_annotations.iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_effective_configs_override_precedence() {
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "2",
+            "--cfg",
+            "prune_window=100,max_block_size=250,max_block_size=500",
+        ]);
+        let configs = params.effective_configs().unwrap();
+        // 2 validators and 2 fullnodes
+        assert_eq!(configs.len(), 4);
+        let config = &configs[&fullnode_pod_name(1, 0)];
+        assert_eq!(config, &configs[&validator_pod_name(0)]);
+        // command line overrides win over the defaults, later overrides win over earlier ones
+        assert_eq!(config.overrides["prune_window"], "100");
+        assert_eq!(config.overrides["max_block_size"], "500");
+
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        let configs = params.effective_configs().unwrap();
+        assert_eq!(
+            configs[&validator_pod_name(0)].overrides["prune_window"],
+            "50000"
+        );
+
+        assert!(
+            ClusterBuilderParams::from_iter_safe(vec!["cluster-builder", "--cfg", "invalid"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    pub fn test_config_override() {
+        let parse = |s: &str| s.parse::<ConfigOverride>().unwrap();
+        assert_eq!(
+            parse("prune_window=100"),
+            ConfigOverride::new("prune_window", toml::Value::Integer(100))
+        );
+        assert_eq!(
+            parse("genesis_file_location=\"genesis.blob\"").value,
+            toml::Value::String("genesis.blob".to_string())
+        );
+        assert_eq!(
+            parse("level=info").value,
+            toml::Value::String("info".to_string())
+        );
+        assert_eq!(parse("enabled=true").value, toml::Value::Boolean(true));
+        assert!("prune_window".parse::<ConfigOverride>().is_err());
+        assert!("=100".parse::<ConfigOverride>().is_err());
+        assert!("location=\"genesis.blob".parse::<ConfigOverride>().is_err());
+
+        let merged = ConfigOverride::merge(vec![
+            parse("prune_window=100"),
+            parse("max_block_size=250"),
+            parse("prune_window=200"),
+        ]);
+        assert_eq!(
+            ConfigOverride::render(&merged),
+            vec!["prune_window=200", "max_block_size=250"]
+        );
+        assert_eq!(
+            parse("location=\"genesis.blob\"").to_string(),
+            "location=\"genesis.blob\""
+        );
+        // Bare words are rendered as toml strings
+        assert_eq!(parse("level=info").to_string(), "level=\"info\"");
+    }
+
+    #[test]
+    pub fn test_node_cfg_overrides() {
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "3",
+            "--cfg",
+            "prune_window=1000",
+            "--node-cfg",
+            "val-1:prune_window=100",
+        ]);
+        let configs = params.effective_configs().unwrap();
+        assert_eq!(
+            configs[&validator_pod_name(1)].overrides["prune_window"],
+            "100"
+        );
+        for pod_name in &[validator_pod_name(0), validator_pod_name(2)] {
+            assert_eq!(configs[pod_name].overrides["prune_window"], "1000");
+        }
+        // The fullnode of the validator keeps the cluster wide value
+        assert_eq!(
+            configs[&fullnode_pod_name(1, 0)].overrides["prune_window"],
+            "1000"
+        );
+        // The pod's override replaces the cluster wide one
+        assert_eq!(
+            ConfigOverride::render(&params.cfg_overrides_for(&validator_pod_name(1))),
+            vec!["prune_window=100"]
+        );
+        assert_eq!(
+            params,
+            ClusterBuilderParams::builder()
+                .num_validators(3)
+                .cfg(vec!["prune_window=1000".parse().unwrap()])
+                .node_cfg("val-1", "prune_window=100".parse().unwrap())
+                .build()
+        );
+
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--node-cfg",
+            "val-9:prune_window=100",
+        ]);
+        assert!(params.effective_configs().is_err());
+        assert!(parse_node_cfg("prune_window=100").is_err());
+        assert!(parse_node_cfg("val-0:prune_window").is_err());
+    }
+
+    #[test]
+    pub fn test_pod_env_precedence() {
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--pod-env",
+            "RUST_LOG=info",
+            "--fullnode-env",
+            "RUST_LOG=debug",
+        ]);
+        let var = |name: &str, value: &str| (name.to_string(), value.to_string());
+        // Later variables override earlier ones when applied to the pod
+        assert_eq!(
+            params.fullnode_pod_env(),
+            vec![var("RUST_LOG", "info"), var("RUST_LOG", "debug")]
+        );
+        assert_eq!(params.validator_pod_env(), vec![var("RUST_LOG", "info")]);
+        assert_eq!(params.lsr_pod_env(), vec![var("RUST_LOG", "info")]);
+    }
+}
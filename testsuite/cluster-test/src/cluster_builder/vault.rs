@@ -0,0 +1,514 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keys of the validators and fullnodes in the vaults backing their LSRs
+
+use super::*;
+
+pub(super) fn resolve_vault_token(
+    token: Option<&str>,
+    token_file: Option<&Path>,
+    env_token: Option<String>,
+) -> Result<String> {
+    if let Some(token) = token {
+        return Ok(token.to_string());
+    }
+    if let Some(path) = token_file {
+        let token = fs::read_to_string(path)
+            .map_err(|e| format_err!("Failed to read {} : {}", path.display(), e))?;
+        let token = token.trim();
+        if token.is_empty() {
+            bail!("No vault token in {}", path.display());
+        }
+        return Ok(token.to_string());
+    }
+    Ok(env_token
+        .filter(|token| !token.is_empty())
+        .unwrap_or_else(|| VAULT_TOKEN.to_string()))
+}
+
+/// Checks that the KV engine mounted at secret/ on the vault has the expected version, as the
+/// vault client otherwise fails with misleading not found errors
+pub(super) async fn verify_vault_kv_version(
+    vault_node: &KubeNode,
+    expected: u32,
+    vault_token: &str,
+) -> Result<()> {
+    let url = format!(
+        "http://{}:{}/v1/sys/mounts",
+        vault_node.internal_ip, VAULT_PORT
+    );
+    let http_client = reqwest::Client::new();
+    let mounts = libra_retrier::retry_async(libra_retrier::fixed_retry_strategy(5000, 15), || {
+        let request = http_client.get(&url).header("X-Vault-Token", vault_token);
+        Box::pin(async move {
+            let mounts: serde_json::Value =
+                request.send().await?.error_for_status()?.json().await?;
+            Ok::<_, anyhow::Error>(mounts)
+        })
+    })
+    .await
+    .map_err(|e| {
+        format_err!(
+            "Failed to list mounts of vault on {}: {}",
+            vault_node.name,
+            e
+        )
+    })?;
+    let version = kv_version_from_mounts(&mounts)?;
+    if version != expected {
+        bail!(
+            "Vault on {} has KV v{} mounted at secret/, expected KV v{}",
+            vault_node.name,
+            version,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// Version of the KV engine mounted at secret/ in the response of /v1/sys/mounts
+fn kv_version_from_mounts(mounts: &serde_json::Value) -> Result<u32> {
+    // Newer vault versions nest the mounts under data
+    let mount = match &mounts["secret/"] {
+        serde_json::Value::Null => &mounts["data"]["secret/"],
+        mount => mount,
+    };
+    if mount["type"] != "kv" {
+        bail!("No KV engine mounted at secret/");
+    }
+    // KV v1 mounts don't always set the version option
+    Ok(mount["options"]["version"]
+        .as_str()
+        .unwrap_or("1")
+        .parse()?)
+}
+
+/// Keys of validator `validator_index` in its vault, scoped by the validator's namespace so
+/// validators sharing a vault don't collide. The first validator also holds the libra root key.
+pub(super) fn vault_keys(validator_index: u32, num_fullnode_keys: u32) -> Vec<String> {
+    let pod_name = validator_pod_name(validator_index);
+    let mut keys: Vec<_> = VALIDATOR_KEYS
+        .iter()
+        .map(|key| format!("{}__{}", pod_name, key))
+        .collect();
+    keys.extend((0..num_fullnode_keys).map(|i| fullnode_network_key(validator_index, i)));
+    if validator_index == 0 {
+        keys.push(format!("{}__{}", LIBRA_ROOT_NAME, LIBRA_ROOT_KEY));
+    }
+    keys
+}
+
+/// Creates the vault keys of the given validator in `storage`, each with its type in `key_types`
+/// or the default key type, and stores the peer id of each fullnode network key next to it.
+/// Returns the created keys with their type
+fn create_vault_keys<S: KVStorage + CryptoStorage>(
+    storage: &mut S,
+    validator_index: u32,
+    num_fullnode_keys: u32,
+    key_types: &HashMap<String, KeyType>,
+) -> Result<Vec<(String, KeyType)>> {
+    let mut created = vec![];
+    for key in vault_keys(validator_index, num_fullnode_keys) {
+        // Vault keys are namespaced as pod__key
+        let key_type = key
+            .rsplit("__")
+            .next()
+            .and_then(|name| key_types.get(name))
+            .copied()
+            .unwrap_or_default();
+        create_key(storage, &key, key_type)
+            .map_err(|e| format_err!("Failed to create {} {} : {}", key_type, key, e))?;
+        created.push((key, key_type));
+    }
+    for fullnode_index in 0..num_fullnode_keys {
+        let key = fullnode_network_key(validator_index, fullnode_index);
+        let peer_id = PeerId::from_identity_public_key(network_public_key(storage, &key)?);
+        let peer_id_name = fullnode_peer_id_name(&key);
+        storage
+            .set(&peer_id_name, Value::String(peer_id.to_string()))
+            .map_err(|e| format_err!("Failed to set {} : {}", peer_id_name, e))?;
+    }
+    Ok(created)
+}
+
+fn rotate_consensus_key<S: CryptoStorage>(
+    storage: &mut S,
+    validator_index: u32,
+) -> Result<Ed25519PublicKey> {
+    let key = format!("{}__{}", validator_pod_name(validator_index), CONSENSUS_KEY);
+    storage
+        .rotate_key(&key)
+        .map_err(|e| format_err!("Failed to rotate {} : {}", key, e))
+}
+
+/// Address the fullnodes of a validator bootstrap from. When the validator has a vault, the noise
+/// key derived from its fullnode network key is appended so fullnodes authenticate their seed.
+pub(super) async fn fullnode_seed_peer_address(
+    validator_index: u32,
+    validator_node: &KubeNode,
+    vault_node: Option<&KubeNode>,
+    address_source: AddressSource,
+    vault_token: &str,
+) -> Result<NetworkAddress> {
+    let address = network_address(
+        &format!("{} fullnode network", validator_pod_name(validator_index)),
+        &validator_node.ip(address_source)?,
+        6181,
+    )?;
+    let vault_node = match vault_node {
+        Some(vault_node) => vault_node,
+        None => return Ok(address),
+    };
+    let addr = vault_node.internal_ip.clone();
+    let vault_token = vault_token.to_string();
+    tokio::task::spawn_blocking(move || {
+        let vault_storage = VaultStorage::new(
+            format!("http://{}:{}", addr, VAULT_PORT),
+            vault_token,
+            None,
+            None,
+        );
+        seed_peer_address(&vault_storage, validator_index, address)
+    })
+    .await?
+}
+
+/// Appends the noise protocols for the fullnode network key of validator `validator_index` in
+/// `storage` to `address`
+fn seed_peer_address<S: CryptoStorage>(
+    storage: &S,
+    validator_index: u32,
+    address: NetworkAddress,
+) -> Result<NetworkAddress> {
+    let key_name = format!(
+        "{}__{}",
+        validator_pod_name(validator_index),
+        FULLNODE_NETWORK_KEY
+    );
+    let public_key = network_public_key(storage, &key_name)
+        .map_err(|e| format_err!("Failed to read {} : {}", key_name, e))?;
+    Ok(address.append_prod_protos(public_key, HANDSHAKE_VERSION))
+}
+
+/// Name of the vault key holding the network identity of a single fullnode
+pub(super) fn fullnode_network_key(validator_index: u32, fullnode_index: u32) -> String {
+    format!(
+        "{}__{}",
+        fullnode_pod_name(validator_index, fullnode_index),
+        FULLNODE_NETWORK_KEY
+    )
+}
+
+/// Name of the vault entry holding the peer id of the fullnode network key `network_key`
+pub(crate) fn fullnode_peer_id_name(network_key: &str) -> String {
+    format!("{}_peer_id", network_key)
+}
+
+/// Client of the vault at `url`, checking up front that the vault can be reached so that an
+/// unreachable vault fails with a single error instead of one per key
+fn connect_vault(url: &str, token: &str) -> Result<VaultStorage> {
+    let vault_storage = VaultStorage::new(url.to_string(), token.to_string(), None, None);
+    vault_storage
+        .available()
+        .map_err(|e| format_err!("Vault unreachable at {} : {}", url, e))?;
+    Ok(vault_storage)
+}
+
+/// Creates `name` in `storage` as a key of the given type
+fn create_key<S: CryptoStorage + ?Sized>(
+    storage: &mut S,
+    name: &str,
+    key_type: KeyType,
+) -> Result<Ed25519PublicKey> {
+    match key_type {
+        KeyType::Ed25519 => Ok(storage.create_key(name)?),
+    }
+}
+
+impl ClusterBuilder {
+    /// Creates the keys of the given validator in its vault, plus a separate network key for each
+    /// of its first `num_fullnode_keys` fullnodes. Keys missing from `key_types` are created with
+    /// the default key type. The vault client is blocking so this holds a thread of the blocking
+    /// pool until done, --vault-init-concurrency bounds how many run at a time
+    pub(super) async fn initialize_vault(
+        &self,
+        validator_index: u32,
+        vault_node: &KubeNode,
+        num_fullnode_keys: u32,
+        key_types: HashMap<String, KeyType>,
+        vault_token: &str,
+    ) -> Result<()> {
+        let addr = vault_node.internal_ip.clone();
+        let vault_token = vault_token.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut vault_storage =
+                connect_vault(&format!("http://{}:{}", addr, VAULT_PORT), &vault_token)?;
+            create_vault_keys(
+                &mut vault_storage,
+                validator_index,
+                num_fullnode_keys,
+                &key_types,
+            )?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Checks that the keys created by initialize_vault are present in vault
+    pub(super) async fn verify_vault_keys(
+        &self,
+        validator_index: u32,
+        vault_node: &KubeNode,
+        num_fullnode_keys: u32,
+        vault_token: &str,
+    ) -> Result<()> {
+        let addr = vault_node.internal_ip.clone();
+        let vault_token = vault_token.to_string();
+        tokio::task::spawn_blocking(move || {
+            let vault_storage = VaultStorage::new(
+                format!("http://{}:{}", addr, VAULT_PORT),
+                vault_token,
+                None,
+                None,
+            );
+            for key in vault_keys(validator_index, num_fullnode_keys) {
+                vault_storage
+                    .get_public_key(&key)
+                    .map_err(|e| format_err!("Key {} not found in vault {} : {}", key, addr, e))?;
+            }
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Rotates the consensus key of validator `validator_index` in its vault and returns the new
+    /// public key. This is a pre-genesis rotation: the validator config on chain keeps the old
+    /// key until genesis is generated again (setup_cluster with --skip-vault-init), rotating the
+    /// key of a live validator additionally needs a set_validator_config transaction.
+    pub async fn rotate_consensus_key(
+        &self,
+        cluster: &Cluster,
+        validator_index: u32,
+    ) -> Result<Ed25519PublicKey> {
+        let vault_index = vault_index(
+            validator_index,
+            cluster.info().validators_per_vault.unwrap_or(1),
+        );
+        let vault = cluster
+            .vault_instances()
+            .iter()
+            .find(|vault| vault.validator_group().index == vault_index)
+            .ok_or_else(|| format_err!("No vault found for validator {}", validator_index))?;
+        let addr = vault.ip().clone();
+        let vault_token = cluster.vault_token().to_string();
+        let public_key = tokio::task::spawn_blocking(move || {
+            let mut vault_storage = VaultStorage::new(
+                format!("http://{}:{}", addr, VAULT_PORT),
+                vault_token,
+                None,
+                None,
+            );
+            rotate_consensus_key(&mut vault_storage, validator_index)
+        })
+        .await??;
+        info!(
+            "Rotated consensus key of {} to {}",
+            validator_pod_name(validator_index),
+            public_key
+        );
+        Ok(public_key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_connect_vault_unreachable() {
+        // Nothing listens on the port once the listener is dropped
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}", port);
+        let error = connect_vault(&url, VAULT_TOKEN).err().unwrap();
+        assert!(
+            error
+                .to_string()
+                .starts_with(&format!("Vault unreachable at {}", url)),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    pub fn test_seed_peer_address() {
+        let mut storage = libra_secure_storage::InMemoryStorage::new();
+        let key_name = format!("{}__{}", validator_pod_name(1), FULLNODE_NETWORK_KEY);
+        storage.create_key(&key_name).unwrap();
+        let expected = network_public_key(&storage, &key_name).unwrap();
+
+        let address = network_address("val-1", "10.0.0.2", 6181).unwrap();
+        let seed_peer_address = seed_peer_address(&storage, 1, address.clone()).unwrap();
+        assert_eq!(seed_peer_address.find_noise_proto(), Some(expected));
+        assert_eq!(
+            seed_peer_address,
+            address.append_prod_protos(expected, HANDSHAKE_VERSION)
+        );
+        assert!(seed_peer_address
+            .to_string()
+            .starts_with("/ip4/10.0.0.2/tcp/6181/ln-noise-ik/"));
+
+        // Validators without a fullnode network key in storage have no seed address
+        assert!(seed_peer_address(
+            &storage,
+            0,
+            network_address("val-0", "10.0.0.1", 6181).unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_create_vault_keys() {
+        let mut storage = libra_secure_storage::InMemoryStorage::new();
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--key-type",
+            "consensus=ed25519",
+        ]);
+        let created = create_vault_keys(&mut storage, 1, 0, &params.key_types()).unwrap();
+        let consensus_key = format!("{}__{}", validator_pod_name(1), CONSENSUS_KEY);
+        assert!(created.contains(&(consensus_key.clone(), KeyType::Ed25519)));
+        assert_eq!(created.len(), VALIDATOR_KEYS.len());
+        assert!(storage.get_public_key(&consensus_key).is_ok());
+    }
+
+    #[test]
+    pub fn test_create_fullnode_peer_ids() {
+        let mut storage = libra_secure_storage::InMemoryStorage::new();
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        create_vault_keys(&mut storage, 1, 2, &params.key_types()).unwrap();
+        for fullnode_index in 0..2 {
+            let key = fullnode_network_key(1, fullnode_index);
+            let public_key = network_public_key(&storage, &key).unwrap();
+            let peer_id = storage
+                .get(&fullnode_peer_id_name(&key))
+                .unwrap()
+                .value
+                .string()
+                .unwrap();
+            assert_eq!(
+                PeerId::try_from(peer_id).unwrap(),
+                PeerId::from_identity_public_key(public_key)
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_rotate_consensus_key() {
+        let mut storage = libra_secure_storage::InMemoryStorage::new();
+        let key = format!("{}__{}", validator_pod_name(2), CONSENSUS_KEY);
+        let original = storage.create_key(&key).unwrap();
+
+        let rotated = rotate_consensus_key(&mut storage, 2).unwrap();
+        assert_ne!(rotated, original);
+        assert_eq!(storage.get_public_key(&key).unwrap().public_key, rotated);
+        assert_eq!(
+            storage.get_public_key_previous_version(&key).unwrap(),
+            original
+        );
+
+        // Validators without a consensus key in storage can't be rotated
+        assert!(rotate_consensus_key(&mut storage, 0).is_err());
+    }
+
+    #[test]
+    pub fn test_vault_kv_version() {
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--vault-kv-version",
+            "1"
+        ])
+        .is_err());
+        let params = ClusterBuilderParams::from_iter(vec!["cluster-builder"]);
+        assert_eq!(params.vault_kv_version, 2);
+
+        let mounts = serde_json::json!({
+            "secret/": {"type": "kv", "options": {"version": "2"}},
+            "transit/": {"type": "transit", "options": null},
+        });
+        assert_eq!(kv_version_from_mounts(&mounts).unwrap(), 2);
+        let mounts = serde_json::json!({
+            "data": {"secret/": {"type": "kv", "options": null}},
+        });
+        assert_eq!(kv_version_from_mounts(&mounts).unwrap(), 1);
+        let mounts = serde_json::json!({"transit/": {"type": "transit"}});
+        assert!(kv_version_from_mounts(&mounts).is_err());
+    }
+
+    #[test]
+    pub fn test_resolve_vault_token() {
+        let token_file = libra_temppath::TempPath::new();
+        token_file.create_as_file().unwrap();
+        fs::write(token_file.path(), "file-token\n").unwrap();
+        let env_token = || Some("env-token".to_string());
+
+        assert_eq!(
+            resolve_vault_token(Some("cli-token"), Some(token_file.path()), env_token()).unwrap(),
+            "cli-token"
+        );
+        assert_eq!(
+            resolve_vault_token(None, Some(token_file.path()), env_token()).unwrap(),
+            "file-token"
+        );
+        assert_eq!(
+            resolve_vault_token(None, None, env_token()).unwrap(),
+            "env-token"
+        );
+        assert_eq!(resolve_vault_token(None, None, None).unwrap(), VAULT_TOKEN);
+        assert_eq!(
+            resolve_vault_token(None, None, Some(String::new())).unwrap(),
+            VAULT_TOKEN
+        );
+
+        fs::write(token_file.path(), "  \n").unwrap();
+        assert!(resolve_vault_token(None, Some(token_file.path()), env_token()).is_err());
+        let missing = libra_temppath::TempPath::new();
+        assert!(resolve_vault_token(None, Some(missing.path()), env_token()).is_err());
+    }
+
+    #[test]
+    pub fn test_shared_vaults() {
+        let params = ClusterBuilderParams::from_iter(vec![
+            "cluster-builder",
+            "--num-validators",
+            "5",
+            "--validators-per-vault",
+            "2",
+        ]);
+        assert_eq!(params.num_vaults(), 3);
+        // 5 validators, 5 LSRs and 3 vaults
+        assert_eq!(params.instance_count(), 13);
+        assert!(ClusterBuilderParams::from_iter_safe(vec![
+            "cluster-builder",
+            "--validators-per-vault",
+            "0"
+        ])
+        .is_err());
+
+        // val-0 and val-1 keep their keys in vault-0, under distinct paths
+        assert_eq!(vault_index(0, params.validators_per_vault), 0);
+        assert_eq!(vault_index(1, params.validators_per_vault), 0);
+        assert_eq!(vault_index(4, params.validators_per_vault), 2);
+        let val_0_keys: HashSet<_> = vault_keys(0, 1).into_iter().collect();
+        let val_1_keys: HashSet<_> = vault_keys(1, 1).into_iter().collect();
+        assert_eq!(val_0_keys.len(), VALIDATOR_KEYS.len() + 2);
+        assert_eq!(val_1_keys.len(), VALIDATOR_KEYS.len() + 1);
+        assert!(val_0_keys.is_disjoint(&val_1_keys));
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+#![forbid(unsafe_code)]
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{bail, format_err, Result};
+use async_trait::async_trait;
+use futures::future::{join_all, try_join_all};
+
+use crate::{
+    cluster_swarm::{
+        cluster_swarm_kube::{KubeNode, NodeFilter},
+        ClusterSwarm, Fault, PodPhase, ProvisioningSwarm,
+    },
+    instance::{pod_validator_index, Instance, InstanceConfig},
+};
+
+/// Spreads a cluster over several k8s clusters. The instances of validator i (validator,
+/// fullnodes, lsrs and vault) all go to swarm i % number of swarms. Genesis is still generated
+/// once by the ClusterBuilder, the validators of different k8s clusters only reach each other
+/// on external addresses (--address-source external).
+pub struct FederatedClusterSwarm<S> {
+    swarms: Vec<S>,
+    // Swarm owning each node returned by allocate_node or get_node_name, used to route node
+    // level operations
+    node_swarms: Mutex<HashMap<String, usize>>,
+}
+
+impl<S: ClusterSwarm> FederatedClusterSwarm<S> {
+    pub fn new(swarms: Vec<S>) -> Result<Self> {
+        if swarms.is_empty() {
+            bail!("FederatedClusterSwarm requires at least one swarm");
+        }
+        Ok(Self {
+            swarms,
+            node_swarms: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn swarms(&self) -> &[S] {
+        &self.swarms
+    }
+
+    fn swarm_index(&self, validator_index: u32) -> usize {
+        validator_index as usize % self.swarms.len()
+    }
+
+    fn swarm_for_pod(&self, pod_name: &str) -> Result<(usize, &S)> {
+        let validator_index = pod_validator_index(pod_name)
+            .ok_or_else(|| format_err!("Can not route pod {} to a swarm", pod_name))?;
+        let index = self.swarm_index(validator_index);
+        Ok((index, &self.swarms[index]))
+    }
+
+    fn record_node(&self, node: &str, index: usize) {
+        self.node_swarms
+            .lock()
+            .expect("node_swarms lock poisoned")
+            .insert(node.to_string(), index);
+    }
+
+    fn swarm_for_node(&self, node: &str) -> Result<&S> {
+        let index = *self
+            .node_swarms
+            .lock()
+            .expect("node_swarms lock poisoned")
+            .get(node)
+            .ok_or_else(|| format_err!("Node {} is not known to any swarm", node))?;
+        Ok(&self.swarms[index])
+    }
+}
+
+#[async_trait]
+impl<S: ClusterSwarm> ClusterSwarm for FederatedClusterSwarm<S> {
+    async fn spawn_new_instance(&self, instance_config: InstanceConfig) -> Result<Instance> {
+        let index = self.swarm_index(instance_config.validator_group.index);
+        self.swarms[index].spawn_new_instance(instance_config).await
+    }
+
+    async fn clean_data(&self, node: &str) -> Result<()> {
+        self.swarm_for_node(node)?.clean_data(node).await
+    }
+
+    async fn get_node_name(&self, pod_name: &str) -> Result<String> {
+        let (index, swarm) = self.swarm_for_pod(pod_name)?;
+        let node = swarm.get_node_name(pod_name).await?;
+        self.record_node(&node, index);
+        Ok(node)
+    }
+
+    async fn get_grafana_baseurl(&self) -> Result<String> {
+        self.swarms[0].get_grafana_baseurl().await
+    }
+
+    async fn put_file(
+        &self,
+        node: &str,
+        pod_name: &str,
+        path: &str,
+        content: Vec<u8>,
+    ) -> Result<()> {
+        self.swarm_for_pod(pod_name)?
+            .1
+            .put_file(node, pod_name, path, content)
+            .await
+    }
+
+    async fn get_pod_phase(&self, pod_name: &str) -> Result<PodPhase> {
+        self.swarm_for_pod(pod_name)?
+            .1
+            .get_pod_phase(pod_name)
+            .await
+    }
+
+    async fn get_container_messages(&self, pod_name: &str) -> Result<Vec<String>> {
+        self.swarm_for_pod(pod_name)?
+            .1
+            .get_container_messages(pod_name)
+            .await
+    }
+
+    async fn delete_pod(&self, pod_name: &str) -> Result<()> {
+        self.swarm_for_pod(pod_name)?.1.delete_pod(pod_name).await
+    }
+
+    async fn respawn_pod(&self, pod_name: &str) -> Result<()> {
+        self.swarm_for_pod(pod_name)?.1.respawn_pod(pod_name).await
+    }
+
+    async fn inject_fault(&self, pod_name: &str, fault: Fault) -> Result<()> {
+        self.swarm_for_pod(pod_name)?
+            .1
+            .inject_fault(pod_name, fault)
+            .await
+    }
+
+    async fn cordon_node(&self, node_name: &str) -> Result<()> {
+        self.swarm_for_node(node_name)?.cordon_node(node_name).await
+    }
+
+    async fn uncordon_node(&self, node_name: &str) -> Result<()> {
+        self.swarm_for_node(node_name)?
+            .uncordon_node(node_name)
+            .await
+    }
+
+    /// Total capacity of the swarms
+    async fn node_capacity(&self) -> Result<u32> {
+        let capacities =
+            try_join_all(self.swarms.iter().map(|swarm| swarm.node_capacity())).await?;
+        Ok(capacities.into_iter().sum())
+    }
+}
+
+#[async_trait]
+impl<S: ProvisioningSwarm> ProvisioningSwarm for FederatedClusterSwarm<S> {
+    async fn allocate_node(
+        &self,
+        pod_name: &str,
+        retries: usize,
+        retry_interval_ms: u64,
+    ) -> Result<KubeNode> {
+        let (index, swarm) = self.swarm_for_pod(pod_name)?;
+        let node = swarm
+            .allocate_node(pod_name, retries, retry_interval_ms)
+            .await?;
+        self.record_node(&node.name, index);
+        Ok(node)
+    }
+
+    async fn cleanup(&self) -> Result<()> {
+        try_join_all(self.swarms.iter().map(|swarm| swarm.cleanup())).await?;
+        Ok(())
+    }
+
+    async fn adopt_pods(&self) -> Result<Vec<String>> {
+        let adopted = try_join_all(self.swarms.iter().map(|swarm| swarm.adopt_pods())).await?;
+        Ok(adopted.into_iter().flatten().collect())
+    }
+
+    /// Workspace of the first swarm, only its ASG is scaled by the ClusterBuilder
+    async fn get_workspace(&self) -> Result<String> {
+        self.swarms[0].get_workspace().await
+    }
+
+    async fn register_namespace(&self, namespace: &str) {
+        join_all(
+            self.swarms
+                .iter()
+                .map(|swarm| swarm.register_namespace(namespace)),
+        )
+        .await;
+    }
+
+    async fn set_node_filter(&self, node_filter: NodeFilter) {
+        join_all(
+            self.swarms
+                .iter()
+                .map(|swarm| swarm.set_node_filter(node_filter.clone())),
+        )
+        .await;
+    }
+
+    async fn set_placement_seed(&self, seed: Option<u64>) {
+        join_all(
+            self.swarms
+                .iter()
+                .map(|swarm| swarm.set_placement_seed(seed)),
+        )
+        .await;
+    }
+
+    async fn create_validator_pdb(&self, namespace: &str, max_unavailable: i32) -> Result<()> {
+        try_join_all(
+            self.swarms
+                .iter()
+                .map(|swarm| swarm.create_validator_pdb(namespace, max_unavailable)),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        instance::{ApplicationConfig::Vault, ValidatorGroup, VaultConfig},
+        test_utils::{block_on, MockSwarm},
+    };
+
+    #[test]
+    pub fn test_federated_cluster_swarm() {
+        let swarm_a = MockSwarm {
+            node_capacity: 2,
+            ..Default::default()
+        };
+        let swarm_b = MockSwarm {
+            node_capacity: 2,
+            pod_nodes: Mutex::new(
+                vec![("val-3".to_string(), "b-node-val-3".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let swarm = FederatedClusterSwarm::new(vec![swarm_a, swarm_b]).unwrap();
+        block_on(async {
+            for i in 0..4 {
+                swarm
+                    .spawn_new_instance(InstanceConfig {
+                        validator_group: ValidatorGroup::new_for_index(i),
+                        application_config: Vault(VaultConfig {}),
+                        pod_metadata: Default::default(),
+                        env: vec![],
+                        namespace: None,
+                        image_pull_policy: None,
+                        image_digests: Default::default(),
+                    })
+                    .await
+                    .unwrap();
+            }
+            let node = swarm.get_node_name("val-3").await.unwrap();
+            swarm.clean_data(&node).await.unwrap();
+            assert!(swarm.clean_data("unknown-node").await.is_err());
+            assert_eq!(swarm.node_capacity().await.unwrap(), 4);
+        });
+
+        let swarms = swarm.swarms();
+        let spawned = |swarm: &MockSwarm| -> Vec<String> {
+            swarm
+                .spawned
+                .lock()
+                .unwrap()
+                .iter()
+                .map(InstanceConfig::pod_name)
+                .collect()
+        };
+        assert_eq!(spawned(&swarms[0]), vec!["vault-0", "vault-2"]);
+        assert_eq!(spawned(&swarms[1]), vec!["vault-1", "vault-3"]);
+        assert!(swarms[0].cleaned.lock().unwrap().is_empty());
+        assert_eq!(*swarms[1].cleaned.lock().unwrap(), vec!["b-node-val-3"]);
+    }
+}
@@ -3,51 +3,107 @@
 
 #![forbid(unsafe_code)]
 
-use std::{collections::HashMap, env, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{bail, format_err, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
-use futures::{future::try_join_all, lock::Mutex};
-use k8s_openapi::api::core::v1::{ConfigMap, Node, Pod, Service};
+use futures::{
+    future::{try_join_all, Future},
+    lock::Mutex,
+};
+use k8s_openapi::{
+    api::{
+        core::v1::{
+            ConfigMap, EnvVar, HTTPGetAction, Node, Pod, Probe, Service, TCPSocketAction,
+            Toleration as PodToleration,
+        },
+        policy::v1beta1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
+    },
+    apimachinery::pkg::{
+        apis::meta::v1::{LabelSelector, ObjectMeta},
+        util::intstr::IntOrString,
+    },
+};
 use kube::{
-    api::{Api, DeleteParams, PostParams},
+    api::{Api, DeleteParams, PatchParams, PostParams},
     client::Client,
     Config,
 };
 use libra_logger::*;
 
-use crate::{cluster_swarm::ClusterSwarm, instance::Instance};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use crate::{
+    cluster::VaultToken,
+    cluster_builder::fullnode_peer_id_name,
+    cluster_swarm::{ClusterSwarm, Fault, PodPhase, ProvisioningSwarm},
+    instance::Instance,
+};
+use rand::{
+    distributions::Alphanumeric, rngs::StdRng, seq::SliceRandom, thread_rng, Rng, SeedableRng,
+};
 
 use crate::instance::{
+    validator_pod_name, vault_index,
     ApplicationConfig::{Fullnode, Validator, Vault, LSR},
-    InstanceConfig,
+    FullnodeConfig, ImagePullPolicy, InstanceConfig, PodMetadata, ReadinessProbe, Toleration,
 };
 use itertools::Itertools;
 use k8s_openapi::api::batch::v1::Job;
 use kube::api::ListParams;
 use libra_config::config::DEFAULT_JSON_RPC_PORT;
 use reqwest::Client as HttpClient;
-use std::{collections::HashSet, convert::TryFrom, process::Command};
+use std::{collections::HashSet, convert::TryFrom, fmt, iter, process::Command, str::FromStr};
 
 use rusoto_core::Region;
 use rusoto_s3::{PutObjectRequest, S3Client, S3};
 use rusoto_sts::WebIdentityProvider;
 
-const DEFAULT_NAMESPACE: &str = "default";
+/// Namespace of the instances that don't set one, jobs always run in it
+pub const DEFAULT_NAMESPACE: &str = "default";
 
 const CFG_SEED: &str = "1337133713371337133713371337133713371337133713371337133713371337";
 const CFG_FULLNODE_SEED: &str = "2674267426742674267426742674267426742674267426742674267426742674";
 
 const ERROR_NOT_FOUND: u16 = 404;
 
+// Data directory of the libra pods, a hostPath volume that outlives the pods on a node
+const LIBRA_DATA_DIR: &str = "/opt/libra/data";
+
+/// Name of the PodDisruptionBudget covering the validators of the run
+const VALIDATOR_PDB_NAME: &str = "libra-validators";
+
+/// iptables chain holding the DROP rules of the network partitions injected into a node
+const PARTITION_CHAIN: &str = "CLUSTER_TEST_PARTITION";
+
+/// Label recording the RUN_ID of the run that created a pod
+const RUN_ID_LABEL: &str = "cluster-test/run-id";
+
+/// CPU requested by validator and fullnode pods, LSR and vault pods don't request resources
+pub const VALIDATOR_CPU_MILLIS: u64 = 7800;
+pub const FULLNODE_CPU_MILLIS: u64 = 7800;
+
 #[derive(Clone)]
 pub struct ClusterSwarmKube {
     client: Client,
     http_client: HttpClient,
     s3_client: S3Client,
     pub node_map: Arc<Mutex<HashMap<String, KubeNode>>>,
+    /// Namespaces cleanup deletes the libra pods and services of, always includes the default one
+    namespaces: Arc<Mutex<BTreeSet<String>>>,
+    /// Namespace of the pods spawned outside of the default namespace. Pod names are unique
+    /// across namespaces, like for node_map.
+    pod_namespaces: Arc<Mutex<HashMap<String, String>>>,
+    /// Nodes allocate_node may pick from
+    node_filter: Arc<Mutex<NodeFilter>>,
+    /// Seed of the order allocate_node considers the nodes in, the order the nodes are listed in
+    /// if not set
+    placement_seed: Arc<Mutex<Option<u64>>>,
 }
 
 impl ClusterSwarmKube {
@@ -76,14 +132,47 @@ impl ClusterSwarmKube {
             rusoto_core::HttpClient::new().expect("failed to create request dispatcher");
         let s3_client = S3Client::new_with(dispatcher, credentials_provider, Region::UsWest2);
         let node_map = Arc::new(Mutex::new(HashMap::new()));
+        let namespaces = Arc::new(Mutex::new(
+            iter::once(DEFAULT_NAMESPACE.to_string()).collect(),
+        ));
         Ok(Self {
             client,
             node_map,
+            namespaces,
+            pod_namespaces: Arc::new(Mutex::new(HashMap::new())),
+            node_filter: Arc::new(Mutex::new(NodeFilter::default())),
+            placement_seed: Arc::new(Mutex::new(None)),
             http_client,
             s3_client,
         })
     }
 
+    /// Makes cleanup also delete the libra pods and services of `namespace`, namespaces
+    /// instances are spawned in are registered automatically
+    pub async fn register_namespace(&self, namespace: &str) {
+        self.namespaces.lock().await.insert(namespace.to_string());
+    }
+
+    /// Restricts the nodes allocate_node picks from, nodes already allocated are kept
+    pub async fn set_node_filter(&self, node_filter: NodeFilter) {
+        *self.node_filter.lock().await = node_filter;
+    }
+
+    /// Makes allocate_node consider the nodes sorted by name and shuffled with `seed`, so that
+    /// allocating the same pods in the same order always gives the same nodes
+    pub async fn set_placement_seed(&self, seed: Option<u64>) {
+        *self.placement_seed.lock().await = seed;
+    }
+
+    async fn pod_namespace(&self, pod_name: &str) -> String {
+        self.pod_namespaces
+            .lock()
+            .await
+            .get(pod_name)
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string())
+    }
+
     fn service_spec(&self, peer_id: String) -> Service {
         let service_yaml = format!(
             include_str!("libra_node_service_template.yaml"),
@@ -98,19 +187,39 @@ impl ClusterSwarmKube {
 
     fn lsr_spec(
         &self,
+        pod_name: &str,
         validator_index: u32,
         num_validators: u32,
+        validators_per_vault: u32,
         node_name: &str,
         image_tag: &str,
         lsr_backend: &str,
+        lsr_port: u32,
+        vault_token: &str,
     ) -> Result<(Pod, Service)> {
+        let validators_per_vault = validators_per_vault.max(1);
+        // Validators sharing a vault keep their safety rules data apart in their own namespace
+        let safety_rules_namespace = if validators_per_vault > 1 {
+            format!(
+                "--safety-rules-namespace={}",
+                validator_pod_name(validator_index)
+            )
+        } else {
+            String::new()
+        };
         let pod_yaml = format!(
             include_str!("lsr_spec_template.yaml"),
+            pod_name = pod_name,
+            lsr_port = lsr_port,
             validator_index = validator_index,
             num_validators = num_validators,
+            vault_index = vault_index(validator_index, validators_per_vault),
+            num_vaults = (num_validators + validators_per_vault - 1) / validators_per_vault,
+            safety_rules_namespace = safety_rules_namespace,
             image_tag = image_tag,
             node_name = node_name,
             lsr_backend = lsr_backend,
+            vault_token = vault_token,
             cfg_seed = CFG_SEED,
         );
         let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml)?;
@@ -119,7 +228,8 @@ impl ClusterSwarmKube {
             .map_err(|e| format_err!("serde_json::from_value failed: {}", e))?;
         let service_yaml = format!(
             include_str!("lsr_service_template.yaml"),
-            validator_index = validator_index,
+            pod_name = pod_name,
+            lsr_port = lsr_port,
         );
         let service_spec: serde_yaml::Value = serde_yaml::from_str(&service_yaml).unwrap();
         let service_spec = serde_json::value::to_value(service_spec).unwrap();
@@ -156,10 +266,12 @@ impl ClusterSwarmKube {
         num_validators: u32,
         num_fullnodes: u32,
         enable_lsr: bool,
+        num_lsrs: u32,
         node_name: &str,
         image_tag: &str,
         seed_peer_ip: &str,
-        safety_rules_addr: &str,
+        safety_rules_addrs: &[String],
+        cfg_base_config: &str,
         cfg_overrides: &str,
     ) -> Result<Pod> {
         let cfg_fullnode_seed = if num_fullnodes > 0 {
@@ -174,43 +286,17 @@ impl ClusterSwarmKube {
             num_validators = num_validators,
             num_fullnodes = num_fullnodes,
             enable_lsr = enable_lsr,
+            num_lsrs = num_lsrs,
             image_tag = image_tag,
             node_name = node_name,
             cfg_overrides = cfg_overrides,
             cfg_seed = CFG_SEED,
             cfg_seed_peer_ip = seed_peer_ip,
-            cfg_safety_rules_addr = safety_rules_addr,
+            cfg_safety_rules_addr = safety_rules_addrs.first().map_or("", String::as_str),
+            cfg_safety_rules_replica_addrs = safety_rules_addrs.join(","),
+            cfg_base_config = cfg_base_config,
             cfg_fullnode_seed = cfg_fullnode_seed,
-        );
-        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml)?;
-        let pod_spec = serde_json::value::to_value(pod_spec)?;
-        serde_json::from_value(pod_spec)
-            .map_err(|e| format_err!("serde_json::from_value failed: {}", e))
-    }
-
-    fn fullnode_spec(
-        &self,
-        fullnode_index: u32,
-        num_fullnodes: u32,
-        validator_index: u32,
-        num_validators: u32,
-        node_name: &str,
-        image_tag: &str,
-        seed_peer_ip: &str,
-        cfg_overrides: &str,
-    ) -> Result<Pod> {
-        let pod_yaml = format!(
-            include_str!("fullnode_spec_template.yaml"),
-            fullnode_index = fullnode_index,
-            num_fullnodes = num_fullnodes,
-            validator_index = validator_index,
-            num_validators = num_validators,
-            node_name = node_name,
-            image_tag = image_tag,
-            cfg_overrides = cfg_overrides,
-            cfg_seed = CFG_SEED,
-            cfg_seed_peer_ip = seed_peer_ip,
-            cfg_fullnode_seed = CFG_FULLNODE_SEED,
+            cpu_millis = VALIDATOR_CPU_MILLIS,
         );
         let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml)?;
         let pod_spec = serde_json::value::to_value(pod_spec)?;
@@ -352,7 +438,7 @@ impl ClusterSwarmKube {
         nodes.into_iter().map(KubeNode::try_from).collect()
     }
 
-    async fn delete_resource<T>(&self, name: &str) -> Result<()>
+    async fn delete_resource<T>(&self, namespace: &str, name: &str) -> Result<()>
     where
         T: k8s_openapi::Resource
             + Clone
@@ -361,8 +447,8 @@ impl ClusterSwarmKube {
             + Send
             + Sync,
     {
-        debug!("Deleting {} {}", T::KIND, name);
-        let resource_api: Api<T> = Api::namespaced(self.client.clone(), DEFAULT_NAMESPACE);
+        debug!("Deleting {} {}/{}", T::KIND, namespace, name);
+        let resource_api: Api<T> = Api::namespaced(self.client.clone(), namespace);
         libra_retrier::retry_async(libra_retrier::fixed_retry_strategy(5000, 60), || {
             let resource_api = resource_api.clone();
             let name = name.to_string();
@@ -488,38 +574,85 @@ impl ClusterSwarmKube {
     }
 
     pub async fn allocate_node(&self, pod_name: &str) -> Result<KubeNode> {
-        libra_retrier::retry_async(libra_retrier::fixed_retry_strategy(5000, 15), || {
-            Box::pin(async move { self.allocate_node_impl(pod_name).await })
-        })
+        self.allocate_node_with_retries(pod_name, 15, 5000).await
+    }
+
+    pub async fn allocate_node_with_retries(
+        &self,
+        pod_name: &str,
+        retries: usize,
+        retry_interval_ms: u64,
+    ) -> Result<KubeNode> {
+        libra_retrier::retry_async(
+            libra_retrier::fixed_retry_strategy(retry_interval_ms, retries),
+            || Box::pin(async move { self.allocate_node_impl(pod_name).await }),
+        )
         .await
     }
 
     async fn allocate_node_impl(&self, pod_name: &str) -> Result<KubeNode> {
-        let nodes = self.list_nodes().await?;
+        let nodes = placement_order(self.list_nodes().await?, *self.placement_seed.lock().await);
         let nodes_count = nodes.len();
         // Holding lock for read-verfy-write to avoid race conditions on this map
         let mut node_map = self.node_map.lock().await;
         if let Some(existed) = node_map.get(pod_name) {
             return Ok(existed.clone());
         }
-        let used_nodes: HashSet<_> = node_map.values().map(|node| &node.name).collect();
-        for node in nodes {
-            if !used_nodes.contains(&node.name) {
+        let node_filter = self.node_filter.lock().await;
+        match free_node(nodes, &node_map, &node_filter) {
+            Some(node) => {
                 node_map.insert(pod_name.to_string(), node.clone());
-                return Ok(node);
+                Ok(node)
             }
+            None => Err(format_err!(
+                "Can not find free node, got total {} nodes",
+                nodes_count
+            )),
         }
-        Err(format_err!(
-            "Can not find free node, got total {} nodes",
-            nodes_count
-        ))
+    }
+
+    async fn set_node_unschedulable(&self, node_name: &str, unschedulable: bool) -> Result<()> {
+        let node_api: Api<Node> = Api::all(self.client.clone());
+        let patch = serde_json::json!({ "spec": { "unschedulable": unschedulable } });
+        node_api
+            .patch(
+                node_name,
+                &PatchParams::default(),
+                serde_json::to_vec(&patch)?,
+            )
+            .await
+            .map_err(|e| match e {
+                kube::Error::Api(ae) if ae.code == ERROR_NOT_FOUND => {
+                    format_err!("Node {} not found", node_name)
+                }
+                e => format_err!(
+                    "Failed to set unschedulable={} on node {}: {}",
+                    unschedulable,
+                    node_name,
+                    e
+                ),
+            })?;
+        Ok(())
     }
 
     pub async fn upsert_node(&self, instance_config: InstanceConfig) -> Result<Instance> {
+        if let Validator(validator_config) = &instance_config.application_config {
+            validator_config.validate()?;
+        }
         let pod_name = instance_config.pod_name();
-        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), DEFAULT_NAMESPACE);
+        let namespace = instance_config.namespace();
+        self.register_namespace(namespace).await;
+        {
+            let mut pod_namespaces = self.pod_namespaces.lock().await;
+            if namespace == DEFAULT_NAMESPACE {
+                pod_namespaces.remove(&pod_name);
+            } else {
+                pod_namespaces.insert(pod_name.clone(), namespace.to_string());
+            }
+        }
+        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), namespace);
         if pod_api.get(&pod_name).await.is_ok() {
-            self.delete_resource::<Pod>(&pod_name).await?;
+            self.delete_resource::<Pod>(namespace, &pod_name).await?;
         }
         let node = self
             .allocate_node(&pod_name)
@@ -542,7 +675,7 @@ impl ClusterSwarmKube {
             _ => {}
         }
         debug!("Creating pod {} on {:?}", pod_name, node);
-        let (p, s): (Pod, Service) = match &instance_config.application_config {
+        let (mut p, s): (Pod, Service) = match &instance_config.application_config {
             Validator(validator_config) => (
                 self.validator_spec(
                     instance_config.validator_group.index,
@@ -550,27 +683,25 @@ impl ClusterSwarmKube {
                     validator_config.num_validators,
                     validator_config.num_fullnodes,
                     validator_config.enable_lsr,
+                    validator_config.num_validators
+                        * validator_config.safety_rules_addrs.len() as u32,
                     &node.name,
                     &validator_config.image_tag,
                     &validator_config.seed_peer_ip,
-                    validator_config
-                        .safety_rules_addr
-                        .as_ref()
-                        .unwrap_or(&"".to_string()),
+                    &validator_config.safety_rules_addrs,
+                    &validator_config
+                        .safety_rules_service
+                        .base_config()
+                        .unwrap_or_default(),
                     &validator_config.config_overrides.iter().join(","),
                 )?,
                 self.service_spec(pod_name.clone()),
             ),
             Fullnode(fullnode_config) => (
-                self.fullnode_spec(
-                    fullnode_config.fullnode_index,
-                    fullnode_config.num_fullnodes_per_validator,
+                fullnode_spec(
+                    fullnode_config,
                     instance_config.validator_group.index_only(),
-                    fullnode_config.num_validators,
                     &node.name,
-                    &fullnode_config.image_tag,
-                    &fullnode_config.seed_peer_ip,
-                    &fullnode_config.config_overrides.iter().join(","),
                 )?,
                 self.service_spec(pod_name.clone()),
             ),
@@ -578,13 +709,45 @@ impl ClusterSwarmKube {
                 self.vault_spec(instance_config.validator_group.index_only(), &node.name)?
             }
             LSR(lsr_config) => self.lsr_spec(
+                &pod_name,
                 instance_config.validator_group.index_only(),
                 lsr_config.num_validators,
+                lsr_config.validators_per_vault,
                 &node.name,
                 &lsr_config.image_tag,
                 &lsr_config.lsr_backend,
+                lsr_config.port,
+                lsr_config.vault_token.as_str(),
             )?,
         };
+        apply_pod_metadata(&mut p, &instance_config.pod_metadata);
+        if let Ok(run_id) = env::var("RUN_ID") {
+            p.metadata
+                .labels
+                .get_or_insert_with(Default::default)
+                .insert(RUN_ID_LABEL.to_string(), run_id);
+        }
+        apply_pod_env(&mut p, &instance_config.env);
+        if let Some(image_pull_policy) = instance_config.image_pull_policy {
+            apply_image_pull_policy(&mut p, image_pull_policy);
+        }
+        apply_image_digests(&mut p, &instance_config.image_digests);
+        let readiness_probe = match &instance_config.application_config {
+            Validator(validator_config) => validator_config.readiness_probe.as_ref(),
+            Fullnode(fullnode_config) => fullnode_config.readiness_probe.as_ref(),
+            LSR(lsr_config) => lsr_config.readiness_probe.as_ref(),
+            // Vault pods come with their own probe in the template
+            Vault(_) => None,
+        };
+        if let Some(readiness_probe) = readiness_probe {
+            apply_readiness_probe(&mut p, readiness_probe);
+        }
+        let tolerations: &[Toleration] = match &instance_config.application_config {
+            Validator(validator_config) => &validator_config.tolerations,
+            Fullnode(fullnode_config) => &fullnode_config.tolerations,
+            _ => &[],
+        };
+        apply_tolerations(&mut p, tolerations);
         match pod_api.create(&PostParams::default(), &p).await {
             Ok(o) => {
                 debug!(
@@ -597,7 +760,7 @@ impl ClusterSwarmKube {
             }
             Err(e) => bail!("Failed to create pod {} : {}", pod_name, e),
         }
-        let service_api: Api<Service> = Api::namespaced(self.client.clone(), DEFAULT_NAMESPACE);
+        let service_api: Api<Service> = Api::namespaced(self.client.clone(), namespace);
         match service_api.create(&PostParams::default(), &s).await {
             Ok(o) => {
                 debug!(
@@ -632,10 +795,7 @@ impl ClusterSwarmKube {
     }
 
     pub async fn delete_node(&self, instance_config: &InstanceConfig) -> Result<()> {
-        let pod_name = instance_config.pod_name();
-        let service_name = pod_name.clone();
-        self.delete_resource::<Pod>(&pod_name).await?;
-        self.delete_resource::<Service>(&service_name).await
+        self.delete_pod(&instance_config.pod_name()).await
     }
 
     async fn remove_all_network_effects(&self) -> Result<()> {
@@ -654,73 +814,175 @@ impl ClusterSwarmKube {
             .map_err(|e| format_err!("remove_all_network_effects: {}", e))
     }
 
+    async fn namespaces(&self) -> Vec<String> {
+        self.namespaces.lock().await.iter().cloned().collect()
+    }
+
+    /// Libra pods of every registered namespace
+    async fn list_libra_pods(&self) -> Result<Vec<Pod>> {
+        let lp = ListParams::default().labels("libra-node=true");
+        let mut pods = vec![];
+        for namespace in self.namespaces().await {
+            let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), &namespace);
+            pods.extend(pod_api.list(&lp).await?.items);
+        }
+        Ok(pods)
+    }
+
+    /// Takes over the libra pods already running instead of cleaning them up: their nodes are
+    /// recorded as allocated to them, so that spawning a pod of the same name replaces it on the
+    /// same node. Returns the names of the adopted pods
+    pub async fn adopt_pods(&self) -> Result<Vec<String>> {
+        let nodes = self.list_nodes().await?;
+        let pods = self.list_libra_pods().await?;
+        let adopted = pod_nodes(&pods, nodes);
+        let mut pod_namespaces = self.pod_namespaces.lock().await;
+        for pod in pods.iter() {
+            if let (Some(name), Some(namespace)) = (&pod.metadata.name, &pod.metadata.namespace) {
+                if adopted.contains_key(name) && namespace != DEFAULT_NAMESPACE {
+                    pod_namespaces.insert(name.clone(), namespace.clone());
+                }
+            }
+        }
+        let mut names: Vec<_> = adopted.keys().cloned().collect();
+        names.sort();
+        self.node_map.lock().await.extend(adopted);
+        Ok(names)
+    }
+
+    /// Pods created by cluster-test that are not part of the current run (RUN_ID), e.g. leftovers
+    /// of runs that were killed before cleaning up. Without RUN_ID every pod is an orphan.
+    pub async fn list_orphans(&self) -> Result<Vec<String>> {
+        let pods = self.list_libra_pods().await?;
+        Ok(orphan_pods(&pods, env::var("RUN_ID").ok().as_deref(), None))
+    }
+
+    /// Deletes the orphaned pods created more than `older_than` ago along with their services,
+    /// returns the names of the deleted pods
+    pub async fn gc_orphans(&self, older_than: Duration) -> Result<Vec<String>> {
+        let created_before = Utc::now() - chrono::Duration::from_std(older_than)?;
+        let pods = self.list_libra_pods().await?;
+        let orphans = orphan_pods(
+            &pods,
+            env::var("RUN_ID").ok().as_deref(),
+            Some(created_before),
+        );
+        let namespaces: HashMap<_, _> = pods
+            .iter()
+            .filter_map(|pod| Some((pod.metadata.name.clone()?, pod.metadata.namespace.clone()?)))
+            .collect();
+        let namespaces = &namespaces;
+        try_join_all(orphans.iter().map(|pod_name| async move {
+            let namespace = namespaces
+                .get(pod_name)
+                .map_or(DEFAULT_NAMESPACE, String::as_str);
+            self.delete_resource::<Pod>(namespace, pod_name).await?;
+            self.delete_resource::<Service>(namespace, pod_name).await
+        }))
+        .await?;
+        info!("Deleted {} orphaned pods: {:?}", orphans.len(), orphans);
+        Ok(orphans)
+    }
+
     pub async fn delete_all(&self) -> Result<()> {
-        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), DEFAULT_NAMESPACE);
-        let pod_names: Vec<String> = pod_api
+        for namespace in self.namespaces().await {
+            self.delete_all_in(&namespace).await?;
+        }
+        let job_api: Api<Job> = Api::namespaced(self.client.clone(), DEFAULT_NAMESPACE);
+        let job_names: Vec<String> = job_api
             .list(&ListParams {
                 label_selector: Some("libra-node=true".to_string()),
                 ..Default::default()
             })
             .await?
             .iter()
-            .map(|pod| -> Result<String, anyhow::Error> {
-                Ok(pod
+            .map(|job| -> Result<String, anyhow::Error> {
+                Ok(job
                     .metadata
                     .name
                     .as_ref()
-                    .ok_or_else(|| format_err!("name not found for pod"))?
+                    .ok_or_else(|| format_err!("name not found for job"))?
                     .clone())
             })
             .collect::<Result<_, _>>()?;
-        let delete_futures = pod_names
+        let delete_futures = job_names
             .iter()
-            .map(|pod_name| self.delete_resource::<Pod>(pod_name));
+            .map(|job_name| self.delete_resource::<Job>(DEFAULT_NAMESPACE, job_name));
         try_join_all(delete_futures).await?;
-        let service_api: Api<Service> = Api::namespaced(self.client.clone(), DEFAULT_NAMESPACE);
-        let service_names: Vec<String> = service_api
+        Ok(())
+    }
+
+    /// Deletes the libra pods and services of `namespace`
+    async fn delete_all_in(&self, namespace: &str) -> Result<()> {
+        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), namespace);
+        let pod_names: Vec<String> = pod_api
             .list(&ListParams {
                 label_selector: Some("libra-node=true".to_string()),
                 ..Default::default()
             })
             .await?
             .iter()
-            .map(|service| -> Result<String, anyhow::Error> {
-                Ok(service
+            .map(|pod| -> Result<String, anyhow::Error> {
+                Ok(pod
                     .metadata
                     .name
                     .as_ref()
-                    .ok_or_else(|| format_err!("name not found for service"))?
+                    .ok_or_else(|| format_err!("name not found for pod"))?
                     .clone())
             })
             .collect::<Result<_, _>>()?;
-        let delete_futures = service_names
+        let delete_futures = pod_names
             .iter()
-            .map(|service_name| self.delete_resource::<Service>(service_name));
+            .map(|pod_name| self.delete_resource::<Pod>(namespace, pod_name));
         try_join_all(delete_futures).await?;
-        let job_api: Api<Job> = Api::namespaced(self.client.clone(), DEFAULT_NAMESPACE);
-        let job_names: Vec<String> = job_api
+        let service_api: Api<Service> = Api::namespaced(self.client.clone(), namespace);
+        let service_names: Vec<String> = service_api
             .list(&ListParams {
                 label_selector: Some("libra-node=true".to_string()),
                 ..Default::default()
             })
             .await?
             .iter()
-            .map(|job| -> Result<String, anyhow::Error> {
-                Ok(job
+            .map(|service| -> Result<String, anyhow::Error> {
+                Ok(service
                     .metadata
                     .name
                     .as_ref()
-                    .ok_or_else(|| format_err!("name not found for job"))?
+                    .ok_or_else(|| format_err!("name not found for service"))?
                     .clone())
             })
             .collect::<Result<_, _>>()?;
-        let delete_futures = job_names
+        let delete_futures = service_names
             .iter()
-            .map(|job_name| self.delete_resource::<Job>(job_name));
+            .map(|service_name| self.delete_resource::<Service>(namespace, service_name));
         try_join_all(delete_futures).await?;
         Ok(())
     }
 
+    /// Creates a PodDisruptionBudget letting at most `max_unavailable` validators of the run in
+    /// `namespace` be evicted at once (e.g. by node drains), replacing the one of a previous run
+    pub async fn create_validator_pdb(&self, namespace: &str, max_unavailable: i32) -> Result<()> {
+        self.delete_resource::<PodDisruptionBudget>(namespace, VALIDATOR_PDB_NAME)
+            .await?;
+        let pdb = validator_pdb(max_unavailable, env::var("RUN_ID").ok().as_deref());
+        let pdb_api: Api<PodDisruptionBudget> = Api::namespaced(self.client.clone(), namespace);
+        pdb_api
+            .create(&PostParams::default(), &pdb)
+            .await
+            .map_err(|e| {
+                format_err!(
+                    "Failed to create PodDisruptionBudget {} : {}",
+                    VALIDATOR_PDB_NAME,
+                    e
+                )
+            })?;
+        info!(
+            "Created PodDisruptionBudget {} with maxUnavailable {}",
+            VALIDATOR_PDB_NAME, max_unavailable
+        );
+        Ok(())
+    }
+
     /// Runs command on the provided host in separate utility container based on cluster-test-util image
     pub async fn util_cmd<S: AsRef<str>>(
         &self,
@@ -772,8 +1034,21 @@ impl ClusterSwarm for ClusterSwarmKube {
     }
 
     async fn clean_data(&self, node: &str) -> Result<()> {
-        self.util_cmd("rm -rf /opt/libra/data/*", node, "clean-data")
-            .await
+        // rm -rf succeeds on already clean data, so retrying a partially applied clean is safe
+        clean_and_verify(
+            libra_retrier::fixed_retry_strategy(5000, 15),
+            || self.util_cmd(format!("rm -rf {}/*", LIBRA_DATA_DIR), node, "clean-data"),
+            || async move {
+                // The job fails unless the data directory is empty
+                let check = format!("test $(ls {} | wc -l) -eq 0", LIBRA_DATA_DIR);
+                Ok(self
+                    .util_cmd(check, node, "verify-clean-data")
+                    .await
+                    .is_ok())
+            },
+        )
+        .await
+        .map_err(|e| format_err!("Failed to clean data on node {}: {}", node, e))
     }
 
     async fn get_node_name(&self, pod_name: &str) -> Result<String> {
@@ -781,6 +1056,99 @@ impl ClusterSwarm for ClusterSwarmKube {
         Ok(node.name)
     }
 
+    async fn get_pod_phase(&self, pod_name: &str) -> Result<PodPhase> {
+        let namespace = self.pod_namespace(pod_name).await;
+        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), &namespace);
+        let pod = pod_api
+            .get(pod_name)
+            .await
+            .map_err(|e| format_err!("Failed to get pod {}: {}", pod_name, e))?;
+        Ok(pod
+            .status
+            .and_then(|status| status.phase)
+            .map_or(PodPhase::Unknown, |phase| PodPhase::from(phase.as_str())))
+    }
+
+    async fn get_container_messages(&self, pod_name: &str) -> Result<Vec<String>> {
+        let namespace = self.pod_namespace(pod_name).await;
+        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), &namespace);
+        let pod = pod_api
+            .get(pod_name)
+            .await
+            .map_err(|e| format_err!("Failed to get pod {}: {}", pod_name, e))?;
+        Ok(container_messages(&pod))
+    }
+
+    async fn delete_pod(&self, pod_name: &str) -> Result<()> {
+        let namespace = self.pod_namespace(pod_name).await;
+        self.delete_resource::<Pod>(&namespace, pod_name).await?;
+        self.delete_resource::<Service>(&namespace, pod_name).await
+    }
+
+    async fn respawn_pod(&self, pod_name: &str) -> Result<()> {
+        let namespace = self.pod_namespace(pod_name).await;
+        let pod_api: Api<Pod> = Api::namespaced(self.client.clone(), &namespace);
+        let pod = pod_api
+            .get(pod_name)
+            .await
+            .map_err(|e| format_err!("Failed to get pod {}: {}", pod_name, e))?;
+        self.delete_resource::<Pod>(&namespace, pod_name).await?;
+        self.node_map.lock().await.remove(pod_name);
+        let node = self
+            .allocate_node(pod_name)
+            .await
+            .map_err(|e| format_err!("Failed to allocate node: {}", e))?;
+        if let Some(input_tag) = fluentbit_input_tag(&pod) {
+            self.config_fluentbit(input_tag, pod_name, &node.name)
+                .await?;
+        }
+        pod_api
+            .create(&PostParams::default(), &respawned_pod(pod, &node.name))
+            .await
+            .map_err(|e| format_err!("Failed to create pod {} : {}", pod_name, e))?;
+        info!("Respawned pod {} on node {}", pod_name, node.name);
+        Ok(())
+    }
+
+    async fn inject_fault(&self, pod_name: &str, fault: Fault) -> Result<()> {
+        let command = fault_command(pod_name, &fault)?;
+        let node = self
+            .node_map
+            .lock()
+            .await
+            .get(pod_name)
+            .cloned()
+            .ok_or_else(|| format_err!("Can not inject {:?}, {} has no node", fault, pod_name))?;
+        self.util_cmd(command, &node.name, "fault")
+            .await
+            .map_err(|e| format_err!("Failed to inject {:?} into {}: {}", fault, pod_name, e))?;
+        info!("Injected {:?} into {}", fault, pod_name);
+        Ok(())
+    }
+
+    async fn cordon_node(&self, node_name: &str) -> Result<()> {
+        self.set_node_unschedulable(node_name, true).await?;
+        // Pods are pinned to their node with nodeName, which bypasses the scheduler, so forget
+        // the allocations on this node for the pods to get a new one when they are respawned
+        self.node_map
+            .lock()
+            .await
+            .retain(|_, node| node.name != node_name);
+        info!("Cordoned node {}", node_name);
+        Ok(())
+    }
+
+    async fn uncordon_node(&self, node_name: &str) -> Result<()> {
+        self.set_node_unschedulable(node_name, false).await?;
+        info!("Uncordoned node {}", node_name);
+        Ok(())
+    }
+
+    async fn node_capacity(&self) -> Result<u32> {
+        let nodes = self.list_nodes().await?;
+        Ok(nodes.iter().filter(|node| !node.unschedulable).count() as u32)
+    }
+
     async fn get_grafana_baseurl(&self) -> Result<String> {
         let workspace = self.get_workspace().await?;
         Ok(format!(
@@ -831,11 +1199,444 @@ impl ClusterSwarm for ClusterSwarmKube {
     }
 }
 
+#[async_trait]
+impl ProvisioningSwarm for ClusterSwarmKube {
+    async fn allocate_node(
+        &self,
+        pod_name: &str,
+        retries: usize,
+        retry_interval_ms: u64,
+    ) -> Result<KubeNode> {
+        self.allocate_node_with_retries(pod_name, retries, retry_interval_ms)
+            .await
+    }
+
+    async fn cleanup(&self) -> Result<()> {
+        ClusterSwarmKube::cleanup(self).await
+    }
+
+    async fn adopt_pods(&self) -> Result<Vec<String>> {
+        ClusterSwarmKube::adopt_pods(self).await
+    }
+
+    async fn get_workspace(&self) -> Result<String> {
+        ClusterSwarmKube::get_workspace(self).await
+    }
+
+    async fn register_namespace(&self, namespace: &str) {
+        ClusterSwarmKube::register_namespace(self, namespace).await
+    }
+
+    async fn set_node_filter(&self, node_filter: NodeFilter) {
+        ClusterSwarmKube::set_node_filter(self, node_filter).await
+    }
+
+    async fn set_placement_seed(&self, seed: Option<u64>) {
+        ClusterSwarmKube::set_placement_seed(self, seed).await
+    }
+
+    async fn create_validator_pdb(&self, namespace: &str, max_unavailable: i32) -> Result<()> {
+        ClusterSwarmKube::create_validator_pdb(self, namespace, max_unavailable).await
+    }
+}
+
+/// Shell command injecting `fault` into the pod, run in a util job on the node of the pod. The
+/// util jobs share the PID and network namespaces of the node, like the pods themselves, so the
+/// signals and iptables rules reach the pod.
+fn fault_command(pod_name: &str, fault: &Fault) -> Result<String> {
+    let process = || match pod_name.split('-').next() {
+        Some("val") | Some("fn") => Ok("libra-node"),
+        Some("lsr") => Ok("safety-rules"),
+        Some("vault") => Ok("vault"),
+        _ => Err(format_err!("Unknown main process for pod {}", pod_name)),
+    };
+    Ok(match fault {
+        Fault::Pause => format!("pkill -STOP -x {}", process()?),
+        Fault::Resume => format!("pkill -CONT -x {}", process()?),
+        Fault::Kill => format!("pkill -KILL -x {}", process()?),
+        Fault::NetworkPartition(ips) => {
+            let mut command = format!(
+                "iptables -N {chain} || true; \
+                 iptables -C INPUT -j {chain} || iptables -I INPUT -j {chain}; \
+                 iptables -C OUTPUT -j {chain} || iptables -I OUTPUT -j {chain}",
+                chain = PARTITION_CHAIN
+            );
+            for ip in ips {
+                command.push_str(&format!(
+                    "; iptables -A {chain} -s {ip} -j DROP; iptables -A {chain} -d {ip} -j DROP",
+                    chain = PARTITION_CHAIN,
+                    ip = ip
+                ));
+            }
+            command
+        }
+        Fault::HealPartition => format!("iptables -F {} || true", PARTITION_CHAIN),
+    })
+}
+
+/// "container: reason: message" of the init and regular containers of the pod that are waiting
+/// or terminated
+fn container_messages(pod: &Pod) -> Vec<String> {
+    let status = match &pod.status {
+        Some(status) => status,
+        None => return vec![],
+    };
+    status
+        .init_container_statuses
+        .iter()
+        .chain(status.container_statuses.iter())
+        .flatten()
+        .filter_map(|container| {
+            let state = container.state.as_ref()?;
+            let (reason, message) = if let Some(waiting) = &state.waiting {
+                (waiting.reason.clone(), waiting.message.clone())
+            } else if let Some(terminated) = &state.terminated {
+                (
+                    terminated
+                        .reason
+                        .clone()
+                        .or_else(|| Some(format!("exit code {}", terminated.exit_code))),
+                    terminated.message.clone(),
+                )
+            } else {
+                return None;
+            };
+            let mut description = container.name.clone();
+            for part in reason.iter().chain(message.iter()) {
+                description.push_str(": ");
+                description.push_str(part);
+            }
+            Some(description)
+        })
+        .collect()
+}
+
+/// Node each pod runs on, by pod name. Pods of jobs and pods on nodes that are not in `nodes`
+/// are left out
+fn pod_nodes(pods: &[Pod], nodes: Vec<KubeNode>) -> HashMap<String, KubeNode> {
+    let nodes: HashMap<_, _> = nodes
+        .into_iter()
+        .map(|node| (node.name.clone(), node))
+        .collect();
+    pods.iter()
+        .filter(|pod| {
+            !pod.metadata
+                .labels
+                .as_ref()
+                .map_or(false, |labels| labels.contains_key("job-name"))
+        })
+        .filter_map(|pod| {
+            let name = pod.metadata.name.clone()?;
+            let node_name = pod.spec.as_ref()?.node_name.as_ref()?;
+            Some((name, nodes.get(node_name)?.clone()))
+        })
+        .collect()
+}
+
+/// PodDisruptionBudget of the validator pods labeled with `run_id`, all validator pods without
+/// one
+fn validator_pdb(max_unavailable: i32, run_id: Option<&str>) -> PodDisruptionBudget {
+    let mut match_labels = BTreeMap::new();
+    match_labels.insert("app".to_string(), "libra-validator".to_string());
+    if let Some(run_id) = run_id {
+        match_labels.insert(RUN_ID_LABEL.to_string(), run_id.to_string());
+    }
+    PodDisruptionBudget {
+        metadata: ObjectMeta {
+            name: Some(VALIDATOR_PDB_NAME.to_string()),
+            ..Default::default()
+        },
+        spec: Some(PodDisruptionBudgetSpec {
+            max_unavailable: Some(IntOrString::Int(max_unavailable)),
+            selector: Some(LabelSelector {
+                match_labels: Some(match_labels),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Names of the pods not labeled with `run_id`, only the ones created before `created_before`
+/// if set
+fn orphan_pods(
+    pods: &[Pod],
+    run_id: Option<&str>,
+    created_before: Option<DateTime<Utc>>,
+) -> Vec<String> {
+    pods.iter()
+        .filter(|pod| {
+            let pod_run_id = pod
+                .metadata
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(RUN_ID_LABEL));
+            run_id.is_none() || pod_run_id.map(String::as_str) != run_id
+        })
+        .filter(|pod| match created_before {
+            Some(created_before) => pod
+                .metadata
+                .creation_timestamp
+                .as_ref()
+                .map_or(false, |created| created.0 < created_before),
+            None => true,
+        })
+        .filter_map(|pod| pod.metadata.name.clone())
+        .collect()
+}
+
+/// Adds the custom labels and annotations to the pod, labels used internally (e.g. libra-node,
+/// peer_id) are never overridden
+fn apply_pod_metadata(pod: &mut Pod, pod_metadata: &PodMetadata) {
+    let labels = pod.metadata.labels.get_or_insert_with(Default::default);
+    for (k, v) in pod_metadata.labels.iter() {
+        labels.entry(k.clone()).or_insert_with(|| v.clone());
+    }
+    let annotations = pod
+        .metadata
+        .annotations
+        .get_or_insert_with(Default::default);
+    for (k, v) in pod_metadata.annotations.iter() {
+        annotations.entry(k.clone()).or_insert_with(|| v.clone());
+    }
+}
+
+/// Copy of `pod` pinned to `node`, without the fields kubernetes sets on the pods it created
+fn respawned_pod(pod: Pod, node: &str) -> Pod {
+    let mut spec = pod.spec.unwrap_or_default();
+    spec.node_name = Some(node.to_string());
+    Pod {
+        metadata: ObjectMeta {
+            name: pod.metadata.name,
+            namespace: pod.metadata.namespace,
+            labels: pod.metadata.labels,
+            annotations: pod.metadata.annotations,
+            ..Default::default()
+        },
+        spec: Some(spec),
+        status: None,
+    }
+}
+
+/// Input tag of the fluent-bit sidecar, as configured by upsert_node, from the app label
+fn fluentbit_input_tag(pod: &Pod) -> Option<&'static str> {
+    match pod.metadata.labels.as_ref()?.get("app")?.as_str() {
+        "libra-validator" | "libra-fullnode" => Some("validator-events"),
+        "libra-lsr" => Some("safety-rules-events"),
+        _ => None,
+    }
+}
+
+/// Sets the readiness probe of the main container
+fn apply_readiness_probe(pod: &mut Pod, readiness_probe: &ReadinessProbe) {
+    let port = IntOrString::Int(readiness_probe.port as i32);
+    let (http_get, tcp_socket) = match &readiness_probe.path {
+        Some(path) => (
+            Some(HTTPGetAction {
+                host: None,
+                http_headers: None,
+                path: Some(path.clone()),
+                port,
+                scheme: None,
+            }),
+            None,
+        ),
+        None => (None, Some(TCPSocketAction { host: None, port })),
+    };
+    let probe = Probe {
+        http_get,
+        tcp_socket,
+        initial_delay_seconds: Some(readiness_probe.initial_delay_secs as i32),
+        period_seconds: Some(readiness_probe.period_secs as i32),
+        ..Default::default()
+    };
+    for container in pod
+        .spec
+        .iter_mut()
+        .flat_map(|spec| spec.containers.iter_mut())
+        .filter(|container| container.name == "main")
+    {
+        container.readiness_probe = Some(probe.clone());
+    }
+}
+
+/// Adds the tolerations to the pod next to the ones of its template
+fn apply_tolerations(pod: &mut Pod, tolerations: &[Toleration]) {
+    if tolerations.is_empty() {
+        return;
+    }
+    for spec in pod.spec.iter_mut() {
+        let pod_tolerations = spec.tolerations.get_or_insert_with(Vec::new);
+        for toleration in tolerations {
+            let operator = if toleration.value.is_some() {
+                "Equal"
+            } else {
+                "Exists"
+            };
+            let toleration = PodToleration {
+                effect: toleration.effect.clone(),
+                key: Some(toleration.key.clone()),
+                operator: Some(operator.to_string()),
+                toleration_seconds: None,
+                value: toleration.value.clone(),
+            };
+            if !pod_tolerations.contains(&toleration) {
+                pod_tolerations.push(toleration);
+            }
+        }
+    }
+}
+
+/// Sets the pull policy of the containers running libra images, the init and main containers
+fn apply_image_pull_policy(pod: &mut Pod, image_pull_policy: ImagePullPolicy) {
+    for spec in pod.spec.iter_mut() {
+        for container in spec
+            .init_containers
+            .iter_mut()
+            .flatten()
+            .chain(spec.containers.iter_mut())
+            .filter(|container| container.name == "init" || container.name == "main")
+        {
+            container.image_pull_policy = Some(image_pull_policy.to_string());
+        }
+    }
+}
+
+/// Pins the images of the containers to `digests`: `registry/repository:tag` becomes
+/// `registry/repository@digest` for the repositories `digests` has a digest for
+fn apply_image_digests(pod: &mut Pod, digests: &BTreeMap<String, String>) {
+    if digests.is_empty() {
+        return;
+    }
+    for spec in pod.spec.iter_mut() {
+        for container in spec
+            .init_containers
+            .iter_mut()
+            .flatten()
+            .chain(spec.containers.iter_mut())
+        {
+            let image = match &container.image {
+                Some(image) => image,
+                None => continue,
+            };
+            let (registry, name) = match image.rfind('/') {
+                Some(i) => image.split_at(i + 1),
+                None => ("", image.as_str()),
+            };
+            let repository = match name.find(|c| c == ':' || c == '@') {
+                Some(i) => &name[..i],
+                None => name,
+            };
+            if let Some(digest) = digests.get(repository) {
+                container.image = Some(format!("{}{}@{}", registry, repository, digest));
+            }
+        }
+    }
+}
+
+/// Sets the given environment variables on all containers of the pod, init containers excluded
+fn apply_pod_env(pod: &mut Pod, env: &[(String, String)]) {
+    if env.is_empty() {
+        return;
+    }
+    for container in pod
+        .spec
+        .iter_mut()
+        .flat_map(|spec| spec.containers.iter_mut())
+    {
+        let container_env = container.env.get_or_insert_with(Vec::new);
+        for (name, value) in env {
+            match container_env.iter_mut().find(|var| &var.name == name) {
+                Some(var) => {
+                    var.value = Some(value.clone());
+                    var.value_from = None;
+                }
+                None => container_env.push(EnvVar {
+                    name: name.clone(),
+                    value: Some(value.clone()),
+                    value_from: None,
+                }),
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct KubeNode {
     pub name: String,
     pub provider_id: String,
     pub internal_ip: String,
+    pub external_ip: Option<String>,
+    pub unschedulable: bool,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Nodes pods may be allocated to. Entries are node names or key=value node labels, a node is
+/// permitted when it matches an allowed entry (any node if none is set) and no denied one
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodeFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl NodeFilter {
+    pub fn permits(&self, node: &KubeNode) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|entry| node.matches(entry)))
+            && !self.deny.iter().any(|entry| node.matches(entry))
+    }
+}
+
+/// Which of the node addresses is used to reach a node
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressSource {
+    Internal,
+    External,
+}
+
+impl FromStr for AddressSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "internal" => Ok(AddressSource::Internal),
+            "external" => Ok(AddressSource::External),
+            _ => bail!(
+                "Invalid address source {}, expected internal or external",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for AddressSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressSource::Internal => write!(f, "internal"),
+            AddressSource::External => write!(f, "external"),
+        }
+    }
+}
+
+impl KubeNode {
+    /// Whether the node is named `entry` or has the label `entry` when it is a key=value pair
+    fn matches(&self, entry: &str) -> bool {
+        let mut kv = entry.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => self.labels.get(key).map(String::as_str) == Some(value),
+            _ => self.name == entry,
+        }
+    }
+
+    pub fn ip(&self, source: AddressSource) -> Result<String> {
+        match source {
+            AddressSource::Internal => Ok(self.internal_ip.clone()),
+            AddressSource::External => self
+                .external_ip
+                .clone()
+                .ok_or_else(|| format_err!("external address not found for node {}", self.name)),
+        }
+    }
 }
 
 impl TryFrom<Node> for KubeNode {
@@ -849,9 +1650,11 @@ impl TryFrom<Node> for KubeNode {
         let provider_id = spec
             .provider_id
             .ok_or_else(|| format_err!("provider_id not found for node"))?;
+        let unschedulable = spec.unschedulable.unwrap_or(false);
         let name = metadata
             .name
             .ok_or_else(|| format_err!("node name not found"))?;
+        let labels = metadata.labels.unwrap_or_default().into_iter().collect();
         let status = node
             .status
             .ok_or_else(|| format_err!("status not found for node"))?;
@@ -863,10 +1666,864 @@ impl TryFrom<Node> for KubeNode {
             .find(|a| a.type_ == "InternalIP")
             .ok_or_else(|| format_err!("internal address not found"))?;
         let internal_ip = internal_address.address.clone();
+        let external_ip = addresses
+            .iter()
+            .find(|a| a.type_ == "ExternalIP")
+            .map(|a| a.address.clone());
         Ok(Self {
             name,
             provider_id,
             internal_ip,
+            external_ip,
+            unschedulable,
+            labels,
+        })
+    }
+}
+
+/// Runs `clean` until `is_clean` confirms the data is gone, retrying with `retry_strategy`. Fails
+/// with the last error once the retries are exhausted.
+async fn clean_and_verify<I, C, CFut, V, VFut>(
+    retry_strategy: I,
+    clean: C,
+    is_clean: V,
+) -> Result<()>
+where
+    I: IntoIterator<Item = Duration>,
+    C: Fn() -> CFut + Sync,
+    CFut: Future<Output = Result<()>> + Send,
+    V: Fn() -> VFut + Sync,
+    VFut: Future<Output = Result<bool>> + Send,
+{
+    let (clean, is_clean) = (&clean, &is_clean);
+    libra_retrier::retry_async(retry_strategy, || {
+        Box::pin(async move {
+            clean().await?;
+            if !is_clean().await? {
+                bail!(
+                    "could not confirm {} is empty after cleaning",
+                    LIBRA_DATA_DIR
+                );
+            }
+            Ok(())
         })
+    })
+    .await
+}
+
+/// Pod of fullnode `fullnode_config` of validator `validator_index`, placed on `node_name`
+fn fullnode_spec(
+    fullnode_config: &FullnodeConfig,
+    validator_index: u32,
+    node_name: &str,
+) -> Result<Pod> {
+    let pod_yaml = format!(
+        include_str!("fullnode_spec_template.yaml"),
+        fullnode_index = fullnode_config.fullnode_index,
+        num_fullnodes = fullnode_config.num_fullnodes_per_validator,
+        validator_index = validator_index,
+        num_validators = fullnode_config.num_validators,
+        node_name = node_name,
+        image_tag = fullnode_config.image_tag,
+        cfg_overrides = fullnode_config.config_overrides.iter().join(","),
+        cfg_seed = CFG_SEED,
+        cfg_seed_peer_addr = fullnode_config
+            .seed_peer_addresses
+            .first()
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        cfg_seed_peer_addrs = fullnode_config.seed_peer_addresses.iter().join(","),
+        cfg_fullnode_seed = CFG_FULLNODE_SEED,
+        cfg_fullnode_network_key = fullnode_config.network_key.as_deref().unwrap_or(""),
+        cfg_fullnode_peer_id = fullnode_config
+            .network_key
+            .as_deref()
+            .map(fullnode_peer_id_name)
+            .unwrap_or_default(),
+        cfg_vault_addr = fullnode_config.vault_addr.as_deref().unwrap_or(""),
+        cfg_vault_token = fullnode_config
+            .vault_token
+            .as_ref()
+            .map_or("", VaultToken::as_str),
+        listen_port = fullnode_config.listen_port,
+        cpu_millis = FULLNODE_CPU_MILLIS,
+    );
+    let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml)?;
+    let pod_spec = serde_json::value::to_value(pod_spec)?;
+    serde_json::from_value(pod_spec)
+        .map_err(|e| format_err!("serde_json::from_value failed: {}", e))
+}
+
+/// Nodes in the order allocate_node considers them: as listed without a seed, otherwise sorted
+/// by name and shuffled with the seed
+fn placement_order(mut nodes: Vec<KubeNode>, seed: Option<u64>) -> Vec<KubeNode> {
+    if let Some(seed) = seed {
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        nodes.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+    nodes
+}
+
+/// First schedulable node permitted by `node_filter` and not yet allocated to a pod in `node_map`
+fn free_node(
+    nodes: Vec<KubeNode>,
+    node_map: &HashMap<String, KubeNode>,
+    node_filter: &NodeFilter,
+) -> Option<KubeNode> {
+    let used_nodes: HashSet<_> = node_map.values().map(|node| &node.name).collect();
+    nodes.into_iter().find(|node| {
+        !node.unschedulable && !used_nodes.contains(&node.name) && node_filter.permits(node)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k8s_openapi::api::core::v1::Container;
+    use libra_network_address::NetworkAddress;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    pub fn test_validator_pdb() {
+        let pdb = validator_pdb(1, Some("run-1"));
+        assert_eq!(pdb.metadata.name.unwrap(), VALIDATOR_PDB_NAME);
+        let spec = pdb.spec.unwrap();
+        assert_eq!(spec.max_unavailable, Some(IntOrString::Int(1)));
+        let match_labels = spec.selector.unwrap().match_labels.unwrap();
+        assert_eq!(match_labels["app"], "libra-validator");
+        assert_eq!(match_labels[RUN_ID_LABEL], "run-1");
+
+        let pdb = validator_pdb(2, None);
+        let match_labels = pdb.spec.unwrap().selector.unwrap().match_labels.unwrap();
+        assert!(!match_labels.contains_key(RUN_ID_LABEL));
+    }
+
+    #[test]
+    pub fn test_fault_command() {
+        assert_eq!(
+            fault_command("val-3", &Fault::Pause).unwrap(),
+            "pkill -STOP -x libra-node"
+        );
+        assert_eq!(
+            fault_command("lsr-0", &Fault::Kill).unwrap(),
+            "pkill -KILL -x safety-rules"
+        );
+        assert!(fault_command("other-0", &Fault::Resume).is_err());
+        let command = fault_command(
+            "fn-1-0",
+            &Fault::NetworkPartition(vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()]),
+        )
+        .unwrap();
+        assert!(command.starts_with("iptables -N CLUSTER_TEST_PARTITION || true; "));
+        assert!(command.ends_with(
+            "iptables -A CLUSTER_TEST_PARTITION -s 10.0.0.2 -j DROP; \
+             iptables -A CLUSTER_TEST_PARTITION -d 10.0.0.2 -j DROP"
+        ));
+        assert!(command.contains("-s 10.0.0.1 -j DROP"));
+        assert_eq!(
+            fault_command("anything", &Fault::HealPartition).unwrap(),
+            "iptables -F CLUSTER_TEST_PARTITION || true"
+        );
+    }
+
+    #[test]
+    pub fn test_apply_pod_metadata() {
+        let pod_yaml = format!(
+            include_str!("vault_spec_template.yaml"),
+            validator_index = 0,
+            node_name = "node-0",
+        );
+        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml).unwrap();
+        let mut pod: Pod = serde_json::from_value(serde_json::to_value(pod_spec).unwrap()).unwrap();
+        let mut pod_metadata = PodMetadata::default();
+        pod_metadata
+            .labels
+            .insert("team".to_string(), "libra".to_string());
+        pod_metadata
+            .labels
+            .insert("libra-node".to_string(), "false".to_string());
+        pod_metadata
+            .annotations
+            .insert("prometheus.io/port".to_string(), "9101".to_string());
+
+        apply_pod_metadata(&mut pod, &pod_metadata);
+
+        let labels = pod.metadata.labels.unwrap();
+        assert_eq!(labels.get("team").unwrap(), "libra");
+        assert_eq!(labels.get("libra-node").unwrap(), "true");
+        let annotations = pod.metadata.annotations.unwrap();
+        assert_eq!(annotations.get("prometheus.io/port").unwrap(), "9101");
+    }
+
+    #[test]
+    pub fn test_orphan_pods() {
+        use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+
+        let now = Utc::now();
+        let pod = |name: &str, run_id: Option<&str>, age_secs: i64| {
+            let mut labels = std::collections::BTreeMap::new();
+            labels.insert("libra-node".to_string(), "true".to_string());
+            if let Some(run_id) = run_id {
+                labels.insert(RUN_ID_LABEL.to_string(), run_id.to_string());
+            }
+            Pod {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    labels: Some(labels),
+                    creation_timestamp: Some(Time(now - chrono::Duration::seconds(age_secs))),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        };
+        let pods = vec![
+            pod("val-0", Some("run-2"), 60),
+            pod("val-1", Some("run-1"), 7200),
+            pod("fn-0-0", Some("run-1"), 60),
+            pod("vault-0", None, 7200),
+        ];
+
+        assert_eq!(
+            orphan_pods(&pods, Some("run-2"), None),
+            vec!["val-1", "fn-0-0", "vault-0"]
+        );
+        assert_eq!(
+            orphan_pods(
+                &pods,
+                Some("run-2"),
+                Some(now - chrono::Duration::seconds(3600))
+            ),
+            vec!["val-1", "vault-0"]
+        );
+        assert_eq!(orphan_pods(&pods, None, None).len(), 4);
+    }
+
+    #[test]
+    pub fn test_respawned_pod() {
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": "val-0",
+                "labels": {"app": "libra-validator"},
+                "resourceVersion": "42",
+                "uid": "5ac1e8c0",
+            },
+            "spec": {
+                "nodeName": "node-0",
+                "containers": [{"name": "main", "image": "libra/validator"}],
+            },
+            "status": {"phase": "Running"},
+        }))
+        .unwrap();
+        assert_eq!(fluentbit_input_tag(&pod), Some("validator-events"));
+
+        let respawned = respawned_pod(pod, "node-1");
+        assert_eq!(respawned.metadata.name.as_deref(), Some("val-0"));
+        assert_eq!(
+            respawned.metadata.labels.as_ref().unwrap()["app"],
+            "libra-validator"
+        );
+        assert!(respawned.metadata.resource_version.is_none());
+        assert!(respawned.metadata.uid.is_none());
+        let spec = respawned.spec.unwrap();
+        assert_eq!(spec.node_name.as_deref(), Some("node-1"));
+        assert_eq!(spec.containers[0].name, "main");
+        assert!(respawned.status.is_none());
+        assert_eq!(fluentbit_input_tag(&Pod::default()), None);
+    }
+
+    #[test]
+    pub fn test_container_messages() {
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "status": {
+                "phase": "Pending",
+                "initContainerStatuses": [{
+                    "name": "init",
+                    "image": "libra_init",
+                    "imageID": "",
+                    "ready": false,
+                    "restartCount": 0,
+                    "state": { "terminated": { "exitCode": 1, "reason": "Error" } }
+                }],
+                "containerStatuses": [{
+                    "name": "main",
+                    "image": "libra_validator",
+                    "imageID": "",
+                    "ready": false,
+                    "restartCount": 0,
+                    "state": {
+                        "waiting": {
+                            "reason": "ImagePullBackOff",
+                            "message": "Back-off pulling image"
+                        }
+                    }
+                }, {
+                    "name": "fluent-bit",
+                    "image": "fluent-bit",
+                    "imageID": "",
+                    "ready": true,
+                    "restartCount": 0,
+                    "state": { "running": {} }
+                }]
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            container_messages(&pod),
+            vec![
+                "init: Error",
+                "main: ImagePullBackOff: Back-off pulling image"
+            ]
+        );
+        assert!(container_messages(&Pod::default()).is_empty());
+    }
+
+    #[test]
+    pub fn test_lsr_service_port() {
+        let service_yaml = format!(
+            include_str!("lsr_service_template.yaml"),
+            pod_name = "lsr-0",
+            lsr_port = 7185,
+        );
+        let service_spec: serde_yaml::Value = serde_yaml::from_str(&service_yaml).unwrap();
+        let service: Service =
+            serde_json::from_value(serde_json::to_value(service_spec).unwrap()).unwrap();
+        let ports = service.spec.unwrap().ports.unwrap();
+        assert_eq!(ports[0].port, 7185);
+    }
+
+    #[test]
+    pub fn test_apply_pod_env() {
+        let pod_yaml = format!(
+            include_str!("lsr_spec_template.yaml"),
+            pod_name = "lsr-0",
+            validator_index = 0,
+            num_validators = 1,
+            vault_index = 0,
+            num_vaults = 1,
+            safety_rules_namespace = "",
+            image_tag = "test",
+            node_name = "node-0",
+            lsr_backend = "vault",
+            lsr_port = 6185,
+            vault_token = "root",
+            cfg_seed = CFG_SEED,
+        );
+        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml).unwrap();
+        let mut pod: Pod = serde_json::from_value(serde_json::to_value(pod_spec).unwrap()).unwrap();
+
+        apply_pod_env(
+            &mut pod,
+            &[
+                ("RUST_LOG".to_string(), "info".to_string()),
+                ("FEATURE".to_string(), "on".to_string()),
+                ("RUST_LOG".to_string(), "debug".to_string()),
+            ],
+        );
+
+        let spec = pod.spec.unwrap();
+        let env = spec.containers[0].env.as_ref().unwrap();
+        let value = |name: &str| {
+            let vars: Vec<_> = env.iter().filter(|var| var.name == name).collect();
+            assert_eq!(vars.len(), 1);
+            vars[0].value.clone().unwrap()
+        };
+        assert_eq!(value("RUST_LOG"), "debug");
+        assert_eq!(value("FEATURE"), "on");
+        let init_env = spec.init_containers.unwrap()[0]
+            .env
+            .clone()
+            .unwrap_or_default();
+        assert!(init_env.iter().all(|var| var.name != "FEATURE"));
+    }
+
+    #[test]
+    pub fn test_fullnode_spec() {
+        let mut fullnode_config = FullnodeConfig {
+            fullnode_index: 1,
+            num_fullnodes_per_validator: 2,
+            num_validators: 4,
+            image_tag: "test".to_string(),
+            config_overrides: vec![],
+            seed_peer_addresses: vec![
+                NetworkAddress::from_str("/ip4/10.0.0.1/tcp/6181").unwrap(),
+                NetworkAddress::from_str("/ip4/10.0.0.2/tcp/6181").unwrap(),
+            ],
+            network_key: None,
+            vault_addr: None,
+            vault_token: None,
+            listen_port: 6182,
+            readiness_probe: None,
+            tolerations: vec![],
+        };
+        let env = |pod: Pod| -> HashMap<String, String> {
+            let main = pod
+                .spec
+                .unwrap()
+                .containers
+                .into_iter()
+                .find(|container| container.name == "main")
+                .unwrap();
+            main.env
+                .unwrap()
+                .into_iter()
+                .map(|var| (var.name, var.value.unwrap_or_default()))
+                .collect()
+        };
+
+        let pod = fullnode_spec(&fullnode_config, 3, "node-0").unwrap();
+        assert_eq!(pod.metadata.name.as_deref(), Some("fn-3-1"));
+        let vars = env(pod);
+        assert_eq!(vars["CFG_SEED_PEER_ADDR"], "/ip4/10.0.0.1/tcp/6181");
+        assert_eq!(
+            vars["CFG_SEED_PEER_ADDRS"],
+            "/ip4/10.0.0.1/tcp/6181,/ip4/10.0.0.2/tcp/6181"
+        );
+        assert_eq!(vars["CFG_LISTEN_PORT"], "6182");
+        assert_eq!(vars["CFG_FULLNODE_NETWORK_KEY"], "");
+        assert_eq!(vars["CFG_VAULT_TOKEN"], "");
+
+        fullnode_config.network_key = Some("fn-3-1__fullnode_network".to_string());
+        fullnode_config.vault_addr = Some("10.0.0.9".to_string());
+        fullnode_config.vault_token = Some(VaultToken::new("s.token".to_string()));
+        let vars = env(fullnode_spec(&fullnode_config, 3, "node-0").unwrap());
+        assert_eq!(vars["CFG_FULLNODE_NETWORK_KEY"], "fn-3-1__fullnode_network");
+        assert_eq!(
+            vars["CFG_FULLNODE_PEER_ID"],
+            "fn-3-1__fullnode_network_peer_id"
+        );
+        assert_eq!(vars["CFG_VAULT_ADDR"], "10.0.0.9");
+        assert_eq!(vars["CFG_VAULT_TOKEN"], "s.token");
+    }
+
+    #[test]
+    pub fn test_lsr_vault_token() {
+        let pod_yaml = format!(
+            include_str!("lsr_spec_template.yaml"),
+            pod_name = "lsr-0",
+            validator_index = 0,
+            num_validators = 1,
+            vault_index = 0,
+            num_vaults = 1,
+            safety_rules_namespace = "",
+            image_tag = "test",
+            node_name = "node-0",
+            lsr_backend = "vault",
+            lsr_port = 6185,
+            vault_token = "s.token",
+            cfg_seed = CFG_SEED,
+        );
+        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml).unwrap();
+        let pod: Pod = serde_json::from_value(serde_json::to_value(pod_spec).unwrap()).unwrap();
+
+        let init = &pod.spec.unwrap().init_containers.unwrap()[0];
+        let token = init
+            .env
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|var| var.name == "VAULT_TOKEN")
+            .and_then(|var| var.value.clone());
+        assert_eq!(token.as_deref(), Some("s.token"));
+        let script = init.command.as_ref().unwrap().join(" ");
+        assert!(script.contains("--header \"X-Vault-Token: $VAULT_TOKEN\""));
+        assert!(script.contains("--safety-rules-token=\"$VAULT_TOKEN\""));
+        assert!(!script.contains("root"));
+    }
+
+    #[test]
+    pub fn test_apply_image_digests() {
+        let pod_yaml = format!(
+            include_str!("lsr_spec_template.yaml"),
+            pod_name = "lsr-0",
+            validator_index = 0,
+            num_validators = 1,
+            vault_index = 0,
+            num_vaults = 1,
+            safety_rules_namespace = "",
+            image_tag = "stable",
+            node_name = "node-0",
+            lsr_backend = "vault",
+            lsr_port = 6185,
+            vault_token = "root",
+            cfg_seed = CFG_SEED,
+        );
+        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml).unwrap();
+        let mut pod: Pod = serde_json::from_value(serde_json::to_value(pod_spec).unwrap()).unwrap();
+        let unpinned = pod.clone();
+
+        apply_image_digests(&mut pod, &BTreeMap::new());
+        assert_eq!(pod, unpinned);
+
+        let digests = vec![
+            ("libra_init".to_string(), "sha256:1a2b".to_string()),
+            ("libra_safety_rules".to_string(), "sha256:3c4d".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        apply_image_digests(&mut pod, &digests);
+
+        let spec = pod.spec.unwrap();
+        let image = |containers: &[Container], name: &str| {
+            containers
+                .iter()
+                .find(|container| container.name == name)
+                .unwrap()
+                .image
+                .clone()
+                .unwrap()
+        };
+        let init_containers = spec.init_containers.unwrap();
+        assert_eq!(
+            image(&init_containers, "init"),
+            "853397791086.dkr.ecr.us-west-2.amazonaws.com/libra_init@sha256:1a2b"
+        );
+        assert_eq!(
+            image(&spec.containers, "main"),
+            "853397791086.dkr.ecr.us-west-2.amazonaws.com/libra_safety_rules@sha256:3c4d"
+        );
+        // fluent-bit is not a libra image and keeps its tag
+        assert_eq!(
+            image(&spec.containers, "fluent-bit"),
+            "853397791086.dkr.ecr.us-west-2.amazonaws.com/fluent-bit:1.3.9"
+        );
+    }
+
+    #[test]
+    pub fn test_apply_image_pull_policy() {
+        let pod_yaml = format!(
+            include_str!("lsr_spec_template.yaml"),
+            pod_name = "lsr-0",
+            validator_index = 0,
+            num_validators = 1,
+            vault_index = 0,
+            num_vaults = 1,
+            safety_rules_namespace = "",
+            image_tag = "test",
+            node_name = "node-0",
+            lsr_backend = "vault",
+            lsr_port = 6185,
+            vault_token = "root",
+            cfg_seed = CFG_SEED,
+        );
+        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml).unwrap();
+        let mut pod: Pod = serde_json::from_value(serde_json::to_value(pod_spec).unwrap()).unwrap();
+
+        apply_image_pull_policy(&mut pod, ImagePullPolicy::Never);
+
+        let spec = pod.spec.unwrap();
+        let policy = |containers: &[Container], name: &str| {
+            containers
+                .iter()
+                .find(|container| container.name == name)
+                .unwrap()
+                .image_pull_policy
+                .clone()
+                .unwrap()
+        };
+        let init_containers = spec.init_containers.unwrap();
+        assert_eq!(policy(&init_containers, "init"), "Never");
+        assert_eq!(policy(&spec.containers, "main"), "Never");
+        // fluent-bit is not a libra image
+        assert_eq!(policy(&spec.containers, "fluent-bit"), "IfNotPresent");
+        assert!("Sometimes".parse::<ImagePullPolicy>().is_err());
+    }
+
+    #[test]
+    pub fn test_apply_tolerations() {
+        let pod_yaml = format!(
+            include_str!("validator_spec_template.yaml"),
+            index = 0,
+            pod_name = "val-0",
+            num_validators = 1,
+            num_fullnodes = 0,
+            enable_lsr = false,
+            num_lsrs = 0,
+            image_tag = "test",
+            node_name = "node-0",
+            cfg_overrides = "",
+            cfg_seed = CFG_SEED,
+            cfg_seed_peer_ip = "10.0.0.1",
+            cfg_safety_rules_addr = "",
+            cfg_safety_rules_replica_addrs = "",
+            cfg_base_config = "",
+            cfg_fullnode_seed = "",
+            cpu_millis = VALIDATOR_CPU_MILLIS,
+        );
+        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml).unwrap();
+        let mut pod: Pod = serde_json::from_value(serde_json::to_value(pod_spec).unwrap()).unwrap();
+        let template_tolerations = pod.spec.as_ref().unwrap().tolerations.clone().unwrap();
+
+        let tolerations = vec![
+            "dedicated=validators:NoSchedule".parse().unwrap(),
+            "spot".parse().unwrap(),
+        ];
+        apply_tolerations(&mut pod, &tolerations);
+        // Applying them again does not duplicate them
+        apply_tolerations(&mut pod, &tolerations);
+
+        let rendered = serde_json::to_value(&pod).unwrap();
+        let pod_tolerations = rendered["spec"]["tolerations"].as_array().unwrap();
+        assert_eq!(pod_tolerations.len(), template_tolerations.len() + 2);
+        assert!(pod_tolerations.contains(&serde_json::json!({
+            "key": "dedicated",
+            "operator": "Equal",
+            "value": "validators",
+            "effect": "NoSchedule",
+        })));
+        assert!(pod_tolerations.contains(&serde_json::json!({
+            "key": "spot",
+            "operator": "Exists",
+        })));
+        assert_eq!(
+            tolerations[0].to_string(),
+            "dedicated=validators:NoSchedule"
+        );
+        assert!("dedicated:Never".parse::<Toleration>().is_err());
+        assert!("=validators".parse::<Toleration>().is_err());
+    }
+
+    #[test]
+    pub fn test_kube_node_addresses() {
+        let node = |addresses: serde_json::Value| -> Result<KubeNode> {
+            let node: Node = serde_json::from_value(serde_json::json!({
+                "metadata": {"name": "node-0"},
+                "spec": {"providerID": "aws:///us-west-2a/i-0"},
+                "status": {"addresses": addresses},
+            }))
+            .unwrap();
+            KubeNode::try_from(node)
+        };
+
+        let kube_node = node(serde_json::json!([
+            {"type": "InternalIP", "address": "10.0.0.1"},
+            {"type": "ExternalIP", "address": "54.0.0.1"},
+        ]))
+        .unwrap();
+        assert_eq!(kube_node.internal_ip, "10.0.0.1");
+        assert_eq!(kube_node.external_ip.as_deref(), Some("54.0.0.1"));
+        assert_eq!(kube_node.ip(AddressSource::External).unwrap(), "54.0.0.1");
+        assert!(!kube_node.unschedulable);
+
+        let kube_node = node(serde_json::json!([
+            {"type": "InternalIP", "address": "10.0.0.1"},
+            {"type": "Hostname", "address": "node-0"},
+        ]))
+        .unwrap();
+        assert_eq!(kube_node.external_ip, None);
+        assert_eq!(kube_node.ip(AddressSource::Internal).unwrap(), "10.0.0.1");
+        assert!(kube_node.ip(AddressSource::External).is_err());
+
+        assert!(node(serde_json::json!([{"type": "ExternalIP", "address": "54.0.0.1"}])).is_err());
+    }
+
+    #[test]
+    pub fn test_free_node_skips_cordoned_nodes() {
+        let node = |i: usize, unschedulable: bool| KubeNode {
+            name: format!("node-{}", i),
+            provider_id: format!("provider-{}", i),
+            internal_ip: format!("10.0.0.{}", i),
+            external_ip: None,
+            unschedulable,
+            labels: BTreeMap::new(),
+        };
+        let mut node_map = HashMap::new();
+        node_map.insert("val-0".to_string(), node(0, false));
+
+        let nodes = vec![node(0, false), node(1, true), node(2, false)];
+        assert_eq!(
+            free_node(nodes, &node_map, &NodeFilter::default())
+                .unwrap()
+                .name,
+            "node-2"
+        );
+
+        let nodes = vec![node(0, false), node(1, true)];
+        assert!(free_node(nodes, &node_map, &NodeFilter::default()).is_none());
+    }
+
+    #[test]
+    pub fn test_clean_and_verify() {
+        let cleans = AtomicUsize::new(0);
+        let cleans = &cleans;
+        let clean = || async move {
+            cleans.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        };
+        // The first clean doesn't take effect
+        let is_clean = || async move { Ok(cleans.load(Ordering::SeqCst) > 1) };
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(clean_and_verify(
+            libra_retrier::fixed_retry_strategy(0, 2),
+            clean,
+            is_clean,
+        ))
+        .unwrap();
+        assert_eq!(cleans.load(Ordering::SeqCst), 2);
+
+        let error = rt
+            .block_on(clean_and_verify(
+                libra_retrier::fixed_retry_strategy(0, 2),
+                clean,
+                || async { Ok(false) },
+            ))
+            .unwrap_err();
+        assert!(error.to_string().contains("could not confirm"));
+        // The initial attempt and 2 retries
+        assert_eq!(cleans.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    pub fn test_placement_order() {
+        let node = |i: usize| KubeNode {
+            name: format!("node-{}", i),
+            provider_id: format!("provider-{}", i),
+            internal_ip: format!("10.0.0.{}", i),
+            external_ip: None,
+            unschedulable: false,
+            labels: BTreeMap::new(),
+        };
+        let names = |nodes: Vec<KubeNode>| -> Vec<String> {
+            nodes.into_iter().map(|node| node.name).collect()
+        };
+        let listed: Vec<_> = (0..8).map(node).collect();
+        let mut reversed = listed.clone();
+        reversed.reverse();
+
+        assert_eq!(
+            names(placement_order(reversed.clone(), None)),
+            names(reversed.clone())
+        );
+        let order = names(placement_order(listed.clone(), Some(42)));
+        assert_eq!(order, names(placement_order(reversed, Some(42))));
+        assert_ne!(order, names(placement_order(listed.clone(), Some(43))));
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, names(listed));
+    }
+
+    #[test]
+    pub fn test_pod_nodes() {
+        use k8s_openapi::{api::core::v1::PodSpec, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+
+        let pod = |name: &str, node_name: &str, job: bool| {
+            let mut labels = BTreeMap::new();
+            labels.insert("libra-node".to_string(), "true".to_string());
+            if job {
+                labels.insert("job-name".to_string(), name.to_string());
+            }
+            Pod {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    labels: Some(labels),
+                    ..Default::default()
+                },
+                spec: Some(PodSpec {
+                    node_name: Some(node_name.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        };
+        let nodes = (0..2)
+            .map(|i| KubeNode {
+                name: format!("node-{}", i),
+                provider_id: format!("provider-{}", i),
+                internal_ip: format!("10.0.0.{}", i),
+                external_ip: None,
+                unschedulable: false,
+                labels: BTreeMap::new(),
+            })
+            .collect();
+        let pods = vec![
+            pod("val-0", "node-1", false),
+            pod("clean-data-0", "node-0", true),
+            pod("val-1", "removed-node", false),
+        ];
+
+        let pod_nodes = pod_nodes(&pods, nodes);
+        assert_eq!(pod_nodes.len(), 1);
+        assert_eq!(pod_nodes["val-0"].name, "node-1");
+    }
+
+    #[test]
+    pub fn test_free_node_skips_denied_nodes() {
+        let node = |i: usize, pool: &str| KubeNode {
+            name: format!("node-{}", i),
+            provider_id: format!("provider-{}", i),
+            internal_ip: format!("10.0.0.{}", i),
+            external_ip: None,
+            unschedulable: false,
+            labels: iter::once(("pool".to_string(), pool.to_string())).collect(),
+        };
+        let nodes = vec![
+            node(0, "reserved"),
+            node(1, "default"),
+            node(2, "default"),
+            node(3, "default"),
+        ];
+        let node_filter = NodeFilter {
+            allow: vec![],
+            deny: vec!["pool=reserved".to_string(), "node-2".to_string()],
+        };
+        // Allocate until no node is left, the denied nodes are never picked
+        let mut node_map = HashMap::new();
+        for i in 0..nodes.len() {
+            match free_node(nodes.clone(), &node_map, &node_filter) {
+                Some(node) => {
+                    node_map.insert(format!("val-{}", i), node);
+                }
+                None => break,
+            }
+        }
+        let mut allocated: Vec<_> = node_map.values().map(|node| node.name.clone()).collect();
+        allocated.sort();
+        assert_eq!(allocated, vec!["node-1", "node-3"]);
+
+        let node_filter = NodeFilter {
+            allow: vec!["pool=default".to_string()],
+            deny: vec!["node-1".to_string()],
+        };
+        assert_eq!(
+            free_node(nodes, &HashMap::new(), &node_filter)
+                .unwrap()
+                .name,
+            "node-2"
+        );
+    }
+
+    #[test]
+    pub fn test_apply_readiness_probe() {
+        let pod_yaml = format!(
+            include_str!("lsr_spec_template.yaml"),
+            pod_name = "lsr-0",
+            validator_index = 0,
+            num_validators = 1,
+            vault_index = 0,
+            num_vaults = 1,
+            safety_rules_namespace = "",
+            image_tag = "test",
+            node_name = "node-0",
+            lsr_backend = "vault",
+            lsr_port = 6185,
+            vault_token = "root",
+            cfg_seed = CFG_SEED,
+        );
+        let pod_spec: serde_yaml::Value = serde_yaml::from_str(&pod_yaml).unwrap();
+        let mut pod: Pod = serde_json::from_value(serde_json::to_value(pod_spec).unwrap()).unwrap();
+
+        apply_readiness_probe(&mut pod, &ReadinessProbe::json_rpc());
+        let manifest = serde_json::to_value(&pod).unwrap();
+        let probe = &manifest["spec"]["containers"][0]["readinessProbe"];
+        assert_eq!(probe["httpGet"]["path"], "/-/healthy");
+        assert_eq!(probe["httpGet"]["port"], DEFAULT_JSON_RPC_PORT);
+        assert_eq!(probe["initialDelaySeconds"], 10);
+        assert_eq!(probe["periodSeconds"], 5);
+        assert!(manifest["spec"]["initContainers"][0]["readinessProbe"].is_null());
+
+        apply_readiness_probe(&mut pod, &ReadinessProbe::tcp(6185));
+        let manifest = serde_json::to_value(&pod).unwrap();
+        let probe = &manifest["spec"]["containers"][0]["readinessProbe"];
+        assert_eq!(probe["tcpSocket"]["port"], 6185);
+        assert!(probe["httpGet"].is_null());
     }
 }
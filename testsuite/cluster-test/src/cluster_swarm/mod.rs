@@ -1,11 +1,59 @@
 // Copyright (c) The Libra Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod cluster_swarm_federated;
 pub mod cluster_swarm_kube;
 
-use crate::instance::{Instance, InstanceConfig};
-use anyhow::Result;
+use crate::{
+    cluster_swarm::cluster_swarm_kube::{KubeNode, NodeFilter},
+    instance::{Instance, InstanceConfig},
+};
+use anyhow::{bail, Result};
 use async_trait::async_trait;
+use std::time::{Duration, Instant};
+
+/// Interval at which wait_for_pod polls the phase of the pod
+const WAIT_FOR_POD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Time reschedule_instance waits for the respawned pod to run
+const RESCHEDULE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Phase of a pod, as reported by kubernetes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PodPhase {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Unknown,
+}
+
+impl From<&str> for PodPhase {
+    fn from(phase: &str) -> Self {
+        match phase {
+            "Pending" => PodPhase::Pending,
+            "Running" => PodPhase::Running,
+            "Succeeded" => PodPhase::Succeeded,
+            "Failed" => PodPhase::Failed,
+            _ => PodPhase::Unknown,
+        }
+    }
+}
+
+/// Fault injected into a running pod with inject_fault
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Suspends the main process of the pod (SIGSTOP)
+    Pause,
+    /// Continues a paused main process (SIGCONT)
+    Resume,
+    /// Kills the main process of the pod (SIGKILL)
+    Kill,
+    /// Drops all traffic between the pod and the given IPs, on top of the previous partitions
+    NetworkPartition(Vec<String>),
+    /// Removes all network partitions of the pod
+    HealPartition,
+}
 
 #[async_trait]
 pub trait ClusterSwarm: Send + Sync {
@@ -13,7 +61,8 @@ pub trait ClusterSwarm: Send + Sync {
     async fn spawn_new_instance(&self, instance_config: InstanceConfig) -> Result<Instance>;
 
     /// If deleting /opt/libra/data/* is required, call clean_date before calling
-    /// spawn_new_instance.
+    /// spawn_new_instance. Cleaning already clean data is a no-op. Fails unless the data is
+    /// confirmed gone.
     async fn clean_data(&self, node: &str) -> Result<()>;
 
     async fn get_node_name(&self, pod_name: &str) -> Result<String>;
@@ -27,4 +76,162 @@ pub trait ClusterSwarm: Send + Sync {
         path: &str,
         content: Vec<u8>,
     ) -> Result<()>;
+
+    async fn get_pod_phase(&self, pod_name: &str) -> Result<PodPhase>;
+
+    /// Reasons and messages of the containers of the pod that are not running, e.g. why an image
+    /// can't be pulled. Swarms without container details return none.
+    async fn get_container_messages(&self, _pod_name: &str) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// Polls the pod until it is in `phase`. Fails once `timeout` elapsed, or as soon as the pod
+    /// failed if another phase is awaited, with the last observed phase and the container
+    /// messages in the error.
+    async fn wait_for_pod(&self, pod_name: &str, phase: PodPhase, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let observed = self.get_pod_phase(pod_name).await;
+            let failed = match &observed {
+                Ok(observed) if *observed == phase => return Ok(()),
+                Ok(observed) => *observed == PodPhase::Failed,
+                Err(_) => false,
+            };
+            if failed || Instant::now() >= deadline {
+                let observed = match observed {
+                    Ok(observed) => format!("{:?}", observed),
+                    Err(e) => format!("unknown ({})", e),
+                };
+                let messages = match self.get_container_messages(pod_name).await {
+                    Ok(messages) => messages.join("; "),
+                    Err(e) => format!("failed to get container messages: {}", e),
+                };
+                bail!(
+                    "Pod {} did not reach phase {:?} within {:?}, last phase {}, containers: [{}]",
+                    pod_name,
+                    phase,
+                    timeout,
+                    observed,
+                    messages
+                );
+            }
+            tokio::time::delay_for(WAIT_FOR_POD_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Deletes the pod and its service
+    async fn delete_pod(&self, pod_name: &str) -> Result<()>;
+
+    /// Deletes the pod and creates it again with the same spec on a newly allocated node, its
+    /// service is kept
+    async fn respawn_pod(&self, pod_name: &str) -> Result<()> {
+        bail!(
+            "Can not respawn {}, the swarm doesn't support respawning pods",
+            pod_name
+        )
+    }
+
+    /// Moves the pod to another node, e.g. to test recovering from the loss of its node: cordons
+    /// the node of the pod, respawns the pod, waits for it to run and uncordons the node again.
+    /// Returns the new node, fails if the pod lands on its old node (e.g. single node cluster).
+    async fn reschedule_instance(&self, pod_name: &str) -> Result<String> {
+        let old_node = self.get_node_name(pod_name).await?;
+        self.cordon_node(&old_node).await?;
+        let rescheduled = async {
+            self.respawn_pod(pod_name).await?;
+            self.wait_for_pod(pod_name, PodPhase::Running, RESCHEDULE_TIMEOUT)
+                .await?;
+            let new_node = self.get_node_name(pod_name).await?;
+            if new_node == old_node {
+                bail!(
+                    "Pod {} was rescheduled on its old node {}",
+                    pod_name,
+                    old_node
+                );
+            }
+            Ok::<_, anyhow::Error>(new_node)
+        }
+        .await;
+        // The node is uncordoned even if rescheduling failed, the first error wins
+        let uncordoned = self.uncordon_node(&old_node).await;
+        let new_node = rescheduled?;
+        uncordoned?;
+        Ok(new_node)
+    }
+
+    /// Injects `fault` into the running pod
+    async fn inject_fault(&self, pod_name: &str, fault: Fault) -> Result<()> {
+        bail!(
+            "Can not inject {:?} into {}, the swarm doesn't support faults",
+            fault,
+            pod_name
+        )
+    }
+
+    /// Marks the node unschedulable, pods placed on it are moved to another node the next time
+    /// they are spawned. Fails if the node doesn't exist.
+    async fn cordon_node(&self, node_name: &str) -> Result<()>;
+
+    /// Makes a node cordoned by cordon_node schedulable again
+    async fn uncordon_node(&self, node_name: &str) -> Result<()>;
+
+    /// Number of schedulable nodes instances can be placed on, one instance per node
+    async fn node_capacity(&self) -> Result<u32>;
+}
+
+/// Swarm ClusterBuilder provisions clusters on: on top of running instances it allocates the
+/// nodes they are placed on and cleans up after previous runs
+#[async_trait]
+pub trait ProvisioningSwarm: ClusterSwarm {
+    /// Node the pod is going to be spawned on, a free node is allocated unless the pod already
+    /// has one. Retries `retries` times, `retry_interval_ms` apart, while no node is free.
+    async fn allocate_node(
+        &self,
+        pod_name: &str,
+        retries: usize,
+        retry_interval_ms: u64,
+    ) -> Result<KubeNode>;
+
+    /// Deletes the libra pods and services of the registered namespaces and removes the network
+    /// effects left behind by previous runs
+    async fn cleanup(&self) -> Result<()>;
+
+    /// Takes over the libra pods already running instead of cleaning them up, returns their names
+    async fn adopt_pods(&self) -> Result<Vec<String>>;
+
+    /// Name of the workspace the swarm runs in, its ASG is named after it
+    async fn get_workspace(&self) -> Result<String>;
+
+    /// Makes cleanup also delete the libra pods and services of `namespace`
+    async fn register_namespace(&self, namespace: &str);
+
+    /// Restricts the nodes allocate_node picks from
+    async fn set_node_filter(&self, node_filter: NodeFilter);
+
+    /// Makes allocate_node place pods deterministically with `seed`
+    async fn set_placement_seed(&self, seed: Option<u64>);
+
+    /// Limits how many validators of the run in `namespace` can be evicted at once
+    async fn create_validator_pdb(&self, namespace: &str, max_unavailable: i32) -> Result<()>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{block_on, MockSwarm};
+
+    #[test]
+    pub fn test_reschedule_instance() {
+        let swarm = MockSwarm::with_nodes(vec!["node-0", "node-1"]);
+        assert_eq!(
+            block_on(swarm.reschedule_instance("val-0")).unwrap(),
+            "node-1"
+        );
+        assert!(swarm.cordoned.lock().unwrap().is_empty());
+
+        let swarm = MockSwarm::with_nodes(vec!["node-0"]);
+        let error = block_on(swarm.reschedule_instance("val-0")).unwrap_err();
+        assert!(error.to_string().contains("old node node-0"), "{}", error);
+        assert!(swarm.cordoned.lock().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,41 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use libra_metrics::{register_histogram, register_int_counter, Histogram, IntCounter};
+use once_cell::sync::Lazy;
+
+/// Count of the clusters setup_cluster brought up successfully
+pub static CLUSTERS_CREATED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "libra_cluster_test_clusters_created",
+        "Count of the clusters set up successfully by the cluster builder"
+    )
+    .unwrap()
+});
+
+/// Count of the nodes allocated to pods
+pub static NODES_ALLOCATED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "libra_cluster_test_nodes_allocated",
+        "Count of the nodes allocated to pods by the cluster builder"
+    )
+    .unwrap()
+});
+
+/// Count of the validators, fullnodes, LSRs and vaults that failed to spawn
+pub static SPAWN_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "libra_cluster_test_spawn_failures",
+        "Count of the instances the cluster builder failed to spawn"
+    )
+    .unwrap()
+});
+
+/// Histogram of the time taken to generate genesis
+pub static GENESIS_DURATION_S: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "libra_cluster_test_genesis_duration_s",
+        "Histogram of the time in seconds the cluster builder takes to generate genesis"
+    )
+    .unwrap()
+});
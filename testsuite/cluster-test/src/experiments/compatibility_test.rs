@@ -112,11 +112,11 @@ pub struct CompatibilityTest {
 impl ExperimentParam for CompatiblityTestParams {
     type E = CompatibilityTest;
     fn build(self, cluster: &Cluster) -> Self::E {
-        if self.count > cluster.validator_instances().len() || self.count == 0 {
+        if self.count > cluster.validator_count() || self.count == 0 {
             panic!(
                 "Can not reboot {} validators in cluster with {} instances",
                 self.count,
-                cluster.validator_instances().len()
+                cluster.validator_count()
             );
         }
         let (first_batch, second_batch) = cluster.split_n_validators_random(self.count);
@@ -49,7 +49,7 @@ pub struct PacketLossRandomValidatorsParams {
 impl ExperimentParam for PacketLossRandomValidatorsParams {
     type E = PacketLossRandomValidators;
     fn build(self, cluster: &Cluster) -> Self::E {
-        let total_instances = cluster.validator_instances().len();
+        let total_instances = cluster.validator_count();
         let packet_loss_num_instances: usize = std::cmp::min(
             ((self.percent_instances / 100.0) * total_instances as f32).ceil() as usize,
             total_instances,
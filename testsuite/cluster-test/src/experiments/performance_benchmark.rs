@@ -105,7 +105,7 @@ impl ExperimentParam for PerformanceBenchmarkParams {
     type E = PerformanceBenchmark;
     fn build(self, cluster: &Cluster) -> Self::E {
         let all_fullnode_instances = cluster.fullnode_instances();
-        let num_nodes = cluster.validator_instances().len();
+        let num_nodes = cluster.validator_count();
         let nodes_down = (num_nodes * self.percent_nodes_down) / 100;
         let (down, up) = cluster.split_n_validators_random(nodes_down);
         let up_validators = up.into_validator_instances();
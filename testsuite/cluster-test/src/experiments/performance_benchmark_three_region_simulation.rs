@@ -34,7 +34,7 @@ impl ExperimentParam for PerformanceBenchmarkThreeRegionSimulationParams {
 #[async_trait]
 impl Experiment for PerformanceBenchmarkThreeRegionSimulation {
     async fn run(&mut self, context: &mut Context<'_>) -> anyhow::Result<()> {
-        let num_nodes = self.cluster.validator_instances().len();
+        let num_nodes = self.cluster.validator_count();
         let split_country_num = ((num_nodes as f64) * 0.8) as usize;
         let split_region_num = split_country_num / 2;
         let (us, euro) = self.cluster.split_n_validators_random(split_country_num);
@@ -45,19 +45,19 @@ pub struct RebootRandomValidators {
 impl ExperimentParam for RebootRandomValidatorsParams {
     type E = RebootRandomValidators;
     fn build(self, cluster: &Cluster) -> Self::E {
-        if self.count > cluster.validator_instances().len() {
+        if self.count > cluster.validator_count() {
             panic!(
                 "Can not reboot {} validators in cluster with {} instances",
                 self.count,
-                cluster.validator_instances().len()
+                cluster.validator_count()
             );
         }
 
-        if self.lsr_count > cluster.lsr_instances().len() {
+        if self.lsr_count > cluster.lsr_count() {
             panic!(
                 "Can not reboot {} lsrs in cluster with {} instances",
                 self.count,
-                cluster.lsr_instances().len()
+                cluster.lsr_count()
             );
         }
 
@@ -37,11 +37,11 @@ pub struct TwinValidators {
 impl ExperimentParam for TwinValidatorsParams {
     type E = TwinValidators;
     fn build(self, cluster: &Cluster) -> Self::E {
-        if self.pair >= cluster.validator_instances().len() {
+        if self.pair >= cluster.validator_count() {
             panic!(
                 "pair number {} can not equal or more than validator number {}",
                 self.pair,
-                cluster.validator_instances().len()
+                cluster.validator_count()
             );
         }
         let mut instances = cluster.validator_instances().to_vec();
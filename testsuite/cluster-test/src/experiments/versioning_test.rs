@@ -51,11 +51,11 @@ pub struct ValidatorVersioning {
 impl ExperimentParam for ValidatorVersioningParams {
     type E = ValidatorVersioning;
     fn build(self, cluster: &Cluster) -> Self::E {
-        if self.count > cluster.validator_instances().len() {
+        if self.count > cluster.validator_count() {
             panic!(
                 "Can not reboot {} validators in cluster with {} instances",
                 self.count,
-                cluster.validator_instances().len()
+                cluster.validator_count()
             );
         }
         let (first_batch, second_batch) = cluster.split_n_validators_random(self.count);
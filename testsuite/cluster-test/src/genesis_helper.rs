@@ -1,25 +1,36 @@
 // Copyright (c) The Libra Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 use libra_crypto::ed25519::Ed25519PublicKey;
-use libra_genesis_tool::{command::Command, layout::Layout};
+use libra_genesis_tool::{command::Command, layout::Layout, PublishingOption};
 use libra_management::{error::Error, secure_backend::DISK};
 use libra_network_address::NetworkAddress;
 use libra_operational_tool::command::Command as OperationalCommand;
 use libra_types::{chain_id::ChainId, transaction::Transaction, waypoint::Waypoint};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 use tokio::task::spawn_blocking;
 
 pub struct GenesisHelper {
-    path: &'static str,
+    /// On-disk storage shared by all owners and operators during genesis
+    path: PathBuf,
+    publishing_option: PublishingOption,
 }
 
 impl GenesisHelper {
-    pub fn new(path: &'static str) -> Self {
-        GenesisHelper { path }
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        GenesisHelper {
+            path: path.into(),
+            publishing_option: PublishingOption::Open,
+        }
     }
 
-    pub async fn set_layout(&self, path: &str, namespace: &str) -> Result<Layout, Error> {
+    /// Publishing option genesis is created with, waypoints are computed with the same one
+    pub fn with_publishing_option(mut self, publishing_option: PublishingOption) -> Self {
+        self.publishing_option = publishing_option;
+        self
+    }
+
+    pub async fn set_layout(&self, path: &Path, namespace: &str) -> Result<Layout, Error> {
         let args = format!(
             "
                 libra-genesis-tool
@@ -29,9 +40,9 @@ impl GenesisHelper {
                     path={storage_path};\
                     namespace={ns}
             ",
-            path = path,
+            path = path.display(),
             backend = DISK,
-            storage_path = self.path,
+            storage_path = self.path.display(),
             ns = namespace,
         );
 
@@ -44,8 +55,6 @@ impl GenesisHelper {
     pub async fn libra_root_key(
         &self,
         validator_backend: &str,
-        server: &str,
-        token_path: &str,
         validator_ns: &str,
         shared_ns: &str,
     ) -> Result<Ed25519PublicKey, Error> {
@@ -53,9 +62,7 @@ impl GenesisHelper {
             "
                 libra-genesis-tool
                 libra-root-key
-                --validator-backend backend={validator_backend};\
-                    server={server};\
-                    token={token_path};\
+                --validator-backend {validator_backend};\
                     namespace={validator_ns}
                 --shared-backend backend={backend};\
                     path={path};\
@@ -63,9 +70,7 @@ impl GenesisHelper {
             ",
             backend = DISK,
             validator_backend = validator_backend,
-            server = server,
-            token_path = token_path,
-            path = self.path,
+            path = self.path.display(),
             validator_ns = validator_ns,
             shared_ns = shared_ns,
         );
@@ -79,8 +84,6 @@ impl GenesisHelper {
     pub async fn owner_key(
         &self,
         validator_backend: &str,
-        server: &str,
-        token_path: &str,
         validator_ns: &str,
         shared_ns: &str,
     ) -> Result<Ed25519PublicKey, Error> {
@@ -88,9 +91,7 @@ impl GenesisHelper {
             "
                 libra-genesis-tool
                 owner-key
-                --validator-backend backend={validator_backend};\
-                    server={server};\
-                    token={token_path};\
+                --validator-backend {validator_backend};\
                     namespace={validator_ns}
                 --shared-backend backend={backend};\
                     path={path};\
@@ -98,9 +99,7 @@ impl GenesisHelper {
             ",
             backend = DISK,
             validator_backend = validator_backend,
-            server = server,
-            token_path = token_path,
-            path = self.path,
+            path = self.path.display(),
             validator_ns = validator_ns,
             shared_ns = shared_ns,
         );
@@ -114,8 +113,6 @@ impl GenesisHelper {
     pub async fn operator_key(
         &self,
         validator_backend: &str,
-        server: &str,
-        token_path: &str,
         validator_ns: &str,
         shared_ns: &str,
     ) -> Result<Ed25519PublicKey, Error> {
@@ -123,9 +120,7 @@ impl GenesisHelper {
             "
                 libra-genesis-tool
                 operator-key
-                --validator-backend backend={validator_backend};\
-                    server={server};\
-                    token={token_path};\
+                --validator-backend {validator_backend};\
                     namespace={validator_ns}
                 --shared-backend backend={backend};\
                     path={path};\
@@ -133,9 +128,7 @@ impl GenesisHelper {
             ",
             backend = DISK,
             validator_backend = validator_backend,
-            server = server,
-            token_path = token_path,
-            path = self.path,
+            path = self.path.display(),
             validator_ns = validator_ns,
             shared_ns = shared_ns,
         );
@@ -153,8 +146,6 @@ impl GenesisHelper {
         fullnode_address: NetworkAddress,
         chain_id: ChainId,
         validator_backend: &str,
-        server: &str,
-        token_path: &str,
         validator_ns: &str,
         shared_ns: &str,
     ) -> Result<Transaction, Error> {
@@ -166,9 +157,7 @@ impl GenesisHelper {
                 --validator-address {validator_address}
                 --fullnode-address {fullnode_address}
                 --chain-id {chain_id}
-                --validator-backend backend={validator_backend};\
-                    server={server};\
-                    token={token_path};\
+                --validator-backend {validator_backend};\
                     namespace={validator_ns}
                 --shared-backend backend={backend};\
                     path={path};\
@@ -179,10 +168,8 @@ impl GenesisHelper {
             fullnode_address = fullnode_address,
             chain_id = chain_id.id(),
             validator_backend = validator_backend,
-            server = server,
-            token_path = token_path,
             backend = DISK,
-            path = self.path,
+            path = self.path.display(),
             validator_ns = validator_ns,
             shared_ns = shared_ns,
         );
@@ -209,7 +196,7 @@ impl GenesisHelper {
             ",
             operator_name = operator_name,
             backend = DISK,
-            path = self.path,
+            path = self.path.display(),
             shared_ns = shared_ns,
         );
 
@@ -232,11 +219,13 @@ impl GenesisHelper {
                 --shared-backend backend={backend};\
                     path={path}
                 --path {genesis_path}
+                --publishing-option {publishing_option}
             ",
             chain_id = chain_id,
             backend = DISK,
-            path = self.path,
+            path = self.path.display(),
             genesis_path = genesis_path.to_str().expect("Unable to parse genesis_path"),
+            publishing_option = self.publishing_option,
         );
 
         let command = Command::from_iter(args.split_whitespace());
@@ -245,12 +234,32 @@ impl GenesisHelper {
             .expect("tokio spawn_blocking runtime error")
     }
 
+    pub async fn create_waypoint(&self, chain_id: ChainId) -> Result<Waypoint, Error> {
+        let args = format!(
+            "
+                libra-genesis-tool
+                create-waypoint
+                --chain-id {chain_id}
+                --shared-backend backend={backend};\
+                    path={path}
+                --publishing-option {publishing_option}
+            ",
+            chain_id = chain_id,
+            backend = DISK,
+            path = self.path.display(),
+            publishing_option = self.publishing_option,
+        );
+
+        let command = Command::from_iter(args.split_whitespace());
+        spawn_blocking(|| command.create_waypoint())
+            .await
+            .expect("tokio spawn_blocking runtime error")
+    }
+
     pub async fn create_and_insert_waypoint(
         &self,
         chain_id: ChainId,
         validator_backend: &str,
-        server: &str,
-        token_path: &str,
         validator_ns: &str,
     ) -> Result<Waypoint, Error> {
         let args = format!(
@@ -260,18 +269,16 @@ impl GenesisHelper {
                 --chain-id {chain_id}
                 --shared-backend backend={backend};\
                     path={path}
-                --validator-backend backend={validator_backend};\
-                    server={server};\
-                    token={token_path};\
-                    namespace={validator_ns}\
+                --validator-backend {validator_backend};\
+                    namespace={validator_ns}
+                --publishing-option {publishing_option}
             ",
             chain_id = chain_id,
             backend = DISK,
             validator_backend = validator_backend,
-            server = server,
-            token_path = token_path,
-            path = self.path,
+            path = self.path.display(),
             validator_ns = validator_ns,
+            publishing_option = self.publishing_option,
         );
 
         let command = Command::from_iter(args.split_whitespace());
@@ -283,10 +290,8 @@ impl GenesisHelper {
     pub async fn extract_private_key(
         &self,
         key_name: &str,
-        key_file: &str,
+        key_file: &Path,
         validator_backend: &str,
-        server: &str,
-        token_path: &str,
     ) -> Result<(), Error> {
         let args = format!(
             "
@@ -294,15 +299,11 @@ impl GenesisHelper {
                 extract-private-key
                 --key-name {key_name}
                 --key-file {key_file}
-                --validator-backend backend={validator_backend};\
-                    server={server};\
-                    token={token_path}\
+                --validator-backend {validator_backend}
             ",
             key_name = key_name,
-            key_file = key_file,
+            key_file = key_file.display(),
             validator_backend = validator_backend,
-            server = server,
-            token_path = token_path,
         );
 
         let command = OperationalCommand::from_iter(args.split_whitespace());
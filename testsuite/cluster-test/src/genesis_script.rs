@@ -0,0 +1,622 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+#![forbid(unsafe_code)]
+
+//! Post-processing of the genesis transaction the genesis tool generates: pre-creates funded
+//! accounts and runs an extra compiled Move script as libra root on top of the genesis state,
+//! folding their effects into the genesis change set, for setups the tool has no flag for.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use anyhow::{bail, format_err, Result};
+use libra_crypto::{ed25519::Ed25519PublicKey, ValidCryptoMaterial, ValidCryptoMaterialStringExt};
+use libra_state_view::StateView;
+use libra_types::{
+    access_path::AccessPath,
+    account_address::AccountAddress,
+    account_config::{
+        coin1_tag, libra_root_address, reserved_vm_address, testnet_dd_account_address,
+        treasury_compliance_account_address, validator_set_address, AccountResource,
+        BalanceResource, CORE_CODE_ADDRESS,
+    },
+    transaction::{
+        authenticator::AuthenticationKey, ChangeSet, Script, Transaction, TransactionArgument,
+        WriteSetPayload,
+    },
+    write_set::{WriteOp, WriteSetMut},
+};
+use libra_vm::{data_cache::StateViewCache, txn_effects_to_writeset_and_events};
+use move_core_types::{
+    gas_schedule::{GasAlgebra, GasUnits},
+    move_resource::MoveResource,
+};
+use move_vm_runtime::move_vm::MoveVM;
+use move_vm_types::{
+    gas_schedule::{zero_cost_schedule, CostStrategy},
+    values::Value,
+};
+use vm::{
+    access::ScriptAccess,
+    file_format::{CompiledScript, SignatureToken},
+};
+
+/// Role of an account pre-created in genesis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GenesisAccountType {
+    /// Parent VASP, created by libra root
+    ParentVasp,
+    /// Designated dealer, created by treasury compliance
+    DesignatedDealer,
+}
+
+impl FromStr for GenesisAccountType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "vasp" => Ok(Self::ParentVasp),
+            "dd" => Ok(Self::DesignatedDealer),
+            _ => bail!("Invalid genesis account type {}, expected vasp or dd", s),
+        }
+    }
+}
+
+impl fmt::Display for GenesisAccountType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ParentVasp => write!(f, "vasp"),
+            Self::DesignatedDealer => write!(f, "dd"),
+        }
+    }
+}
+
+/// Account pre-created in genesis and funded with Coin1 by the testnet designated dealer, parsed
+/// from TYPE:ADDRESS:BALANCE:PUBLIC_KEY
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenesisAccount {
+    pub account_type: GenesisAccountType,
+    pub address: AccountAddress,
+    /// Coin1 balance in microunits, never 0
+    pub balance: u64,
+    /// Key the account authenticates with and its compliance key, its address is the one derived
+    /// from the key
+    pub public_key: Ed25519PublicKey,
+}
+
+impl GenesisAccount {
+    fn auth_key_prefix(&self) -> Vec<u8> {
+        AuthenticationKey::ed25519(&self.public_key)
+            .prefix()
+            .to_vec()
+    }
+
+    /// Sender and script creating the account with all currencies
+    fn create_script(&self) -> (AccountAddress, Script) {
+        let human_name = self.address.to_string().into_bytes();
+        let compliance_public_key = self.public_key.to_bytes().to_vec();
+        match self.account_type {
+            GenesisAccountType::ParentVasp => (
+                libra_root_address(),
+                transaction_builder::encode_create_parent_vasp_account_script(
+                    coin1_tag(),
+                    0,
+                    self.address,
+                    self.auth_key_prefix(),
+                    human_name,
+                    vec![],
+                    compliance_public_key,
+                    true,
+                ),
+            ),
+            GenesisAccountType::DesignatedDealer => (
+                treasury_compliance_account_address(),
+                transaction_builder::encode_create_designated_dealer_script(
+                    coin1_tag(),
+                    0,
+                    self.address,
+                    self.auth_key_prefix(),
+                    human_name,
+                    vec![],
+                    compliance_public_key,
+                    true,
+                ),
+            ),
+        }
+    }
+
+    /// Sender and script paying the balance of the account from the testnet designated dealer
+    fn fund_script(&self) -> (AccountAddress, Script) {
+        (
+            testnet_dd_account_address(),
+            transaction_builder::encode_peer_to_peer_with_metadata_script(
+                coin1_tag(),
+                self.address,
+                self.balance,
+                vec![],
+                vec![],
+            ),
+        )
+    }
+}
+
+impl FromStr for GenesisAccount {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<_> = s.split(':').collect();
+        let (account_type, address, balance, public_key) = match parts.as_slice() {
+            [account_type, address, balance, public_key] => {
+                (*account_type, *address, *balance, *public_key)
+            }
+            [_, _, _] => bail!(
+                "Genesis account {} has no public key, expected TYPE:ADDRESS:BALANCE:PUBLIC_KEY",
+                s
+            ),
+            _ => bail!(
+                "Invalid genesis account {}, expected TYPE:ADDRESS:BALANCE:PUBLIC_KEY",
+                s
+            ),
+        };
+        let account_type = account_type.parse()?;
+        let digits = address.strip_prefix("0x").unwrap_or(address);
+        if digits.is_empty() {
+            bail!("Genesis account {} has no address", s);
+        }
+        let address = AccountAddress::from_hex_literal(&format!("0x{}", digits))
+            .map_err(|e| format_err!("Invalid address of genesis account {} : {}", s, e))?;
+        let balance: u64 = balance
+            .parse()
+            .map_err(|e| format_err!("Invalid balance of genesis account {} : {}", s, e))?;
+        if balance == 0 {
+            bail!("Genesis account {} must have a non zero balance", s);
+        }
+        let public_key = Ed25519PublicKey::from_encoded_string(public_key)
+            .map_err(|e| format_err!("Invalid public key of genesis account {} : {}", s, e))?;
+        let derived = AuthenticationKey::ed25519(&public_key).derived_address();
+        if derived != address {
+            bail!(
+                "Address of genesis account {} must be {}, the one derived from its public key",
+                s,
+                derived
+            );
+        }
+        Ok(Self {
+            account_type,
+            address,
+            balance,
+            public_key,
+        })
+    }
+}
+
+impl fmt::Display for GenesisAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.account_type,
+            self.address,
+            self.balance,
+            hex::encode(self.public_key.to_bytes())
+        )
+    }
+}
+
+/// Genesis account with the key generated from `seed`, for tests
+#[cfg(test)]
+pub(crate) fn test_genesis_account(
+    account_type: GenesisAccountType,
+    seed: u8,
+    balance: u64,
+) -> GenesisAccount {
+    use libra_crypto::{ed25519::Ed25519PrivateKey, PrivateKey, Uniform};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let public_key = Ed25519PrivateKey::generate(&mut StdRng::from_seed([seed; 32])).public_key();
+    GenesisAccount {
+        account_type,
+        address: AuthenticationKey::ed25519(&public_key).derived_address(),
+        balance,
+        public_key,
+    }
+}
+
+/// Checks that the genesis accounts have distinct addresses, none of them an account genesis
+/// always creates
+pub fn validate_genesis_accounts(accounts: &[GenesisAccount]) -> Result<()> {
+    let reserved = [
+        CORE_CODE_ADDRESS,
+        reserved_vm_address(),
+        libra_root_address(),
+        treasury_compliance_account_address(),
+        testnet_dd_account_address(),
+        validator_set_address(),
+    ];
+    let mut addresses = HashSet::new();
+    for account in accounts {
+        if reserved.contains(&account.address) {
+            bail!(
+                "Genesis account {} uses the address of an account genesis creates",
+                account
+            );
+        }
+        if !addresses.insert(account.address) {
+            bail!(
+                "Genesis account {} is given more than once",
+                account.address
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Creates `accounts` against the state `genesis` writes and funds them from the testnet
+/// designated dealer, returns a genesis transaction that also contains the write sets and events
+/// of the account creations. Fails if an account already exists in `genesis`, e.g. the one of a
+/// validator, or the testnet designated dealer can't fund all of them.
+pub fn apply_genesis_accounts(
+    genesis: &Transaction,
+    accounts: &[GenesisAccount],
+) -> Result<Transaction> {
+    validate_genesis_accounts(accounts)?;
+    let change_set = genesis_change_set(genesis)?;
+    let state_view = GenesisStateView::new(change_set);
+    for account in accounts {
+        let account_path = AccessPath::new(account.address, AccountResource::resource_path());
+        if state_view.get(&account_path)?.is_some() {
+            bail!("Genesis account {} already exists in genesis", account);
+        }
+    }
+    let dd_balance_path = AccessPath::new(
+        testnet_dd_account_address(),
+        BalanceResource::access_path_for(coin1_tag()),
+    );
+    let dd_balance = match state_view.get(&dd_balance_path)? {
+        Some(blob) => lcs::from_bytes::<BalanceResource>(&blob)?.coin(),
+        None => bail!("Genesis has no testnet designated dealer to fund the genesis accounts"),
+    };
+    let total = accounts
+        .iter()
+        .try_fold(0u64, |total, account| total.checked_add(account.balance));
+    match total {
+        Some(total) if total <= dd_balance => (),
+        _ => bail!(
+            "The genesis accounts need more than the {} Coin1 of the testnet designated dealer",
+            dd_balance
+        ),
+    }
+    let scripts: Vec<_> = accounts
+        .iter()
+        .flat_map(|account| vec![account.create_script(), account.fund_script()])
+        .collect();
+    execute_on_genesis(change_set, &state_view, &scripts)
+}
+
+/// Checks that `code` is a well formed script that passes the bytecode verifier and takes the
+/// libra root signer as its only argument
+pub fn verify_genesis_script(code: &[u8]) -> Result<()> {
+    let script = CompiledScript::deserialize(code)
+        .map_err(|e| format_err!("Failed to deserialize the genesis script : {}", e))?;
+    bytecode_verifier::verify_script(&script)
+        .map_err(|e| format_err!("Genesis script fails bytecode verification : {}", e))?;
+    let parameters = &script.signature_at(script.as_inner().parameters).0;
+    match parameters.as_slice() {
+        [SignatureToken::Reference(inner)] if **inner == SignatureToken::Signer => Ok(()),
+        _ => bail!(
+            "Genesis script must take the libra root &signer as its only argument, it takes {:?}",
+            parameters
+        ),
+    }
+}
+
+/// Executes `code` as libra root against the state `genesis` writes and returns a genesis
+/// transaction that also contains the write set and events of the script. The waypoint of the
+/// result differs from the one of `genesis` and has to be derived again.
+pub fn apply_genesis_script(genesis: &Transaction, code: &[u8]) -> Result<Transaction> {
+    verify_genesis_script(code)?;
+    let change_set = genesis_change_set(genesis)?;
+    let state_view = GenesisStateView::new(change_set);
+    let script = Script::new(code.to_vec(), vec![], vec![]);
+    execute_on_genesis(change_set, &state_view, &[(libra_root_address(), script)])
+}
+
+fn genesis_change_set(genesis: &Transaction) -> Result<&ChangeSet> {
+    match genesis {
+        Transaction::GenesisTransaction(WriteSetPayload::Direct(change_set)) => Ok(change_set),
+        _ => bail!("Expected a genesis transaction with a direct write set"),
+    }
+}
+
+// Runs each script as its sender in a single session on top of `change_set`, whose state
+// `state_view` serves, and folds the effects of the session into a new genesis transaction
+fn execute_on_genesis(
+    change_set: &ChangeSet,
+    state_view: &GenesisStateView,
+    scripts: &[(AccountAddress, Script)],
+) -> Result<Transaction> {
+    let data_cache = StateViewCache::new(state_view);
+    let move_vm = MoveVM::new();
+    let mut session = move_vm.new_session(&data_cache);
+    for (sender, script) in scripts {
+        session
+            .execute_script(
+                script.code().to_vec(),
+                script.ty_args().to_vec(),
+                convert_txn_args(script.args()),
+                vec![*sender],
+                &mut CostStrategy::system(&zero_cost_schedule(), GasUnits::new(100_000_000)),
+            )
+            .map_err(|e| format_err!("Failed to execute a genesis script as {} : {}", sender, e))?;
+    }
+    let effects = session
+        .finish()
+        .map_err(|e| format_err!("Failed to finish the genesis script session : {}", e))?;
+    let (script_write_set, script_events) = txn_effects_to_writeset_and_events(effects)
+        .map_err(|e| format_err!("Failed to convert the genesis script effects : {}", e))?;
+
+    // Writes of the scripts replace the genesis writes to the same access path
+    let mut writes: BTreeMap<AccessPath, WriteOp> =
+        change_set.write_set().iter().cloned().collect();
+    for (access_path, op) in script_write_set.iter() {
+        match op {
+            WriteOp::Deletion => writes.remove(access_path),
+            WriteOp::Value(_) => writes.insert(access_path.clone(), op.clone()),
+        };
+    }
+    let write_set = WriteSetMut::new(writes.into_iter().collect())
+        .freeze()
+        .map_err(|e| format_err!("Failed to build the genesis write set : {}", e))?;
+    let events = change_set
+        .events()
+        .iter()
+        .cloned()
+        .chain(script_events)
+        .collect();
+    Ok(Transaction::GenesisTransaction(WriteSetPayload::Direct(
+        ChangeSet::new(write_set, events),
+    )))
+}
+
+fn convert_txn_args(args: &[TransactionArgument]) -> Vec<Value> {
+    args.iter()
+        .map(|arg| match arg {
+            TransactionArgument::U8(i) => Value::u8(*i),
+            TransactionArgument::U64(i) => Value::u64(*i),
+            TransactionArgument::U128(i) => Value::u128(*i),
+            TransactionArgument::Address(a) => Value::address(*a),
+            TransactionArgument::Bool(b) => Value::bool(*b),
+            TransactionArgument::U8Vector(v) => Value::vector_u8(v.clone()),
+        })
+        .collect()
+}
+
+// State written by the genesis change set, the script runs on top of it
+struct GenesisStateView {
+    data: HashMap<AccessPath, Vec<u8>>,
+}
+
+impl GenesisStateView {
+    fn new(change_set: &ChangeSet) -> Self {
+        let data = change_set
+            .write_set()
+            .iter()
+            .filter_map(|(access_path, op)| match op {
+                WriteOp::Value(blob) => Some((access_path.clone(), blob.clone())),
+                WriteOp::Deletion => None,
+            })
+            .collect();
+        Self { data }
+    }
+}
+
+impl StateView for GenesisStateView {
+    fn get(&self, access_path: &AccessPath) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.get(access_path).cloned())
+    }
+
+    fn multi_get(&self, access_paths: &[AccessPath]) -> Result<Vec<Option<Vec<u8>>>> {
+        access_paths
+            .iter()
+            .map(|access_path| self.get(access_path))
+            .collect()
+    }
+
+    fn is_genesis(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use compiler::Compiler;
+    use config_builder::ValidatorConfig;
+    use executor::db_bootstrapper;
+    use libra_crypto::{ed25519::Ed25519PrivateKey, PrivateKey, Uniform};
+    use libra_types::{
+        account_config::{from_currency_code_string, DesignatedDealer, ParentVASP, COIN1_NAME},
+        account_state::AccountState,
+        on_chain_config::{config_address, LibraVersion},
+        waypoint::Waypoint,
+    };
+    use libra_vm::LibraVM;
+    use libradb::LibraDB;
+    use std::convert::TryFrom;
+    use storage_interface::DbReaderWriter;
+
+    fn test_genesis() -> Transaction {
+        ValidatorConfig::new()
+            .build_set()
+            .unwrap()
+            .remove(0)
+            .execution
+            .genesis
+            .unwrap()
+    }
+
+    // Commits `genesis` to a fresh db, returns its waypoint and the state of `address` after it
+    fn execute_genesis(
+        genesis: &Transaction,
+        address: AccountAddress,
+    ) -> (Waypoint, Option<AccountState>) {
+        let db_path = libra_temppath::TempPath::new();
+        let db_rw = DbReaderWriter::new(LibraDB::open(db_path.path(), false, None).unwrap());
+        let waypoint = db_bootstrapper::generate_waypoint::<LibraVM>(&db_rw, genesis).unwrap();
+        db_bootstrapper::maybe_bootstrap::<LibraVM>(&db_rw, genesis, waypoint).unwrap();
+        let account_state = db_rw
+            .reader
+            .get_latest_account_state(address)
+            .unwrap()
+            .map(|blob| AccountState::try_from(&blob).unwrap());
+        (waypoint, account_state)
+    }
+
+    fn coin1_balance(account_state: &AccountState) -> u64 {
+        let coin1 = from_currency_code_string(COIN1_NAME).unwrap();
+        account_state
+            .get_balance_resources(&[coin1.clone()])
+            .unwrap()[&coin1]
+            .coin()
+    }
+
+    #[test]
+    pub fn test_verify_genesis_script() {
+        assert!(verify_genesis_script(&[]).is_err());
+        assert!(verify_genesis_script(b"not a script").is_err());
+        // A valid script, but it takes more than the libra root signer
+        let script =
+            transaction_builder::encode_update_libra_version_script(0, LibraVersion { major: 1 });
+        let err = verify_genesis_script(script.code()).unwrap_err();
+        assert!(err.to_string().contains("only argument"));
+    }
+
+    #[test]
+    pub fn test_apply_genesis_script() {
+        // Bumps the libra version, genesis sets it to 1
+        let source = "
+            import 0x1.LibraVersion;
+
+            main(account: &signer) {
+                LibraVersion.set(move(account), 42);
+                return;
+            }
+        ";
+        let code = Compiler::default()
+            .into_script_blob("genesis_script", source)
+            .expect("Failed to compile the genesis script");
+        verify_genesis_script(&code).unwrap();
+
+        let genesis = test_genesis();
+        let applied = apply_genesis_script(&genesis, &code).unwrap();
+        let (waypoint, config) = execute_genesis(&genesis, config_address());
+        let (applied_waypoint, applied_config) = execute_genesis(&applied, config_address());
+        assert_ne!(applied_waypoint, waypoint);
+        assert_eq!(
+            config.unwrap().get_libra_version().unwrap(),
+            Some(LibraVersion { major: 1 })
+        );
+        assert_eq!(
+            applied_config.unwrap().get_libra_version().unwrap(),
+            Some(LibraVersion { major: 42 })
+        );
+    }
+
+    #[test]
+    pub fn test_parse_genesis_account() {
+        let public_key = Ed25519PrivateKey::generate_for_testing().public_key();
+        let derived = AuthenticationKey::ed25519(&public_key).derived_address();
+        let encoded = hex::encode(public_key.to_bytes());
+        let account =
+            GenesisAccount::from_str(&format!("vasp:{}:1000:{}", derived, encoded)).unwrap();
+        assert_eq!(account.account_type, GenesisAccountType::ParentVasp);
+        assert_eq!(account.address, derived);
+        assert_eq!(account.balance, 1000);
+        assert_eq!(account.public_key, public_key);
+        assert_eq!(
+            GenesisAccount::from_str(&account.to_string()).unwrap(),
+            account
+        );
+        let account = GenesisAccount::from_str(&format!("dd:0x{}:5:{}", derived, encoded)).unwrap();
+        assert_eq!(account.account_type, GenesisAccountType::DesignatedDealer);
+        assert_eq!(account.address, derived);
+
+        for invalid in &[
+            format!("vasp:{}", derived),
+            format!("vasp:{}:1000:{}:00", derived, encoded),
+            format!("child:{}:1000:{}", derived, encoded),
+            format!("vasp::1000:{}", encoded),
+            format!("vasp:0xzz:1000:{}", encoded),
+            format!("vasp:{}:0:{}", derived, encoded),
+            format!("vasp:{}:-1:{}", derived, encoded),
+            format!("vasp:{}:1000:notakey", derived),
+        ] {
+            assert!(GenesisAccount::from_str(invalid).is_err(), "{}", invalid);
+        }
+        // Accounts without a key are rejected instead of being created keyless
+        let err = GenesisAccount::from_str(&format!("vasp:{}:1000", derived)).unwrap_err();
+        assert!(err.to_string().contains("has no public key"));
+        // The key of another address
+        let err = GenesisAccount::from_str(&format!("dd:0xdd:5:{}", encoded)).unwrap_err();
+        assert!(err.to_string().contains("derived from its public key"));
+    }
+
+    #[test]
+    pub fn test_validate_genesis_accounts() {
+        let vasp = test_genesis_account(GenesisAccountType::ParentVasp, 1, 1000);
+        let dd = test_genesis_account(GenesisAccountType::DesignatedDealer, 2, 1000);
+        assert!(validate_genesis_accounts(&[]).is_ok());
+        assert!(validate_genesis_accounts(&[vasp.clone(), dd.clone()]).is_ok());
+        let mut duplicate = dd;
+        duplicate.address = vasp.address;
+        let err = validate_genesis_accounts(&[vasp.clone(), duplicate]).unwrap_err();
+        assert!(err.to_string().contains("more than once"));
+        for reserved in &[libra_root_address(), testnet_dd_account_address()] {
+            let mut account = vasp.clone();
+            account.address = *reserved;
+            assert!(validate_genesis_accounts(&[account]).is_err());
+        }
+    }
+
+    #[test]
+    pub fn test_apply_genesis_accounts() {
+        let genesis = test_genesis();
+        let vasp = test_genesis_account(GenesisAccountType::ParentVasp, 1, 1000);
+        let dd = test_genesis_account(GenesisAccountType::DesignatedDealer, 2, 2000);
+        let with_accounts = apply_genesis_accounts(&genesis, &[vasp.clone(), dd.clone()]).unwrap();
+
+        let (_, vasp_state) = execute_genesis(&with_accounts, vasp.address);
+        let vasp_state = vasp_state.expect("The VASP is not in the genesis state");
+        assert!(vasp_state.get(&ParentVASP::resource_path()).is_some());
+        assert_eq!(coin1_balance(&vasp_state), 1000);
+        let (_, dd_state) = execute_genesis(&with_accounts, dd.address);
+        let dd_state = dd_state.expect("The designated dealer is not in the genesis state");
+        assert!(dd_state.get(&DesignatedDealer::resource_path()).is_some());
+        assert_eq!(coin1_balance(&dd_state), 2000);
+        assert_eq!(
+            dd_state
+                .get_account_resource()
+                .unwrap()
+                .unwrap()
+                .authentication_key(),
+            AuthenticationKey::ed25519(&dd.public_key)
+                .to_vec()
+                .as_slice()
+        );
+        // Without the accounts they are not there
+        assert!(execute_genesis(&genesis, vasp.address).1.is_none());
+
+        // Accounts can't be created twice
+        let err = apply_genesis_accounts(&with_accounts, &[vasp.clone()]).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        // The testnet designated dealer can't fund more than it has
+        let mut rich = vasp;
+        rich.balance = std::u64::MAX;
+        let err = apply_genesis_accounts(&genesis, &[rich]).unwrap_err();
+        assert!(err.to_string().contains("testnet designated dealer"));
+    }
+}
@@ -3,15 +3,19 @@
 
 #![forbid(unsafe_code)]
 
-use crate::cluster_swarm::cluster_swarm_kube::ClusterSwarmKube;
-use anyhow::{format_err, Result};
+use crate::{
+    cluster::VaultToken,
+    cluster_swarm::cluster_swarm_kube::{ClusterSwarmKube, DEFAULT_NAMESPACE},
+};
+use anyhow::{bail, format_err, Result};
 use debug_interface::AsyncNodeDebugClient;
-use libra_config::config::NodeConfig;
+use libra_config::config::{NodeConfig, DEFAULT_JSON_RPC_PORT};
 use libra_json_rpc_client::{JsonRpcAsyncClient, JsonRpcBatch};
+use libra_network_address::NetworkAddress;
 use reqwest::{Client, Url};
 use serde_json::Value;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
     fmt,
     process::Stdio,
     str::FromStr,
@@ -29,6 +33,148 @@ pub struct ValidatorGroup {
 pub struct InstanceConfig {
     pub validator_group: ValidatorGroup,
     pub application_config: ApplicationConfig,
+    pub pod_metadata: PodMetadata,
+    /// Environment variables set on the pod's containers, overriding the ones from the template
+    pub env: Vec<(String, String)>,
+    /// Kubernetes namespace the pod and its service are created in, the default namespace if not
+    /// set
+    pub namespace: Option<String>,
+    /// Pull policy of the libra images of the pod, the policies of the template if not set
+    pub image_pull_policy: Option<ImagePullPolicy>,
+    /// Digests the libra images of the pod are pinned to instead of their tag, by ECR
+    /// repository. Images of the other repositories are deployed by tag.
+    pub image_digests: BTreeMap<String, String>,
+}
+
+/// Extra labels and annotations added to the pod, on top of the ones required internally
+#[derive(Debug, Clone, Default)]
+pub struct PodMetadata {
+    pub labels: HashMap<String, String>,
+    pub annotations: HashMap<String, String>,
+}
+
+/// Readiness probe set on the main container of the pod, an HTTP GET on `path` if set and a TCP
+/// connect on `port` otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadinessProbe {
+    pub path: Option<String>,
+    pub port: u32,
+    pub initial_delay_secs: u32,
+    pub period_secs: u32,
+}
+
+impl ReadinessProbe {
+    /// Probes the health check exposed by libra nodes on the JSON-RPC port
+    pub fn json_rpc() -> Self {
+        Self {
+            path: Some("/-/healthy".to_string()),
+            port: DEFAULT_JSON_RPC_PORT as u32,
+            initial_delay_secs: 10,
+            period_secs: 5,
+        }
+    }
+
+    pub fn tcp(port: u32) -> Self {
+        Self {
+            path: None,
+            port,
+            initial_delay_secs: 5,
+            period_secs: 5,
+        }
+    }
+}
+
+/// Toleration added to the pod next to the ones of its template, written like a kubectl taint as
+/// key[=value][:effect]. Without a value any value of the taint is tolerated, without an effect
+/// any effect
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toleration {
+    pub key: String,
+    pub value: Option<String>,
+    pub effect: Option<String>,
+}
+
+const TAINT_EFFECTS: [&str; 3] = ["NoSchedule", "PreferNoSchedule", "NoExecute"];
+
+impl FromStr for Toleration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (taint, effect) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let (key, value) = match taint.find('=') {
+            Some(i) => (&taint[..i], Some(&taint[i + 1..])),
+            None => (taint, None),
+        };
+        if key.is_empty() {
+            bail!("Invalid toleration {}, expected key[=value][:effect]", s);
+        }
+        if let Some(effect) = effect {
+            if !TAINT_EFFECTS.contains(&effect) {
+                bail!(
+                    "Invalid toleration effect {}, expected one of {}",
+                    effect,
+                    TAINT_EFFECTS.join(", ")
+                );
+            }
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value: value.map(str::to_string),
+            effect: effect.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for Toleration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.key)?;
+        if let Some(value) = &self.value {
+            write!(f, "={}", value)?;
+        }
+        if let Some(effect) = &self.effect {
+            write!(f, ":{}", effect)?;
+        }
+        Ok(())
+    }
+}
+
+/// When the kubelet pulls the image of a container, images pushed again under the same tag are
+/// only picked up with Always
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImagePullPolicy {
+    Always,
+    IfNotPresent,
+    Never,
+}
+
+impl FromStr for ImagePullPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Always" => Ok(ImagePullPolicy::Always),
+            "IfNotPresent" => Ok(ImagePullPolicy::IfNotPresent),
+            "Never" => Ok(ImagePullPolicy::Never),
+            _ => bail!(
+                "Invalid image pull policy {}, expected Always, IfNotPresent or Never",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ImagePullPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ImagePullPolicy::Always => "Always",
+            ImagePullPolicy::IfNotPresent => "IfNotPresent",
+            ImagePullPolicy::Never => "Never",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -44,9 +190,18 @@ pub struct VaultConfig {}
 
 #[derive(Debug, Clone)]
 pub struct LSRConfig {
+    pub replica_index: u32,
+    pub port: u32,
     pub num_validators: u32,
+    /// Number of validators sharing a vault, the vault of the LSR's validator is at vault_index
+    pub validators_per_vault: u32,
+    /// Number of validators sharing the LSR, the LSR is set up for the first of them
+    pub validators_per_lsr: u32,
     pub image_tag: String,
     pub lsr_backend: String,
+    /// Token the LSR reads and writes its vault with
+    pub vault_token: VaultToken,
+    pub readiness_probe: Option<ReadinessProbe>,
 }
 
 #[derive(Debug, Clone)]
@@ -57,7 +212,86 @@ pub struct ValidatorConfig {
     pub image_tag: String,
     pub config_overrides: Vec<String>,
     pub seed_peer_ip: String,
-    pub safety_rules_addr: Option<String>,
+    pub safety_rules_addrs: Vec<String>,
+    pub safety_rules_service: SafetyRulesServiceType,
+    pub readiness_probe: Option<ReadinessProbe>,
+    pub tolerations: Vec<Toleration>,
+}
+
+impl ValidatorConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.safety_rules_service.validate(self.enable_lsr)
+    }
+}
+
+/// How a validator runs safety rules, see SafetyRulesService in libra-config
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SafetyRulesServiceType {
+    Local,
+    Thread,
+    Process,
+    Serializer,
+}
+
+impl SafetyRulesServiceType {
+    /// Rejects the combinations with the LSR setting that can't work: only the process service
+    /// talks to the LSRs, and config-builder switches any validator given an LSR address to it
+    pub fn validate(self, enable_lsr: bool) -> Result<()> {
+        match (self, enable_lsr) {
+            (SafetyRulesServiceType::Process, false) => {
+                bail!("The process safety rules service requires LSR")
+            }
+            (SafetyRulesServiceType::Process, true) | (_, false) => Ok(()),
+            (service, true) => bail!(
+                "The {} safety rules service runs in the validator and can't be used with LSR",
+                service
+            ),
+        }
+    }
+
+    /// Partial node config selecting the service, used by config-builder as the template of the
+    /// validator config (CFG_BASE_CONFIG). None for process, which config-builder sets up itself
+    /// from the LSR address. Templates are loaded as validator configs, which need a validator
+    /// network
+    pub fn base_config(self) -> Option<String> {
+        match self {
+            SafetyRulesServiceType::Process => None,
+            service => Some(format!(
+                "{{consensus: {{safety_rules: {{service: {{type: {}}}}}}}, \
+                 validator_network: {{}}}}",
+                service
+            )),
+        }
+    }
+}
+
+impl FromStr for SafetyRulesServiceType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "local" => Ok(SafetyRulesServiceType::Local),
+            "thread" => Ok(SafetyRulesServiceType::Thread),
+            "process" => Ok(SafetyRulesServiceType::Process),
+            "serializer" => Ok(SafetyRulesServiceType::Serializer),
+            _ => bail!(
+                "Invalid safety rules service {}, expected local, thread, process or serializer",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SafetyRulesServiceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SafetyRulesServiceType::Local => "local",
+            SafetyRulesServiceType::Thread => "thread",
+            SafetyRulesServiceType::Process => "process",
+            SafetyRulesServiceType::Serializer => "serializer",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -67,7 +301,18 @@ pub struct FullnodeConfig {
     pub num_validators: u32,
     pub image_tag: String,
     pub config_overrides: Vec<String>,
-    pub seed_peer_ip: String,
+    /// Fullnode network addresses of the validators the fullnode bootstraps from, its upstream
+    /// validator first. They carry the validator's noise key when its keys are in vault.
+    pub seed_peer_addresses: Vec<NetworkAddress>,
+    /// Vault key holding this fullnode's own network identity, the validator's shared fullnode
+    /// network key is used when unset
+    pub network_key: Option<String>,
+    pub vault_addr: Option<String>,
+    pub vault_token: Option<VaultToken>,
+    /// Port the fullnode listens on and advertises
+    pub listen_port: u32,
+    pub readiness_probe: Option<ReadinessProbe>,
+    pub tolerations: Vec<Toleration>,
 }
 
 #[derive(Clone)]
@@ -93,6 +338,52 @@ struct K8sInstanceInfo {
     kube: ClusterSwarmKube,
 }
 
+/// Consensus progress of a validator as reported by its counters. The counters don't tell who
+/// proposed a round, `proposals` is the number of proposals the validator sent since it started
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsensusStatus {
+    pub peer_name: String,
+    pub current_round: u64,
+    pub last_committed_round: u64,
+    pub proposals: u64,
+}
+
+impl ConsensusStatus {
+    pub(crate) async fn fetch(
+        client: &Client,
+        peer_name: &str,
+        counters_url: &str,
+    ) -> Result<Self> {
+        let counters: Value = client
+            .get(counters_url)
+            .send()
+            .await
+            .map_err(|e| format_err!("Failed to get the counters of {} : {}", peer_name, e))?
+            .json()
+            .await
+            .map_err(|e| format_err!("Failed to parse the counters of {} : {}", peer_name, e))?;
+        let counter = |name: &str| {
+            counters[name]
+                .as_f64()
+                .map(|value| value as u64)
+                .ok_or_else(|| {
+                    format_err!(
+                        "Counter({}) of {} was not a number: {:?}",
+                        name,
+                        peer_name,
+                        counters[name]
+                    )
+                })
+        };
+        Ok(Self {
+            peer_name: peer_name.to_string(),
+            current_round: counter("libra_consensus_current_round")?,
+            last_committed_round: counter("libra_consensus_last_committed_round")?,
+            proposals: counter("libra_consensus_proposals_count")?,
+        })
+    }
+}
+
 impl ValidatorGroup {
     pub fn new_for_index(index: u32) -> ValidatorGroup {
         Self {
@@ -127,6 +418,8 @@ impl InstanceConfig {
                 ));
             }
         }
+        // The digests belong to the images of the previous tag
+        self.image_digests.clear();
         Ok(())
     }
 
@@ -143,11 +436,25 @@ impl InstanceConfig {
             ApplicationConfig::Fullnode(fullnode_config) => {
                 fullnode_pod_name(self.validator_group.index, fullnode_config.fullnode_index)
             }
-            ApplicationConfig::LSR(_) => lsr_pod_name(self.validator_group.index),
+            ApplicationConfig::LSR(lsr_config) => {
+                lsr_replica_pod_name(self.validator_group.index, lsr_config.replica_index)
+            }
             ApplicationConfig::Vault(_) => vault_pod_name(self.validator_group.index),
         }
     }
 
+    pub fn namespace(&self) -> &str {
+        self.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE)
+    }
+
+    /// Whether the instance runs a libra node, vault and LSR instances don't serve JSON-RPC
+    pub fn serves_json_rpc(&self) -> bool {
+        matches!(
+            self.application_config,
+            ApplicationConfig::Validator(_) | ApplicationConfig::Fullnode(_)
+        )
+    }
+
     pub fn make_twin(&mut self, twin_index: u32) {
         self.validator_group.twin_index = Some(twin_index);
     }
@@ -200,9 +507,12 @@ impl Instance {
         }
     }
 
+    fn counters_url(&self) -> String {
+        format!("http://{}:9101/counters", self.ip)
+    }
+
     pub fn counter(&self, counter: &str) -> Result<f64> {
-        let response: Value =
-            reqwest::blocking::get(format!("http://{}:9101/counters", self.ip).as_str())?.json()?;
+        let response: Value = reqwest::blocking::get(self.counters_url().as_str())?.json()?;
         if let Value::Number(ref response) = response[counter] {
             if let Some(response) = response.as_f64() {
                 Ok(response)
@@ -222,6 +532,11 @@ impl Instance {
         }
     }
 
+    /// Round progress of the validator, read from its consensus counters
+    pub async fn consensus_status(&self) -> Result<ConsensusStatus> {
+        ConsensusStatus::fetch(&self.http_client, &self.peer_name, &self.counters_url()).await
+    }
+
     pub async fn try_json_rpc(&self) -> Result<()> {
         self.json_rpc_client().execute(JsonRpcBatch::new()).await?;
         Ok(())
@@ -249,6 +564,14 @@ impl Instance {
         &self.ip
     }
 
+    /// Kubernetes node the instance runs on, None for instances not started with k8s
+    pub fn k8s_node(&self) -> Option<&str> {
+        match &self.backend {
+            InstanceBackend::K8S(k8s) => Some(&k8s.k8s_node),
+            InstanceBackend::Swarm => None,
+        }
+    }
+
     pub fn ac_port(&self) -> u32 {
         self.ac_port
     }
@@ -257,6 +580,15 @@ impl Instance {
         Url::from_str(&format!("http://{}:{}/v1", self.ip(), self.ac_port())).expect("Invalid URL.")
     }
 
+    /// JSON-RPC URL of validators and fullnodes, served on the instance's ac_port. None for vault
+    /// and LSR instances.
+    pub fn json_rpc_endpoint(&self) -> Option<Url> {
+        match &self.backend {
+            InstanceBackend::K8S(k8s) if !k8s.instance_config.serves_json_rpc() => None,
+            _ => Some(self.json_rpc_url()),
+        }
+    }
+
     fn k8s_backend(&self) -> &K8sInstanceInfo {
         if let InstanceBackend::K8S(ref k8s) = self.backend {
             return k8s;
@@ -329,8 +661,11 @@ impl Instance {
     /// Unlike util_cmd, exec runs command inside the container
     pub async fn exec(&self, command: &str, mute: bool) -> Result<()> {
         let mut cmd = Command::new("kubectl");
-        cmd.arg("exec")
-            .arg(&self.peer_name)
+        cmd.arg("exec");
+        if let InstanceBackend::K8S(k8s) = &self.backend {
+            cmd.arg("--namespace").arg(k8s.instance_config.namespace());
+        }
+        cmd.arg(&self.peer_name)
             .arg("--container")
             .arg("main")
             .arg("--")
@@ -402,10 +737,37 @@ pub fn vault_pod_name(index: u32) -> String {
     format!("vault-{}", index)
 }
 
+/// Index of the vault holding the keys of validator `validator_index`, consecutive validators
+/// share a vault when `validators_per_vault` is more than 1
+pub fn vault_index(validator_index: u32, validators_per_vault: u32) -> u32 {
+    validator_index / validators_per_vault.max(1)
+}
+
+/// Index of the validator whose LSR validator `validator_index` uses, consecutive validators
+/// share the LSR of the first of them when `validators_per_lsr` is more than 1
+pub fn lsr_index(validator_index: u32, validators_per_lsr: u32) -> u32 {
+    let validators_per_lsr = validators_per_lsr.max(1);
+    validator_index - validator_index % validators_per_lsr
+}
+
 pub fn lsr_pod_name(index: u32) -> String {
     format!("lsr-{}", index)
 }
 
+/// The first replica keeps the plain lsr-{index} name, so single replica setups are unchanged
+pub fn lsr_replica_pod_name(index: u32, replica_index: u32) -> String {
+    if replica_index == 0 {
+        lsr_pod_name(index)
+    } else {
+        format!("lsr-{}-{}", index, replica_index)
+    }
+}
+
 pub fn fullnode_pod_name(validator_index: u32, fullnode_index: u32) -> String {
     format!("fn-{}-{}", validator_index, fullnode_index)
 }
+
+/// Index of the validator group a pod belongs to, e.g. 3 for val-3, fn-3-0 or lsr-3-1
+pub fn pod_validator_index(pod_name: &str) -> Option<u32> {
+    pod_name.split('-').nth(1)?.parse().ok()
+}
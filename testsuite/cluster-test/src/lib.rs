@@ -6,19 +6,26 @@ pub mod aws;
 pub mod cluster;
 pub mod cluster_builder;
 pub mod cluster_swarm;
+pub mod counters;
 pub mod effects;
 pub mod experiments;
 pub mod genesis_helper;
+pub mod genesis_script;
 pub mod github;
 pub mod health;
 pub mod instance;
+pub mod local_cluster;
 pub mod prometheus;
 pub mod report;
 pub mod slack;
+pub mod spawn_plan;
 pub mod stats;
 pub mod suite;
 pub mod tx_emitter;
 
+#[cfg(test)]
+mod test_utils;
+
 pub mod util {
     use std::time::{Duration, SystemTime};
 
@@ -0,0 +1,86 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+#![forbid(unsafe_code)]
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Result};
+use futures::future::try_join_all;
+use libra_config::config::NodeConfig;
+use libra_swarm::swarm::LibraSwarm;
+use tokio::task::spawn_blocking;
+
+use crate::cluster::Cluster;
+
+/// Time the validators of a launched swarm have to serve JSON RPC
+const JSON_RPC_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Smallest working cluster for integration tests: `num_validators` validators launched with
+/// libra-swarm, keys in on-disk storage, no fullnodes, LSRs or vaults. Returns once every
+/// validator serves JSON RPC, the mint key is the libra root key of the swarm. The validators are
+/// killed once the cluster and all its clones are dropped.
+pub async fn local_test_cluster(num_validators: u32) -> Result<Cluster> {
+    if num_validators == 0 {
+        bail!("A local test cluster needs at least one validator");
+    }
+    let swarm = spawn_blocking(move || {
+        let mut swarm = LibraSwarm::configure_validator_swarm(num_validators as usize, None, None)?;
+        swarm
+            .launch_attempt(true)
+            .map_err(|e| format_err!("Failed to launch local swarm : {}", e))?;
+        Ok::<_, anyhow::Error>(swarm)
+    })
+    .await??;
+    let peers = swarm
+        .config
+        .config_files
+        .iter()
+        .map(|path| {
+            let config = NodeConfig::load(path)
+                .map_err(|e| format_err!("Failed to load {} : {}", path.display(), e))?;
+            Ok((
+                "localhost".to_string(),
+                config.rpc.address.port() as u32,
+                Some(config.debug_interface.admission_control_node_debug_port as u32),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mint_file = swarm
+        .config
+        .libra_root_key_path
+        .to_str()
+        .ok_or_else(|| format_err!("Invalid libra root key path"))?;
+    let cluster = Cluster::from_host_port(peers, mint_file);
+    let deadline = Instant::now() + JSON_RPC_TIMEOUT;
+    try_join_all(
+        cluster
+            .validator_instances()
+            .iter()
+            .map(|instance| instance.wait_json_rpc(deadline)),
+    )
+    .await?;
+    Ok(cluster.with_local_swarm(swarm))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Launches libra-node processes, which have to be built first, run with `-- --ignored`
+    #[test]
+    #[ignore]
+    pub fn test_local_test_cluster() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(local_test_cluster(0).await.is_err());
+
+            let cluster = local_test_cluster(2).await.unwrap();
+            assert_eq!(cluster.validator_count(), 2);
+            assert_eq!(cluster.fullnode_count(), 0);
+            for instance in cluster.validator_instances() {
+                instance.try_json_rpc().await.unwrap();
+            }
+        });
+    }
+}
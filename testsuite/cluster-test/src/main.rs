@@ -3,7 +3,9 @@
 
 use std::{
     collections::HashSet,
-    env, fmt, process,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    process,
     time::{Duration, Instant},
 };
 
@@ -14,9 +16,9 @@ use termion::{color, style};
 
 use anyhow::{bail, format_err, Result};
 use cluster_test::{
-    aws,
+    aws::{self, TeardownAsgSize},
     cluster::Cluster,
-    cluster_builder::{ClusterBuilder, ClusterBuilderParams},
+    cluster_builder::{verify_genesis, ClusterBuilder, ClusterBuilderParams, VerificationReport},
     cluster_swarm::{cluster_swarm_kube::ClusterSwarmKube, ClusterSwarm},
     experiments::{get_experiment, Context, Experiment},
     github::GitHub,
@@ -34,6 +36,8 @@ use futures::{
 };
 use itertools::zip;
 use libra_config::config::DEFAULT_JSON_RPC_PORT;
+use libra_genesis_tool::layout::Layout;
+use libra_metrics::metric_server;
 use std::cmp::min;
 use tokio::time::{delay_for, delay_until, Instant as TokioInstant};
 
@@ -63,6 +67,18 @@ struct Args {
     suite: Option<String>,
     #[structopt(long, group = "action")]
     exec: Option<String>,
+    #[structopt(
+        long,
+        group = "action",
+        requires = "genesis-layout",
+        help = "Verifies the genesis blob at this path against --genesis-layout and exits"
+    )]
+    verify_genesis: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Layout (toml, yaml or json) --verify-genesis checks the genesis blob against"
+    )]
+    genesis_layout: Option<PathBuf>,
 
     #[structopt(last = true)]
     last: Vec<String>,
@@ -102,6 +118,19 @@ struct Args {
     )]
     pub wait_on_failure: Option<u64>,
 
+    #[structopt(
+        long,
+        default_value = "zero",
+        help = "Size the validators ASG is left at after the run: zero, keep or a number of instances"
+    )]
+    pub teardown_asg_size: TeardownAsgSize,
+
+    #[structopt(
+        long,
+        help = "If set, serves the cluster-test metrics for Prometheus to scrape on this port"
+    )]
+    pub metrics_port: Option<u16>,
+
     #[structopt(flatten)]
     pub cluster_builder_params: ClusterBuilderParams,
 }
@@ -112,6 +141,19 @@ pub async fn main() {
 
     let args = Args::from_args();
 
+    if let Some(metrics_port) = args.metrics_port {
+        metric_server::start_server("0.0.0.0".to_string(), metrics_port, false);
+    }
+
+    if let (Some(genesis_path), Some(layout_path)) = (&args.verify_genesis, &args.genesis_layout) {
+        let report = exit_on_error(verify_genesis_file(genesis_path, layout_path).await);
+        println!("{}", report);
+        if !report.is_valid() {
+            process::exit(1);
+        }
+        return;
+    }
+
     if args.swarm && !(args.emit_tx || args.diag || args.health_check) {
         panic!("Can only use --emit-tx or --diag or --health-check in --swarm mode");
     }
@@ -169,7 +211,13 @@ pub async fn main() {
             warn!("Tearing down cluster now");
         }
     }
-    runner.teardown().await;
+    // Externally managed ASGs are never resized
+    let teardown_asg_size = if args.cluster_builder_params.fixed_asg_size {
+        TeardownAsgSize::Keep
+    } else {
+        args.teardown_asg_size
+    };
+    runner.teardown(teardown_asg_size).await;
     let perf_msg = exit_on_error(result);
 
     if let Some(mut changelog) = args.changelog {
@@ -228,6 +276,17 @@ async fn handle_cluster_test_runner_commands(
     Ok(perf_msg)
 }
 
+async fn verify_genesis_file(
+    genesis_path: &Path,
+    layout_path: &Path,
+) -> Result<VerificationReport> {
+    let blob = fs::read(genesis_path)
+        .map_err(|e| format_err!("Failed to read {} : {}", genesis_path.display(), e))?;
+    let layout = Layout::from_disk(layout_path)
+        .map_err(|e| format_err!("Failed to read {} : {}", layout_path.display(), e))?;
+    verify_genesis(&blob, &layout).await
+}
+
 fn exit_on_error<T>(r: Result<T>) -> T {
     match r {
         Ok(r) => r,
@@ -438,15 +497,22 @@ impl BasicSwarmUtil {
 }
 
 impl ClusterTestRunner {
-    pub async fn teardown(&mut self) {
+    pub async fn teardown(&mut self, asg_size: TeardownAsgSize) {
         self.cluster_swarm.cleanup().await.expect("Cleanup failed");
+        let desired_capacity = match asg_size.desired_capacity() {
+            Some(desired_capacity) => desired_capacity,
+            None => {
+                info!("Keeping the validators ASG at its current size");
+                return;
+            }
+        };
         let workspace = self
             .cluster_swarm
             .get_workspace()
             .await
             .expect("Failed to get workspace");
         let asg_name = format!("{}-k8s-testnet-validators", workspace);
-        aws::set_asg_size(0, 0.0, &asg_name, false, true)
+        aws::set_asg_size(desired_capacity, 0.0, &asg_name, false, true)
             .await
             .unwrap_or_else(|_| panic!("{} scaling failed", asg_name));
     }
@@ -493,12 +559,11 @@ impl ClusterTestRunner {
                 wait_committed: !args.burst,
             },
         };
-        let emit_to_validator =
-            if cluster.fullnode_instances().len() < cluster.validator_instances().len() {
-                true
-            } else {
-                args.emit_to_validator.unwrap_or(false)
-            };
+        let emit_to_validator = if cluster.fullnode_count() < cluster.validator_count() {
+            true
+        } else {
+            args.emit_to_validator.unwrap_or(false)
+        };
         Ok(Self {
             logs,
             trace_tail,
@@ -0,0 +1,332 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+#![forbid(unsafe_code)]
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, format_err, Result};
+use futures::{stream, Future, StreamExt};
+
+use crate::instance::{
+    lsr_index, validator_pod_name, vault_index, vault_pod_name,
+    ApplicationConfig::{Fullnode, Validator, Vault, LSR},
+    InstanceConfig,
+};
+
+/// Order in which a set of instances is spawned: an instance is only spawned once every instance
+/// it depends on spawned successfully. Dependencies on pods outside of the plan are assumed to be
+/// spawned already.
+pub struct SpawnPlan {
+    configs: Vec<InstanceConfig>,
+    // Indices in configs of the instances each instance depends on
+    dependencies: Vec<HashSet<usize>>,
+}
+
+impl SpawnPlan {
+    /// Derives the dependencies from the configs: fullnodes depend on their validator,
+    /// validators on their (possibly shared) LSRs and LSRs on the vault of their validator
+    pub fn new(configs: Vec<InstanceConfig>) -> Self {
+        let indices: HashMap<_, _> = configs
+            .iter()
+            .enumerate()
+            .map(|(i, config)| (config.pod_name(), i))
+            .collect();
+        let dependencies = configs
+            .iter()
+            .map(|config| {
+                let group = config.validator_group.index;
+                match &config.application_config {
+                    Fullnode(_) => indices
+                        .get(&validator_pod_name(group))
+                        .copied()
+                        .into_iter()
+                        .collect(),
+                    Validator(_) => configs
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, other)| match &other.application_config {
+                            LSR(lsr_config) => {
+                                other.validator_group.index
+                                    == lsr_index(group, lsr_config.validators_per_lsr)
+                            }
+                            _ => false,
+                        })
+                        .map(|(i, _)| i)
+                        .collect(),
+                    LSR(lsr_config) => indices
+                        .get(&vault_pod_name(vault_index(
+                            group,
+                            lsr_config.validators_per_vault,
+                        )))
+                        .copied()
+                        .into_iter()
+                        .collect(),
+                    Vault(_) => HashSet::new(),
+                }
+            })
+            .collect();
+        Self {
+            configs,
+            dependencies,
+        }
+    }
+
+    /// Declares that `pod_name` is only spawned after `depends_on`, on top of the derived
+    /// dependencies
+    pub fn add_dependency(&mut self, pod_name: &str, depends_on: &str) -> Result<()> {
+        let index = self.index_of(pod_name)?;
+        let dependency = self.index_of(depends_on)?;
+        self.dependencies[index].insert(dependency);
+        Ok(())
+    }
+
+    fn index_of(&self, pod_name: &str) -> Result<usize> {
+        self.configs
+            .iter()
+            .position(|config| config.pod_name() == pod_name)
+            .ok_or_else(|| format_err!("{} is not part of the spawn plan", pod_name))
+    }
+
+    /// Indices of the instances grouped in layers, every instance only depends on instances of
+    /// the previous layers
+    fn layers(&self) -> Result<Vec<Vec<usize>>> {
+        let mut remaining: HashMap<usize, HashSet<usize>> =
+            self.dependencies.iter().cloned().enumerate().collect();
+        let mut layers = vec![];
+        while !remaining.is_empty() {
+            let mut layer: Vec<_> = remaining
+                .iter()
+                .filter(|(_, dependencies)| dependencies.is_empty())
+                .map(|(i, _)| *i)
+                .collect();
+            if layer.is_empty() {
+                let mut pods: Vec<_> = remaining
+                    .keys()
+                    .map(|i| self.configs[*i].pod_name())
+                    .collect();
+                pods.sort();
+                bail!("Dependency cycle between {}", pods.join(", "));
+            }
+            layer.sort_unstable();
+            for i in layer.iter() {
+                remaining.remove(i);
+            }
+            for dependencies in remaining.values_mut() {
+                for i in layer.iter() {
+                    dependencies.remove(i);
+                }
+            }
+            layers.push(layer);
+        }
+        Ok(layers)
+    }
+
+    /// Pod names in the order they are spawned
+    pub fn order(&self) -> Result<Vec<Vec<String>>> {
+        Ok(self
+            .layers()?
+            .into_iter()
+            .map(|layer| {
+                layer
+                    .into_iter()
+                    .map(|i| self.configs[i].pod_name())
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Spawns every instance with `spawn`, at most `concurrency` at a time (unbounded if None).
+    /// Returns the results in the order of the configs, the instances whose dependencies failed
+    /// are not spawned and fail too.
+    pub async fn execute<F, Fut, T>(
+        self,
+        concurrency: Option<usize>,
+        spawn: F,
+    ) -> Result<Vec<Result<T>>>
+    where
+        F: Fn(InstanceConfig) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let layers = self.layers()?;
+        let concurrency = concurrency.unwrap_or_else(|| self.configs.len().max(1));
+        let mut failed = HashSet::new();
+        let mut results: Vec<Option<Result<T>>> = self.configs.iter().map(|_| None).collect();
+        for layer in layers {
+            let (blocked, ready): (Vec<_>, Vec<_>) = layer.into_iter().partition(|i| {
+                self.dependencies[*i]
+                    .iter()
+                    .any(|dependency| failed.contains(dependency))
+            });
+            for i in blocked {
+                failed.insert(i);
+                results[i] = Some(Err(format_err!(
+                    "Not spawning {}, one of its dependencies failed",
+                    self.configs[i].pod_name()
+                )));
+            }
+            let spawn = &spawn;
+            let configs = &self.configs;
+            let layer_results: Vec<_> = stream::iter(ready)
+                .map(|i| async move { (i, spawn(configs[i].clone()).await) })
+                .buffer_unordered(concurrency)
+                .collect()
+                .await;
+            for (i, result) in layer_results {
+                if result.is_err() {
+                    failed.insert(i);
+                }
+                results[i] = Some(result);
+            }
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("every instance of the plan has a result"))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cluster::VaultToken,
+        instance::{
+            FullnodeConfig, LSRConfig, SafetyRulesServiceType, ValidatorConfig, ValidatorGroup,
+        },
+    };
+    use libra_network_address::NetworkAddress;
+    use std::{str::FromStr, sync::Mutex};
+
+    fn instance_config(
+        index: u32,
+        application_config: crate::instance::ApplicationConfig,
+    ) -> InstanceConfig {
+        InstanceConfig {
+            validator_group: ValidatorGroup::new_for_index(index),
+            application_config,
+            pod_metadata: Default::default(),
+            env: vec![],
+            namespace: None,
+            image_pull_policy: None,
+            image_digests: Default::default(),
+        }
+    }
+
+    fn validator(index: u32) -> InstanceConfig {
+        instance_config(
+            index,
+            Validator(ValidatorConfig {
+                num_validators: 2,
+                num_fullnodes: 1,
+                enable_lsr: true,
+                image_tag: "test".to_string(),
+                config_overrides: vec![],
+                seed_peer_ip: "10.0.0.1".to_string(),
+                safety_rules_addrs: vec![],
+                safety_rules_service: SafetyRulesServiceType::Process,
+                readiness_probe: None,
+                tolerations: vec![],
+            }),
+        )
+    }
+
+    fn fullnode(index: u32) -> InstanceConfig {
+        instance_config(
+            index,
+            Fullnode(FullnodeConfig {
+                fullnode_index: 0,
+                num_fullnodes_per_validator: 1,
+                num_validators: 2,
+                image_tag: "test".to_string(),
+                config_overrides: vec![],
+                seed_peer_addresses: vec![
+                    NetworkAddress::from_str("/ip4/10.0.0.1/tcp/6181").unwrap()
+                ],
+                network_key: None,
+                vault_addr: None,
+                vault_token: None,
+                listen_port: 6180,
+                readiness_probe: None,
+                tolerations: vec![],
+            }),
+        )
+    }
+
+    fn lsr(index: u32) -> InstanceConfig {
+        instance_config(
+            index,
+            LSR(LSRConfig {
+                replica_index: 0,
+                port: 6185,
+                num_validators: 2,
+                validators_per_vault: 1,
+                validators_per_lsr: 1,
+                image_tag: "test".to_string(),
+                lsr_backend: "vault".to_string(),
+                vault_token: VaultToken::new("root".to_string()),
+                readiness_probe: None,
+            }),
+        )
+    }
+
+    #[test]
+    pub fn test_spawn_plan_order() {
+        let plan = SpawnPlan::new(vec![
+            fullnode(0),
+            fullnode(1),
+            validator(0),
+            validator(1),
+            lsr(0),
+        ]);
+        assert_eq!(
+            plan.order().unwrap(),
+            vec![
+                vec!["val-1".to_string(), "lsr-0".to_string()],
+                vec!["fn-1-0".to_string(), "val-0".to_string()],
+                vec!["fn-0-0".to_string()],
+            ]
+        );
+
+        let mut plan = SpawnPlan::new(vec![validator(0), validator(1)]);
+        plan.add_dependency("val-1", "val-0").unwrap();
+        assert_eq!(
+            plan.order().unwrap(),
+            vec![vec!["val-0".to_string()], vec!["val-1".to_string()]]
+        );
+        plan.add_dependency("val-0", "val-1").unwrap();
+        assert!(plan.order().is_err());
+        assert!(plan.add_dependency("val-0", "fn-0-0").is_err());
+    }
+
+    #[test]
+    pub fn test_spawn_plan_execute() {
+        let plan = SpawnPlan::new(vec![validator(0), fullnode(0), validator(1), fullnode(1)]);
+        let spawned = Mutex::new(vec![]);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let results = rt
+            .block_on(plan.execute(Some(1), |config| {
+                let spawned = &spawned;
+                async move {
+                    let pod_name = config.pod_name();
+                    if pod_name == "val-1" {
+                        bail!("Failed to spawn {}", pod_name);
+                    }
+                    spawned.lock().unwrap().push(pod_name.clone());
+                    Ok(pod_name)
+                }
+            }))
+            .unwrap();
+
+        assert_eq!(*spawned.lock().unwrap(), vec!["val-0", "fn-0-0"]);
+        assert_eq!(results[0].as_ref().unwrap(), "val-0");
+        assert_eq!(results[1].as_ref().unwrap(), "fn-0-0");
+        assert!(results[2].is_err());
+        // The fullnode of the failed validator is not spawned
+        assert!(results[3]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("dependencies failed"));
+    }
+}
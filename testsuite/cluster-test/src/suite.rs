@@ -29,7 +29,7 @@ impl ExperimentSuite {
                 .build(cluster),
             ));
         }
-        let count = min(3, cluster.validator_instances().len() / 3);
+        let count = min(3, cluster.validator_count() / 3);
         // Reboot different sets of 3 validators *100 times
         for _ in 0..10 {
             let b = Box::new(RebootRandomValidatorsParams::new(count, 0).build(cluster));
@@ -93,7 +93,7 @@ impl ExperimentSuite {
             Ok(val) => val
                 .parse()
                 .map_err(|e| format_err!("Failed to parse BATCH_SIZE {}: {}", val, e))?,
-            Err(_) => cluster.validator_instances().len() / 2,
+            Err(_) => cluster.validator_count() / 2,
         };
         let updated_image_tag = env::var("UPDATE_TO_TAG")
             .map_err(|_| format_err!("Expected environment variable UPDATE_TO_TAG"))?;
@@ -0,0 +1,204 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fixtures shared by the unit tests of the crate
+
+use crate::{
+    cluster_swarm::{
+        cluster_swarm_kube::{KubeNode, NodeFilter},
+        ClusterSwarm, Fault, PodPhase, ProvisioningSwarm,
+    },
+    instance::{Instance, InstanceConfig},
+};
+use anyhow::{bail, format_err, Result};
+use async_trait::async_trait;
+use futures::Future;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// Runs `future` to completion on a new runtime
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Runtime::new().unwrap().block_on(future)
+}
+
+/// Swarm recording what is done to it instead of running pods
+#[derive(Default)]
+pub struct MockSwarm {
+    /// Phase of each pod, pods that are not listed are running
+    pub phases: HashMap<String, PodPhase>,
+    pub deleted: Mutex<Vec<String>>,
+    pub node_capacity: u32,
+    // (node, path) of every put_file
+    pub files: Mutex<Vec<(String, String)>>,
+    pub faults: Mutex<Vec<(String, Fault)>>,
+    pub spawned: Mutex<Vec<InstanceConfig>>,
+    pub cleaned: Mutex<Vec<String>>,
+    // Pods spawn_new_instance fails for
+    pub failing: HashSet<String>,
+    /// Nodes respawned pods are placed on, the first one that is not cordoned
+    pub nodes: Vec<String>,
+    /// Node of each pod, pods that are not listed are on node-{pod name}
+    pub pod_nodes: Mutex<HashMap<String, String>>,
+    pub cordoned: Mutex<Vec<String>>,
+}
+
+impl MockSwarm {
+    /// Swarm of `nodes` with val-0 placed on the first one
+    pub fn with_nodes(nodes: Vec<&str>) -> Self {
+        let pod_nodes = vec![("val-0".to_string(), nodes[0].to_string())];
+        Self {
+            node_capacity: nodes.len() as u32,
+            nodes: nodes.into_iter().map(ToString::to_string).collect(),
+            pod_nodes: Mutex::new(pod_nodes.into_iter().collect()),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl ClusterSwarm for MockSwarm {
+    async fn spawn_new_instance(&self, instance_config: InstanceConfig) -> Result<Instance> {
+        if self.failing.contains(&instance_config.pod_name()) {
+            bail!("Failed to spawn {}", instance_config.pod_name());
+        }
+        let instance = Instance::new(
+            instance_config.pod_name(),
+            "127.0.0.1".to_string(),
+            8080,
+            None,
+            reqwest::Client::new(),
+        );
+        self.spawned.lock().unwrap().push(instance_config);
+        Ok(instance)
+    }
+
+    async fn clean_data(&self, node: &str) -> Result<()> {
+        self.cleaned.lock().unwrap().push(node.to_string());
+        Ok(())
+    }
+
+    async fn get_node_name(&self, pod_name: &str) -> Result<String> {
+        Ok(self
+            .pod_nodes
+            .lock()
+            .unwrap()
+            .get(pod_name)
+            .cloned()
+            .unwrap_or_else(|| format!("node-{}", pod_name)))
+    }
+
+    async fn get_grafana_baseurl(&self) -> Result<String> {
+        bail!("MockSwarm has no grafana")
+    }
+
+    async fn put_file(
+        &self,
+        node: &str,
+        _pod_name: &str,
+        path: &str,
+        _content: Vec<u8>,
+    ) -> Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .push((node.to_string(), path.to_string()));
+        Ok(())
+    }
+
+    async fn get_pod_phase(&self, pod_name: &str) -> Result<PodPhase> {
+        Ok(self
+            .phases
+            .get(pod_name)
+            .copied()
+            .unwrap_or(PodPhase::Running))
+    }
+
+    async fn delete_pod(&self, pod_name: &str) -> Result<()> {
+        self.deleted.lock().unwrap().push(pod_name.to_string());
+        Ok(())
+    }
+
+    async fn respawn_pod(&self, pod_name: &str) -> Result<()> {
+        let cordoned = self.cordoned.lock().unwrap();
+        // Like a single node cluster, fall back to the first node if all are cordoned
+        let node = self
+            .nodes
+            .iter()
+            .find(|node| !cordoned.contains(node))
+            .or_else(|| self.nodes.first())
+            .ok_or_else(|| format_err!("MockSwarm has no node to respawn {} on", pod_name))?;
+        self.pod_nodes
+            .lock()
+            .unwrap()
+            .insert(pod_name.to_string(), node.clone());
+        Ok(())
+    }
+
+    async fn inject_fault(&self, pod_name: &str, fault: Fault) -> Result<()> {
+        self.faults
+            .lock()
+            .unwrap()
+            .push((pod_name.to_string(), fault));
+        Ok(())
+    }
+
+    async fn cordon_node(&self, node_name: &str) -> Result<()> {
+        self.cordoned.lock().unwrap().push(node_name.to_string());
+        Ok(())
+    }
+
+    async fn uncordon_node(&self, node_name: &str) -> Result<()> {
+        self.cordoned
+            .lock()
+            .unwrap()
+            .retain(|node| node != node_name);
+        Ok(())
+    }
+
+    async fn node_capacity(&self) -> Result<u32> {
+        Ok(self.node_capacity)
+    }
+}
+
+#[async_trait]
+impl ProvisioningSwarm for MockSwarm {
+    async fn allocate_node(
+        &self,
+        pod_name: &str,
+        _retries: usize,
+        _retry_interval_ms: u64,
+    ) -> Result<KubeNode> {
+        Ok(KubeNode {
+            name: format!("node-{}", pod_name),
+            provider_id: format!("provider-{}", pod_name),
+            internal_ip: "10.0.0.1".to_string(),
+            external_ip: None,
+            unschedulable: false,
+            labels: Default::default(),
+        })
+    }
+
+    async fn cleanup(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn adopt_pods(&self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    async fn get_workspace(&self) -> Result<String> {
+        Ok("test".to_string())
+    }
+
+    async fn register_namespace(&self, _namespace: &str) {}
+
+    async fn set_node_filter(&self, _node_filter: NodeFilter) {}
+
+    async fn set_placement_seed(&self, _seed: Option<u64>) {}
+
+    async fn create_validator_pdb(&self, _namespace: &str, _max_unavailable: i32) -> Result<()> {
+        Ok(())
+    }
+}